@@ -0,0 +1,100 @@
+//! Redis clients storing the sessions of `RedisSession`.
+
+use std::collections::HashMap;
+
+use actix::prelude::*;
+use futures_util::future::{ok, FutureExt, LocalBoxFuture};
+use redis_async::resp::RespValue;
+
+use crate::command::{cluster_slots, DeserializeError, DirectedTo, RedisCommand};
+use crate::{Command, Error, RedisActor, RedisClusterActor};
+
+/// Actor executing the Redis commands of [`RedisSession`](struct.RedisSession.html).
+///
+/// Implemented by `RedisActor` and `RedisClusterActor`. Implement it for other
+/// actors, e.g. a connection pool, a proxy or a test double, to store sessions
+/// with them; an async client can be wrapped in such an actor.
+pub trait SessionBackend: Actor {
+    /// Execute a serialized command with the actor at `addr`, returning the reply of
+    /// Redis, which may be an error reply.
+    ///
+    /// `slot` is the hash slot of the keys of the command, which is only relevant
+    /// to Redis Cluster.
+    fn execute(
+        addr: &Addr<Self>,
+        slot: u16,
+        command: RespValue,
+    ) -> LocalBoxFuture<'static, Result<RespValue, Error>>;
+
+    /// A hash slot served by each node of the backend, so that commands without
+    /// keys, such as `SCAN`, reach all the keys. Default is the slot `0` only, for a
+    /// single node.
+    fn nodes(_addr: &Addr<Self>) -> LocalBoxFuture<'static, Result<Vec<u16>, Error>> {
+        ok(vec![0]).boxed_local()
+    }
+}
+
+impl SessionBackend for RedisActor {
+    fn execute(
+        addr: &Addr<Self>,
+        _: u16,
+        command: RespValue,
+    ) -> LocalBoxFuture<'static, Result<RespValue, Error>> {
+        addr.send(Command(command))
+            .map(|res| res.unwrap_or(Err(Error::Disconnected)))
+            .boxed_local()
+    }
+}
+
+impl SessionBackend for RedisClusterActor {
+    fn execute(
+        addr: &Addr<Self>,
+        slot: u16,
+        command: RespValue,
+    ) -> LocalBoxFuture<'static, Result<RespValue, Error>> {
+        let command = DirectedTo {
+            command: Raw(command),
+            slot,
+        };
+        addr.send(command)
+            .map(|res| res.unwrap_or(Err(Error::Disconnected)))
+            .boxed_local()
+    }
+
+    /// A slot served by each master node.
+    fn nodes(addr: &Addr<Self>) -> LocalBoxFuture<'static, Result<Vec<u16>, Error>> {
+        let command = DirectedTo {
+            command: cluster_slots(),
+            slot: 0,
+        };
+        addr.send(command)
+            .map(|res| {
+                let mut masters = HashMap::new();
+                for slots in res.unwrap_or(Err(Error::Disconnected))? {
+                    masters.entry(slots.master_addr()).or_insert(slots.start);
+                }
+                Ok(masters.values().copied().collect())
+            })
+            .boxed_local()
+    }
+}
+
+/// A serialized command.
+#[derive(Debug)]
+struct Raw(RespValue);
+
+impl RedisCommand for Raw {
+    type Output = RespValue;
+
+    fn serialize(self) -> RespValue {
+        self.0
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        Ok(resp)
+    }
+}
+
+impl Message for Raw {
+    type Result = Result<RespValue, Error>;
+}
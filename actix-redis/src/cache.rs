@@ -0,0 +1,792 @@
+//! HTTP response cache middleware.
+//!
+//! [`ResponseCache`](struct.ResponseCache.html) stores the status, headers and body
+//! of successful `GET` responses in Redis, so that the cache is shared by all
+//! instances of the application.
+//!
+//! When many requests miss the same entry at once, only the one holding the
+//! [`Lock`](../lock/struct.Lock.html) of the entry calls the service; the others
+//! wait for the entry to appear for up to the lock timeout.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use actix::dev::ToEnvelope;
+use actix::prelude::*;
+use actix_rt::time::delay_for;
+use actix_service::{Service, Transform};
+use actix_web::body::{Body, MessageBody, ResponseBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{Error, HttpResponse};
+use futures_util::future::{ok, Future, Ready};
+use futures_util::stream::StreamExt;
+use log::warn;
+
+use crate::command::{get, set, Eval, Get, Set};
+use crate::lock::{Lock, LockGuard};
+use crate::redis::RedisActor;
+
+/// Version of the encoding of the cached entries.
+const VERSION: u8 = 1;
+/// Delay between two lookups while waiting for another request to fill the entry.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Headers which are not stored with the entry.
+const SKIPPED_HEADERS: [HeaderName; 4] = [
+    header::CONNECTION,
+    header::TRANSFER_ENCODING,
+    header::CONTENT_LENGTH,
+    header::SET_COOKIE,
+];
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or(0)
+}
+
+fn millis(duration: Duration) -> i64 {
+    i64::try_from(duration.as_millis()).unwrap_or(i64::MAX)
+}
+
+// Whether any `Cache-Control` header contains one of `directives`.
+fn cache_control(headers: &HeaderMap, directives: &[&str]) -> bool {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.split('=').next().unwrap_or("").trim())
+        .any(|name| directives.iter().any(|d| name.eq_ignore_ascii_case(d)))
+}
+
+/// A cached response.
+#[derive(Debug, PartialEq)]
+struct Entry {
+    /// Time the response was stored, in milliseconds since the Unix epoch.
+    stored: u64,
+    /// Time until which the response is fresh, in milliseconds since the Unix epoch.
+    fresh_until: u64,
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: Bytes,
+}
+
+impl Entry {
+    fn is_fresh(&self, now: u64) -> bool {
+        now < self.fresh_until
+    }
+
+    // version, stored, fresh_until, status, number of headers, then each header as
+    // length-prefixed name and value, then the body; `None` if the number of
+    // headers or the length of a header does not fit the 16 bits prefixes
+    fn encode(&self) -> Option<Vec<u8>> {
+        let mut buf = Vec::with_capacity(self.body.len() + 64);
+        buf.push(VERSION);
+        buf.extend_from_slice(&self.stored.to_be_bytes());
+        buf.extend_from_slice(&self.fresh_until.to_be_bytes());
+        buf.extend_from_slice(&self.status.as_u16().to_be_bytes());
+        let count = u16::try_from(self.headers.len()).ok()?;
+        buf.extend_from_slice(&count.to_be_bytes());
+        for (name, value) in &self.headers {
+            for part in [name.as_str().as_bytes(), value.as_bytes()].iter() {
+                let len = u16::try_from(part.len()).ok()?;
+                buf.extend_from_slice(&len.to_be_bytes());
+                buf.extend_from_slice(part);
+            }
+        }
+        buf.extend_from_slice(&self.body);
+        Some(buf)
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        fn take<'a>(data: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+            if data.len() < n {
+                return None;
+            }
+            let (head, tail) = data.split_at(n);
+            *data = tail;
+            Some(head)
+        }
+        fn u16(data: &mut &[u8]) -> Option<u16> {
+            take(data, 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+        }
+        fn u64(data: &mut &[u8]) -> Option<u64> {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(take(data, 8)?);
+            Some(u64::from_be_bytes(buf))
+        }
+
+        let mut data = data;
+        if take(&mut data, 1)? != [VERSION] {
+            return None;
+        }
+        let stored = u64(&mut data)?;
+        let fresh_until = u64(&mut data)?;
+        let status = StatusCode::from_u16(u16(&mut data)?).ok()?;
+        let count = u16(&mut data)?;
+
+        let mut headers = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let len = u16(&mut data)? as usize;
+            let name = HeaderName::from_bytes(take(&mut data, len)?).ok()?;
+            let len = u16(&mut data)? as usize;
+            let value = HeaderValue::from_bytes(take(&mut data, len)?).ok()?;
+            headers.push((name, value));
+        }
+
+        Some(Entry {
+            stored,
+            fresh_until,
+            status,
+            headers,
+            body: Bytes::copy_from_slice(data),
+        })
+    }
+
+    fn into_response<B>(self, req: ServiceRequest, now: u64) -> ServiceResponse<B> {
+        let mut res = HttpResponse::with_body(self.status, Body::from(self.body));
+        let headers = res.headers_mut();
+        for (name, value) in self.headers {
+            headers.append(name, value);
+        }
+        let age = now.saturating_sub(self.stored) / 1000;
+        headers.insert(header::AGE, HeaderValue::from(age));
+        req.into_response(res.into_body())
+    }
+}
+
+/// Response cache middleware backed by Redis.
+///
+/// Only `GET` requests without an `Authorization` or `Cookie` header are cached,
+/// and only successful responses without a `Set-Cookie` header are stored. Entries
+/// are keyed by the method, path, query and the values of the headers given to
+/// [`vary`](#method.vary). Requests with cookies are only cached when varying on
+/// `Cookie`, so that each client gets its own entries.
+///
+/// `Cache-Control` is honored in both directions: requests with `no-store` bypass
+/// the cache and requests with `no-cache` are revalidated, while responses with
+/// `no-store`, `no-cache` or `private` are not stored. Cache hits carry an `Age`
+/// header.
+///
+/// Response bodies are buffered in memory before being stored.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use actix_redis::{cache::ResponseCache, RedisActor};
+/// use actix_web::{http::header, web, App, HttpResponse};
+/// use std::time::Duration;
+///
+/// let addr = RedisActor::start("127.0.0.1:6379");
+///
+/// let app = App::new()
+///     .wrap(
+///         ResponseCache::new(addr, Duration::from_secs(60))
+///             .route_ttl("/users/{id}", Duration::from_secs(5))
+///             .vary(header::ACCEPT_LANGUAGE)
+///             .stale_during_refresh(Duration::from_secs(30)),
+///     )
+///     .service(web::resource("/users/{id}").to(|| HttpResponse::Ok()));
+/// ```
+pub struct ResponseCache<A: Actor = RedisActor>(Rc<Inner<A>>);
+
+struct Inner<A: Actor> {
+    addr: Addr<A>,
+    ttl: Duration,
+    route_ttls: HashMap<String, Duration>,
+    vary: Vec<HeaderName>,
+    stale_during_refresh: Duration,
+    lock_timeout: Duration,
+    prefix: String,
+}
+
+impl<A: Actor> ResponseCache<A> {
+    /// Cache responses for `ttl` unless overridden by [`route_ttl`](#method.route_ttl).
+    pub fn new(addr: Addr<A>, ttl: Duration) -> Self {
+        ResponseCache(Rc::new(Inner {
+            addr,
+            ttl,
+            route_ttls: HashMap::new(),
+            vary: Vec::new(),
+            stale_during_refresh: Duration::from_secs(0),
+            lock_timeout: Duration::from_secs(5),
+            prefix: "cache:".to_owned(),
+        }))
+    }
+
+    /// Cache the responses of the resource registered with `pattern` (e.g.
+    /// `/users/{id}`) for `ttl`.
+    pub fn route_ttl(mut self, pattern: &str, ttl: Duration) -> Self {
+        Rc::get_mut(&mut self.0)
+            .unwrap()
+            .route_ttls
+            .insert(pattern.to_owned(), ttl);
+        self
+    }
+
+    /// Store a separate entry for each value of the request header `name`.
+    ///
+    /// Varying on `header::COOKIE` caches requests carrying cookies, which are
+    /// otherwise passed to the service.
+    pub fn vary(mut self, name: HeaderName) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().vary.push(name);
+        self
+    }
+
+    /// Keep serving expired entries for up to `duration` after their TTL, while a
+    /// single request refreshes the entry. Default is zero, i.e. disabled.
+    ///
+    /// Unlike HTTP `stale-while-revalidate`, the refresh is not done in the
+    /// background: the request refreshing the entry calls the service and waits
+    /// for it, only the concurrent requests are served the stale entry.
+    pub fn stale_during_refresh(mut self, duration: Duration) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().stale_during_refresh = duration;
+        self
+    }
+
+    /// Set how long a request may hold the lock of an entry while calling the
+    /// service, and how long other requests wait for it. Default is 5 seconds.
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().lock_timeout = timeout;
+        self
+    }
+
+    /// Set the prefix of the Redis keys. Default is `cache:`.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().prefix = prefix.to_owned();
+        self
+    }
+}
+
+impl<A: Actor> Inner<A> {
+    fn key(&self, req: &ServiceRequest) -> String {
+        let mut key = format!(
+            "{}{} {}?{}",
+            self.prefix,
+            req.method(),
+            req.path(),
+            req.query_string()
+        );
+        for name in &self.vary {
+            key.push('\n');
+            key.push_str(name.as_str());
+            key.push(':');
+            let values = req.headers().get_all(name).map(HeaderValue::as_bytes);
+            for (i, value) in values.enumerate() {
+                if i > 0 {
+                    key.push(',');
+                }
+                key.push_str(&String::from_utf8_lossy(value));
+            }
+        }
+        key
+    }
+
+    fn ttl(&self, req: &ServiceRequest) -> Duration {
+        req.match_pattern()
+            .and_then(|pattern| self.route_ttls.get(&pattern).copied())
+            .unwrap_or(self.ttl)
+    }
+}
+
+impl<A> Inner<A>
+where
+    A: Actor + Handler<Get> + Handler<Set> + Handler<Eval>,
+    A::Context: ToEnvelope<A, Get> + ToEnvelope<A, Set> + ToEnvelope<A, Eval>,
+{
+    async fn lookup(&self, key: &str) -> Result<Option<Entry>, crate::Error> {
+        match self.addr.send(get(key)).await {
+            Ok(Ok(Some(data))) => Ok(Entry::decode(&data)),
+            Ok(Ok(None)) => Ok(None),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(crate::Error::Disconnected),
+        }
+    }
+
+    async fn store(&self, key: &str, entry: &Entry, ttl: Duration) {
+        let data = match entry.encode() {
+            Some(data) => data,
+            None => {
+                warn!("cannot store response in cache: headers are too large");
+                return;
+            }
+        };
+        let px = millis(ttl + self.stale_during_refresh);
+        match self.addr.send(set(key, data).px(px)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => warn!("cannot store response in cache: {}", e),
+            Err(e) => warn!("cannot store response in cache: {}", e),
+        }
+    }
+
+    async fn try_lock(&self, key: &str) -> Result<Option<LockGuard<A>>, crate::Error> {
+        let lock =
+            Lock::new(self.addr.clone(), format!("{}:lock", key)).ttl(self.lock_timeout);
+        lock.try_acquire().await
+    }
+
+    // Waits for the request holding the lock to fill the entry.
+    async fn wait(&self, key: &str) -> Option<Entry> {
+        let deadline = Instant::now() + self.lock_timeout;
+        while Instant::now() < deadline {
+            delay_for(POLL_INTERVAL).await;
+            if let Ok(Some(entry)) = self.lookup(key).await {
+                return Some(entry);
+            }
+        }
+        None
+    }
+}
+
+impl<S, B, A> Transform<S> for ResponseCache<A>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+    A: Actor + Handler<Get> + Handler<Set> + Handler<Eval>,
+    A::Context: ToEnvelope<A, Get> + ToEnvelope<A, Set> + ToEnvelope<A, Eval>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = S::Error;
+    type InitError = ();
+    type Transform = ResponseCacheMiddleware<S, A>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ResponseCacheMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            inner: self.0.clone(),
+        })
+    }
+}
+
+/// Response cache middleware
+pub struct ResponseCacheMiddleware<S: 'static, A: Actor> {
+    service: Rc<RefCell<S>>,
+    inner: Rc<Inner<A>>,
+}
+
+impl<S, B, A> Service for ResponseCacheMiddleware<S, A>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+    A: Actor + Handler<Get> + Handler<Set> + Handler<Eval>,
+    A::Context: ToEnvelope<A, Get> + ToEnvelope<A, Set> + ToEnvelope<A, Eval>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let srv = self.service.clone();
+        let inner = self.inner.clone();
+
+        Box::pin(async move {
+            if req.method() != Method::GET
+                || req.headers().contains_key(header::AUTHORIZATION)
+                || (req.headers().contains_key(header::COOKIE)
+                    && !inner.vary.contains(&header::COOKIE))
+                || cache_control(req.headers(), &["no-store"])
+            {
+                let fut = srv.borrow_mut().call(req);
+                return fut.await;
+            }
+
+            let key = inner.key(&req);
+            let ttl = inner.ttl(&req);
+
+            let cached = if cache_control(req.headers(), &["no-cache"]) {
+                None
+            } else {
+                match inner.lookup(&key).await {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        warn!("response cache is unavailable: {}", e);
+                        let fut = srv.borrow_mut().call(req);
+                        return fut.await;
+                    }
+                }
+            };
+
+            let now = unix_millis();
+            let guard = match cached {
+                Some(entry) if entry.is_fresh(now) => {
+                    return Ok(entry.into_response(req, now));
+                }
+                // stale: refresh it if nobody else does, serve it otherwise; the
+                // request cannot be kept for a background refresh, as it is
+                // needed for the response
+                Some(entry) => match inner.try_lock(&key).await {
+                    Ok(Some(guard)) => Some(guard),
+                    Ok(None) => return Ok(entry.into_response(req, now)),
+                    Err(e) => {
+                        warn!("cannot lock cache entry: {}", e);
+                        return Ok(entry.into_response(req, now));
+                    }
+                },
+                None => match inner.try_lock(&key).await {
+                    Ok(Some(guard)) => Some(guard),
+                    Ok(None) => match inner.wait(&key).await {
+                        Some(entry) => {
+                            return Ok(entry.into_response(req, unix_millis()))
+                        }
+                        None => None,
+                    },
+                    // nobody may be filling the entry, call the service right away
+                    Err(e) => {
+                        warn!("cannot lock cache entry: {}", e);
+                        None
+                    }
+                },
+            };
+
+            let fut = srv.borrow_mut().call(req);
+            let mut res = fut.await?;
+
+            if guard.is_none()
+                || !res.status().is_success()
+                || res.headers().contains_key(header::SET_COOKIE)
+                || cache_control(res.headers(), &["no-store", "no-cache", "private"])
+            {
+                return Ok(res);
+            }
+
+            let mut body = BytesMut::new();
+            let mut stream = Box::pin(res.take_body());
+            while let Some(chunk) = stream.next().await {
+                body.extend_from_slice(&chunk?);
+            }
+            let body = body.freeze();
+
+            let stored = unix_millis();
+            let entry = Entry {
+                stored,
+                fresh_until: stored + millis(ttl) as u64,
+                status: res.status(),
+                headers: res
+                    .headers()
+                    .iter()
+                    .filter(|(name, _)| !SKIPPED_HEADERS.contains(name))
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect(),
+                body: body.clone(),
+            };
+            inner.store(&key, &entry, ttl).await;
+            if let Some(guard) = guard {
+                let _ = guard.release().await;
+            }
+
+            Ok(res.map_body(|_, _| ResponseBody::Other(Body::from(body))))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::{test, web, App, HttpRequest};
+    use redis_async::resp::RespValue;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_encode_decode() {
+        let entry = Entry {
+            stored: 1_600_000_000_000,
+            fresh_until: 1_600_000_060_000,
+            status: StatusCode::OK,
+            headers: vec![
+                (header::CONTENT_TYPE, HeaderValue::from_static("text/plain")),
+                (header::VARY, HeaderValue::from_static("accept-language")),
+            ],
+            body: Bytes::from_static(b"hello"),
+        };
+        assert_eq!(Entry::decode(&entry.encode().unwrap()), Some(entry));
+        assert_eq!(Entry::decode(b"\x02garbage"), None);
+    }
+
+    #[test]
+    fn test_encode_large_header() {
+        let value = HeaderValue::from_str(&"a".repeat(70_000)).unwrap();
+        let entry = Entry {
+            stored: 1_600_000_000_000,
+            fresh_until: 1_600_000_060_000,
+            status: StatusCode::OK,
+            headers: vec![(header::LINK, value)],
+            body: Bytes::from_static(b"hello"),
+        };
+        assert_eq!(entry.encode(), None);
+    }
+
+    // Responds with the number of calls and the requested language.
+    fn counting(calls: Rc<Cell<usize>>) -> impl Fn(HttpRequest) -> HttpResponse + Clone {
+        move |req: HttpRequest| {
+            calls.set(calls.get() + 1);
+            let lang = req
+                .headers()
+                .get(header::ACCEPT_LANGUAGE)
+                .map(|v| v.to_str().unwrap().to_owned())
+                .unwrap_or_default();
+            HttpResponse::Ok().body(format!("{} {}", calls.get(), lang))
+        }
+    }
+
+    fn prefix() -> String {
+        format!("test-cache-{}:", rand::random::<u32>())
+    }
+
+    #[actix_rt::test]
+    async fn test_cache_hit() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let calls = Rc::new(Cell::new(0));
+        let cache = ResponseCache::new(addr, Duration::from_secs(60)).prefix(&prefix());
+        let mut app = test::init_service(
+            App::new()
+                .wrap(cache)
+                .service(web::resource("/").to(counting(calls.clone()))),
+        )
+        .await;
+
+        let req = test::TestRequest::get().to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert_eq!(body, Bytes::from_static(b"1 "));
+
+        let req = test::TestRequest::get().to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(res.headers().contains_key(header::AGE));
+        assert_eq!(test::read_body(res).await, Bytes::from_static(b"1 "));
+        assert_eq!(calls.get(), 1);
+
+        // a different query is a different entry
+        let req = test::TestRequest::get().uri("/?page=2").to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert_eq!(body, Bytes::from_static(b"2 "));
+    }
+
+    #[actix_rt::test]
+    async fn test_vary() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let calls = Rc::new(Cell::new(0));
+        let cache = ResponseCache::new(addr, Duration::from_secs(60))
+            .prefix(&prefix())
+            .vary(header::ACCEPT_LANGUAGE);
+        let mut app = test::init_service(
+            App::new()
+                .wrap(cache)
+                .service(web::resource("/").to(counting(calls.clone()))),
+        )
+        .await;
+
+        for (lang, expected) in &[("en", "1 en"), ("fr", "2 fr"), ("en", "1 en")] {
+            let req = test::TestRequest::get()
+                .header(header::ACCEPT_LANGUAGE, *lang)
+                .to_request();
+            let body = test::read_response(&mut app, req).await;
+            assert_eq!(body, Bytes::from(*expected));
+        }
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_cache_control() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let calls = Rc::new(Cell::new(0));
+        let cache = ResponseCache::new(addr, Duration::from_secs(60)).prefix(&prefix());
+        let mut app = test::init_service(
+            App::new()
+                .wrap(cache)
+                .service(web::resource("/").to(counting(calls.clone()))),
+        )
+        .await;
+
+        for _ in 0..2 {
+            let req = test::TestRequest::get()
+                .header(header::CACHE_CONTROL, "no-store")
+                .to_request();
+            test::read_response(&mut app, req).await;
+        }
+        assert_eq!(calls.get(), 2);
+
+        // no-cache refreshes the entry
+        let req = test::TestRequest::get().to_request();
+        assert_eq!(test::read_response(&mut app, req).await, Bytes::from("3 "));
+        let req = test::TestRequest::get()
+            .header(header::CACHE_CONTROL, "max-age=0, no-cache")
+            .to_request();
+        assert_eq!(test::read_response(&mut app, req).await, Bytes::from("4 "));
+        let req = test::TestRequest::get().to_request();
+        assert_eq!(test::read_response(&mut app, req).await, Bytes::from("4 "));
+    }
+
+    #[actix_rt::test]
+    async fn test_private_response() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let calls = Rc::new(Cell::new(0));
+        let cache = ResponseCache::new(addr, Duration::from_secs(60)).prefix(&prefix());
+        let calls2 = calls.clone();
+        let mut app = test::init_service(App::new().wrap(cache).service(
+            web::resource("/").to(move || {
+                calls2.set(calls2.get() + 1);
+                HttpResponse::Ok()
+                    .header(header::CACHE_CONTROL, "private")
+                    .finish()
+            }),
+        ))
+        .await;
+
+        for _ in 0..2 {
+            let req = test::TestRequest::get().to_request();
+            test::call_service(&mut app, req).await;
+        }
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_stale_during_refresh() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let calls = Rc::new(Cell::new(0));
+        let prefix = prefix();
+        let cache = ResponseCache::new(addr.clone(), Duration::from_millis(100))
+            .prefix(&prefix)
+            .stale_during_refresh(Duration::from_secs(10));
+        let mut app = test::init_service(
+            App::new()
+                .wrap(cache)
+                .service(web::resource("/").to(counting(calls.clone()))),
+        )
+        .await;
+
+        let req = test::TestRequest::get().to_request();
+        assert_eq!(test::read_response(&mut app, req).await, Bytes::from("1 "));
+        delay_for(Duration::from_millis(200)).await;
+
+        // another request is refreshing the entry: serve the stale one
+        let lock = Lock::new(addr, format!("{}GET /?:lock", prefix));
+        let guard = lock.try_acquire().await.unwrap().unwrap();
+        let req = test::TestRequest::get().to_request();
+        assert_eq!(test::read_response(&mut app, req).await, Bytes::from("1 "));
+        guard.release().await.unwrap();
+
+        let req = test::TestRequest::get().to_request();
+        assert_eq!(test::read_response(&mut app, req).await, Bytes::from("2 "));
+        let req = test::TestRequest::get().to_request();
+        assert_eq!(test::read_response(&mut app, req).await, Bytes::from("2 "));
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_cookies() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let calls = Rc::new(Cell::new(0));
+        let cache =
+            ResponseCache::new(addr.clone(), Duration::from_secs(60)).prefix(&prefix());
+        let mut app = test::init_service(
+            App::new()
+                .wrap(cache)
+                .service(web::resource("/").to(counting(calls.clone()))),
+        )
+        .await;
+
+        // requests with cookies may be personalised: they are not cached
+        for expected in &["1 ", "2 "] {
+            let req = test::TestRequest::get()
+                .header(header::COOKIE, "session=alice")
+                .to_request();
+            let body = test::read_response(&mut app, req).await;
+            assert_eq!(body, Bytes::from(*expected));
+        }
+        let req = test::TestRequest::get().to_request();
+        assert_eq!(test::read_response(&mut app, req).await, Bytes::from("3 "));
+        let req = test::TestRequest::get()
+            .header(header::COOKIE, "session=bob")
+            .to_request();
+        assert_eq!(test::read_response(&mut app, req).await, Bytes::from("4 "));
+
+        // unless the entries vary on the cookies
+        let cache = ResponseCache::new(addr, Duration::from_secs(60))
+            .prefix(&prefix())
+            .vary(header::COOKIE);
+        let mut app = test::init_service(
+            App::new()
+                .wrap(cache)
+                .service(web::resource("/").to(counting(calls.clone()))),
+        )
+        .await;
+        for (cookie, expected) in &[("alice", "5 "), ("bob", "6 "), ("alice", "5 ")] {
+            let req = test::TestRequest::get()
+                .header(header::COOKIE, format!("session={}", cookie))
+                .to_request();
+            let body = test::read_response(&mut app, req).await;
+            assert_eq!(body, Bytes::from(*expected));
+        }
+        assert_eq!(calls.get(), 6);
+    }
+
+    /// Misses every entry and fails every script, e.g. a server without `EVAL`.
+    struct NoScripts;
+
+    impl Actor for NoScripts {
+        type Context = actix::Context<Self>;
+    }
+
+    impl Handler<Get> for NoScripts {
+        type Result = Result<Option<Vec<u8>>, crate::Error>;
+
+        fn handle(&mut self, _: Get, _: &mut Self::Context) -> Self::Result {
+            Ok(None)
+        }
+    }
+
+    impl Handler<Set> for NoScripts {
+        type Result = Result<bool, crate::Error>;
+
+        fn handle(&mut self, _: Set, _: &mut Self::Context) -> Self::Result {
+            Ok(true)
+        }
+    }
+
+    impl Handler<Eval> for NoScripts {
+        type Result = Result<RespValue, crate::Error>;
+
+        fn handle(&mut self, _: Eval, _: &mut Self::Context) -> Self::Result {
+            Err(crate::Error::NotConnected)
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_lock_unavailable() {
+        let calls = Rc::new(Cell::new(0));
+        let cache = ResponseCache::new(NoScripts.start(), Duration::from_secs(60))
+            .lock_timeout(Duration::from_secs(5));
+        let mut app = test::init_service(
+            App::new()
+                .wrap(cache)
+                .service(web::resource("/").to(counting(calls.clone()))),
+        )
+        .await;
+
+        // the service is called without waiting for the lock timeout
+        let start = Instant::now();
+        let req = test::TestRequest::get().to_request();
+        assert_eq!(test::read_response(&mut app, req).await, Bytes::from("1 "));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -1,13 +1,13 @@
 use actix::prelude::*;
 use actix_utils::oneshot;
-use futures_util::future::FutureExt;
+use futures_util::future::{join_all, FutureExt};
 use log::{debug, info, warn};
 use redis_async::resp::RespValue;
 
 use std::collections::HashMap;
 
 use crate::command::{Asking, ClusterSlots, RedisClusterCommand, RedisCommand};
-use crate::{Error, RedisActor, RespError, Slots};
+use crate::{Error, GracefulShutdown, RedisActor, RespError, Slots};
 
 const MAX_RETRY: usize = 16;
 
@@ -33,6 +33,7 @@ pub struct RedisClusterActor {
     initial_addr: String,
     slots: Vec<Slots>,
     connections: HashMap<String, Addr<RedisActor>>,
+    closing: bool,
 }
 
 impl RedisClusterActor {
@@ -43,6 +44,7 @@ impl RedisClusterActor {
             initial_addr: addr,
             slots: vec![],
             connections: HashMap::new(),
+            closing: false,
         })
     }
 
@@ -97,6 +99,12 @@ impl RedisClusterActor {
             DebugResp(&req)
         );
 
+        // Do not open new connections after a graceful shutdown.
+        if self.closing {
+            let _ = sender.send(Err(Error::NotConnected));
+            return Box::pin(actix::fut::ready(()));
+        }
+
         // If the node address is specified (in the case of redirection), use the address.
         // Otherwise, select the node based on slot information.
         let addr = match addr {
@@ -261,7 +269,9 @@ impl Actor for RedisClusterActor {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.wait(self.refresh_slots());
+        if !self.closing {
+            ctx.wait(self.refresh_slots());
+        }
     }
 }
 
@@ -281,6 +291,10 @@ where
     type Result = ResponseFuture<Result<T::Output, Error>>;
 
     fn handle(&mut self, msg: T, ctx: &mut Self::Context) -> Self::Result {
+        if self.closing {
+            return Box::pin(futures_util::future::err(Error::NotConnected));
+        }
+
         // refuse operations over multiple slots
         let slot = match msg.slot() {
             Ok(slot) => slot,
@@ -302,3 +316,21 @@ where
         }))
     }
 }
+
+impl Handler<GracefulShutdown> for RedisClusterActor {
+    type Result = ResponseFuture<()>;
+
+    /// Shuts down the connections to all nodes.
+    fn handle(&mut self, msg: GracefulShutdown, _: &mut Self::Context) -> Self::Result {
+        self.closing = true;
+        self.slots.clear();
+
+        let timeout = msg.timeout;
+        let shutdown = self
+            .connections
+            .drain()
+            .map(|(_, connection)| connection.send(GracefulShutdown { timeout }))
+            .collect::<Vec<_>>();
+        Box::pin(join_all(shutdown).map(|_| ()))
+    }
+}
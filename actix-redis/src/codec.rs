@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+
+use actix_web::{error, Error};
+use log::warn;
+#[cfg(feature = "bincode")]
+use serde::{Deserialize, Serialize};
+
+/// Tag of a state prefixed with its version, see
+/// [`RedisSession::optimistic_concurrency`](struct.RedisSession.html#method.optimistic_concurrency).
+const VERSIONED: u8 = 0;
+/// Tag of the MessagePack format, version 1.
+#[cfg(feature = "msgpack")]
+const MSGPACK_V1: u8 = 1;
+/// Tag of the bincode format, version 1.
+#[cfg(feature = "bincode")]
+const BINCODE_V1: u8 = 2;
+
+/// Serialization format of the session state stored by
+/// [`RedisSession`](struct.RedisSession.html).
+///
+/// Except for JSON, which is recognized by its leading `{`, the encoded state starts
+/// with a tag identifying its format and version. States are decoded according to
+/// their tag rather than the configured codec, so that existing sessions keep
+/// loading after the codec is changed; they are written in the new format on their
+/// next change.
+///
+/// The values of the session are JSON, as serialized by [`Session::set`]. JSON
+/// stores them as strings, while MessagePack and bincode encode the values
+/// themselves in their own format, so that they are not escaped a second time.
+/// They are loaded back as equivalent JSON: numbers and the order of object keys
+/// may be written differently, and values which are not JSON cannot be stored.
+///
+/// [`Session::set`]: ../actix_session/struct.Session.html#method.set
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Codec {
+    /// JSON, compatible with the previous versions of this crate.
+    Json,
+    /// MessagePack. Requires the `msgpack` feature.
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    /// bincode. Requires the `bincode` feature.
+    #[cfg(feature = "bincode")]
+    Bincode,
+}
+
+impl Codec {
+    pub(crate) fn encode(
+        self,
+        state: &HashMap<String, String>,
+    ) -> Result<Vec<u8>, Error> {
+        match self {
+            Codec::Json => Ok(serde_json::to_vec(state)?),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => {
+                let mut buf = vec![MSGPACK_V1];
+                rmp_serde::encode::write(&mut buf, &parse_values(state)?)
+                    .map_err(error::ErrorInternalServerError)?;
+                Ok(buf)
+            }
+            #[cfg(feature = "bincode")]
+            Codec::Bincode => {
+                let values: HashMap<&str, BincodeValue> = parse_values(state)?
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect();
+                let mut buf = vec![BINCODE_V1];
+                bincode1::serialize_into(&mut buf, &values)
+                    .map_err(error::ErrorInternalServerError)?;
+                Ok(buf)
+            }
+        }
+    }
+
+    /// Decodes a state encoded by any of the enabled codecs.
+    pub(crate) fn decode(data: &[u8]) -> Option<HashMap<String, String>> {
+        let res = match data.first() {
+            Some(b'{') => serde_json::from_slice(data).map_err(error::ErrorBadRequest),
+            #[cfg(feature = "msgpack")]
+            Some(&MSGPACK_V1) => rmp_serde::from_read_ref(&data[1..])
+                .map(print_values)
+                .map_err(error::ErrorBadRequest),
+            #[cfg(feature = "bincode")]
+            Some(&BINCODE_V1) => {
+                bincode1::deserialize::<HashMap<String, BincodeValue>>(&data[1..])
+                    .map(|values| {
+                        print_values(
+                            values
+                                .into_iter()
+                                .map(|(key, value)| (key, value.into()))
+                                .collect(),
+                        )
+                    })
+                    .map_err(error::ErrorBadRequest)
+            }
+            Some(tag) => Err(error::ErrorBadRequest(format!(
+                "unsupported session format {}",
+                tag
+            ))),
+            None => Err(error::ErrorBadRequest("empty session state")),
+        };
+
+        match res {
+            Ok(state) => Some(state),
+            Err(e) => {
+                warn!("cannot decode session state: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Parses the JSON values of a state, to be encoded natively.
+#[cfg(any(feature = "msgpack", feature = "bincode"))]
+fn parse_values(
+    state: &HashMap<String, String>,
+) -> Result<HashMap<&str, serde_json::Value>, Error> {
+    state
+        .iter()
+        .map(|(key, value)| {
+            let value = serde_json::from_str(value).map_err(|e| {
+                error::ErrorInternalServerError(format!(
+                    "session value {:?} is not JSON: {}",
+                    key, e
+                ))
+            })?;
+            Ok((key.as_str(), value))
+        })
+        .collect()
+}
+
+/// Prints back the values parsed by `parse_values`.
+#[cfg(any(feature = "msgpack", feature = "bincode"))]
+fn print_values(values: HashMap<String, serde_json::Value>) -> HashMap<String, String> {
+    values
+        .into_iter()
+        .map(|(key, value)| (key, value.to_string()))
+        .collect()
+}
+
+/// A JSON value encoded by bincode, which cannot decode `serde_json::Value` as its
+/// format does not describe the types of the values.
+#[cfg(feature = "bincode")]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum BincodeValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    Array(Vec<BincodeValue>),
+    Object(Vec<(String, BincodeValue)>),
+}
+
+#[cfg(feature = "bincode")]
+impl From<serde_json::Value> for BincodeValue {
+    fn from(value: serde_json::Value) -> Self {
+        use serde_json::Value;
+
+        match value {
+            Value::Null => BincodeValue::Null,
+            Value::Bool(b) => BincodeValue::Bool(b),
+            Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+                (Some(n), _) => BincodeValue::UInt(n),
+                (None, Some(n)) => BincodeValue::Int(n),
+                (None, None) => BincodeValue::Float(n.as_f64().unwrap_or_default()),
+            },
+            Value::String(s) => BincodeValue::String(s),
+            Value::Array(values) => {
+                BincodeValue::Array(values.into_iter().map(Into::into).collect())
+            }
+            Value::Object(map) => BincodeValue::Object(
+                map.into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl From<BincodeValue> for serde_json::Value {
+    fn from(value: BincodeValue) -> Self {
+        use serde_json::Value;
+
+        match value {
+            BincodeValue::Null => Value::Null,
+            BincodeValue::Bool(b) => Value::Bool(b),
+            BincodeValue::Int(n) => Value::from(n),
+            BincodeValue::UInt(n) => Value::from(n),
+            BincodeValue::Float(n) => Value::from(n),
+            BincodeValue::String(s) => Value::String(s),
+            BincodeValue::Array(values) => {
+                Value::Array(values.into_iter().map(Into::into).collect())
+            }
+            BincodeValue::Object(entries) => Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Header of a state prefixed with `version`.
+pub(crate) fn version_header(version: u64) -> Vec<u8> {
+    let mut header = vec![VERSIONED];
+    header.extend_from_slice(&version.to_be_bytes());
+    header
+}
+
+/// Prefixes an encoded state with `version`.
+pub(crate) fn versioned(version: u64, data: &[u8]) -> Vec<u8> {
+    let mut buf = version_header(version);
+    buf.extend_from_slice(data);
+    buf
+}
+
+/// Splits the version off an encoded state, if prefixed with one.
+pub(crate) fn unversioned(data: &[u8]) -> (Option<u64>, &[u8]) {
+    match data.split_first() {
+        Some((&VERSIONED, rest)) if rest.len() >= 8 => {
+            let mut version = [0; 8];
+            version.copy_from_slice(&rest[..8]);
+            (Some(u64::from_be_bytes(version)), &rest[8..])
+        }
+        _ => (None, data),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state() -> HashMap<String, String> {
+        let mut state = HashMap::new();
+        state.insert("user_id".to_owned(), "\"ferris\"".to_owned());
+        state.insert("counter".to_owned(), "42".to_owned());
+        state.insert(
+            "cart".to_owned(),
+            r#"{"items":[1,-2,2.5,null,true]}"#.to_owned(),
+        );
+        state
+    }
+
+    #[test]
+    fn test_json() {
+        let data = Codec::Json.encode(&state()).unwrap();
+        assert_eq!(data[0], b'{');
+        assert_eq!(Codec::decode(&data), Some(state()));
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack() {
+        let data = Codec::MessagePack.encode(&state()).unwrap();
+        assert_eq!(data[0], MSGPACK_V1);
+        assert_eq!(Codec::decode(&data), Some(state()));
+
+        // the values are MessagePack rather than JSON strings
+        let values: HashMap<String, serde_json::Value> =
+            rmp_serde::from_read_ref(&data[1..]).unwrap();
+        assert_eq!(values["counter"], serde_json::json!(42));
+        assert_eq!(values["user_id"], serde_json::json!("ferris"));
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode() {
+        let data = Codec::Bincode.encode(&state()).unwrap();
+        assert_eq!(data[0], BINCODE_V1);
+        assert_eq!(Codec::decode(&data), Some(state()));
+
+        // the values are bincode rather than JSON strings
+        let values: HashMap<String, BincodeValue> =
+            bincode1::deserialize(&data[1..]).unwrap();
+        assert_eq!(values["counter"], BincodeValue::UInt(42));
+        assert_eq!(values["user_id"], BincodeValue::String("ferris".to_owned()));
+    }
+
+    #[cfg(any(feature = "msgpack", feature = "bincode"))]
+    #[test]
+    fn test_not_json() {
+        let mut state = state();
+        state.insert("raw".to_owned(), "not json".to_owned());
+        #[cfg(feature = "msgpack")]
+        assert!(Codec::MessagePack.encode(&state).is_err());
+        #[cfg(feature = "bincode")]
+        assert!(Codec::Bincode.encode(&state).is_err());
+    }
+
+    #[test]
+    fn test_legacy_json() {
+        let data = serde_json::to_string(&state()).unwrap();
+        assert_eq!(Codec::decode(data.as_bytes()), Some(state()));
+    }
+
+    #[test]
+    fn test_versioned() {
+        let data = Codec::Json.encode(&state()).unwrap();
+        let stored = versioned(3, &data);
+        assert!(stored.starts_with(&version_header(3)));
+        assert_eq!(unversioned(&stored), (Some(3), &data[..]));
+        assert_eq!(unversioned(&data), (None, &data[..]));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(Codec::decode(b""), None);
+        assert_eq!(Codec::decode(b"\xffstate"), None);
+        assert_eq!(Codec::decode(b"{\"counter\""), None);
+    }
+}
@@ -1,30 +1,62 @@
 //! Redis command types.
 
 mod asking;
+mod cluster_addslots;
 mod cluster_countkeysinslot;
 mod cluster_getkeysinslot;
+mod cluster_info;
+mod cluster_meet;
+mod cluster_nodes;
+mod cluster_replicate;
 pub mod cluster_setslot;
 mod cluster_slots;
 mod del;
 mod echo;
+mod eval;
+mod exists;
+mod expire;
 mod get;
+mod hdel;
+mod hgetall;
+mod hset;
 mod migrate;
 mod ping;
+mod sadd;
+mod scan;
 mod set;
 mod shutdown;
+mod smembers;
+mod srem;
+mod ttl;
 
 pub use asking::{asking, Asking};
+pub use cluster_addslots::{cluster_add_slots, ClusterAddSlots};
 pub use cluster_countkeysinslot::{cluster_count_keys_in_slot, ClusterCountKeysInSlot};
 pub use cluster_getkeysinslot::{cluster_get_keys_in_slot, ClusterGetKeysInSlot};
+pub use cluster_info::{cluster_info, ClusterInfo};
+pub use cluster_meet::{cluster_meet, ClusterMeet};
+pub use cluster_nodes::{cluster_nodes, ClusterNode, ClusterNodes};
+pub use cluster_replicate::{cluster_replicate, ClusterReplicate};
 pub use cluster_setslot::ClusterSetSlot;
 pub use cluster_slots::{cluster_slots, ClusterSlots};
 pub use del::{del, del_multiple, Del};
 pub use echo::{echo, Echo};
+pub use eval::{eval, Eval};
+pub use exists::{exists, exists_multiple, Exists};
+pub use expire::{expire, Expire};
 pub use get::{get, Get};
-pub use migrate::{migrate, Migrate};
+pub use hdel::{hdel, hdel_multiple, HDel};
+pub use hgetall::{hgetall, HGetAll};
+pub use hset::{hset, hset_multiple, HSet};
+pub use migrate::{migrate, migrate_keys, Migrate, MigrateKeys};
 pub use ping::{ping, ping_message, Ping};
+pub use sadd::{sadd, sadd_multiple, SAdd};
+pub use scan::{scan, Scan};
 pub use set::{set, Set};
 pub use shutdown::{shutdown, Shutdown};
+pub use smembers::{smembers, SMembers};
+pub use srem::{srem, srem_multiple, SRem};
+pub use ttl::{ttl, Ttl};
 
 use redis_async::resp::RespValue;
 
@@ -0,0 +1,45 @@
+use super::{DeserializeError, RedisCommand};
+use crate::Error;
+
+use actix::Message;
+use redis_async::resp::RespValue;
+
+/// CLUSTER ADDSLOTS command.
+#[derive(Debug)]
+pub struct ClusterAddSlots {
+    pub slots: Vec<u16>,
+}
+
+/// CLUSTER ADDSLOTS command, assigning the slots to the node.
+pub fn cluster_add_slots<I: IntoIterator<Item = u16>>(slots: I) -> ClusterAddSlots {
+    ClusterAddSlots {
+        slots: slots.into_iter().collect(),
+    }
+}
+
+impl RedisCommand for ClusterAddSlots {
+    type Output = ();
+
+    fn serialize(self) -> RespValue {
+        let mut data = Vec::with_capacity(2 + self.slots.len());
+        data.push("CLUSTER".into());
+        data.push("ADDSLOTS".into());
+        data.extend(self.slots.into_iter().map(|slot| slot.to_string().into()));
+
+        RespValue::Array(data)
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            RespValue::SimpleString(s) if s == "OK" => Ok(()),
+            resp => Err(DeserializeError::new(
+                "invalid response to CLUSTER ADDSLOTS",
+                resp,
+            )),
+        }
+    }
+}
+
+impl Message for ClusterAddSlots {
+    type Result = Result<(), Error>;
+}
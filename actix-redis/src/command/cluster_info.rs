@@ -0,0 +1,49 @@
+use super::{DeserializeError, RedisCommand};
+use crate::Error;
+
+use actix::Message;
+use redis_async::{resp::RespValue, resp_array};
+use std::collections::HashMap;
+
+/// CLUSTER INFO command.
+#[derive(Debug)]
+pub struct ClusterInfo;
+
+/// CLUSTER INFO command.
+pub fn cluster_info() -> ClusterInfo {
+    ClusterInfo
+}
+
+impl RedisCommand for ClusterInfo {
+    /// The `field:value` pairs of the reply, e.g. `cluster_state` => `ok`.
+    type Output = HashMap<String, String>;
+
+    fn serialize(self) -> RespValue {
+        resp_array!["CLUSTER", "INFO"]
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            RespValue::BulkString(s) => Ok(String::from_utf8_lossy(&s)
+                .lines()
+                .filter_map(|line| {
+                    let mut field = line.splitn(2, ':');
+                    match (field.next(), field.next()) {
+                        (Some(key), Some(value)) => {
+                            Some((key.to_string(), value.trim().to_string()))
+                        }
+                        _ => None,
+                    }
+                })
+                .collect()),
+            resp => Err(DeserializeError::new(
+                "invalid response to CLUSTER INFO",
+                resp,
+            )),
+        }
+    }
+}
+
+impl Message for ClusterInfo {
+    type Result = Result<HashMap<String, String>, Error>;
+}
@@ -0,0 +1,42 @@
+use super::{DeserializeError, RedisCommand};
+use crate::Error;
+
+use actix::Message;
+use redis_async::{resp::RespValue, resp_array};
+
+/// CLUSTER MEET command.
+#[derive(Debug)]
+pub struct ClusterMeet {
+    pub host: String,
+    pub port: u16,
+}
+
+/// CLUSTER MEET command, connecting the node to another node at `host:port`.
+pub fn cluster_meet<S: Into<String>>(host: S, port: u16) -> ClusterMeet {
+    ClusterMeet {
+        host: host.into(),
+        port,
+    }
+}
+
+impl RedisCommand for ClusterMeet {
+    type Output = ();
+
+    fn serialize(self) -> RespValue {
+        resp_array!["CLUSTER", "MEET", self.host, self.port.to_string()]
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            RespValue::SimpleString(s) if s == "OK" => Ok(()),
+            resp => Err(DeserializeError::new(
+                "invalid response to CLUSTER MEET",
+                resp,
+            )),
+        }
+    }
+}
+
+impl Message for ClusterMeet {
+    type Result = Result<(), Error>;
+}
@@ -0,0 +1,204 @@
+use super::{DeserializeError, RedisCommand};
+use crate::Error;
+
+use actix::Message;
+use redis_async::{resp::RespValue, resp_array};
+
+/// A node entry in the response to CLUSTER NODES.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClusterNode {
+    /// Node ID.
+    pub id: String,
+    /// IP address the node is reachable at (may be empty while the node is unknown).
+    pub host: String,
+    /// Port used by clients.
+    pub port: u16,
+    /// Flags, e.g. `myself`, `master`, `slave`, `fail?`, `fail`.
+    pub flags: Vec<String>,
+    /// ID of the master if the node is a replica.
+    pub master_id: Option<String>,
+    /// Configuration epoch of the node.
+    pub config_epoch: u64,
+    /// Whether the link of the cluster bus is `connected`.
+    pub connected: bool,
+    /// Ranges of the slots served by the node, both ends inclusive.
+    ///
+    /// Slots being imported or migrated (`[slot-<-id]` / `[slot->-id]`) are not included.
+    pub slots: Vec<(u16, u16)>,
+    /// Slots being migrated to another node, with the ID of that node. Only listed
+    /// in the `myself` entry.
+    pub migrating: Vec<(u16, String)>,
+    /// Slots being imported from another node, with the ID of that node. Only
+    /// listed in the `myself` entry.
+    pub importing: Vec<(u16, String)>,
+}
+
+impl ClusterNode {
+    /// Returns true if this entry describes the node the command was sent to.
+    pub fn is_myself(&self) -> bool {
+        self.flags.iter().any(|flag| flag == "myself")
+    }
+
+    /// Returns true if the node is a master.
+    pub fn is_master(&self) -> bool {
+        self.flags.iter().any(|flag| flag == "master")
+    }
+}
+
+/// CLUSTER NODES command.
+#[derive(Debug)]
+pub struct ClusterNodes;
+
+/// CLUSTER NODES command.
+pub fn cluster_nodes() -> ClusterNodes {
+    ClusterNodes
+}
+
+fn parse_line(line: &str) -> Result<ClusterNode, String> {
+    let mut fields = line.split(' ');
+    let mut next = |name: &str| {
+        fields
+            .next()
+            .ok_or_else(|| format!("CLUSTER NODES: missing {}", name))
+    };
+
+    let id = next("id")?.to_string();
+
+    // ip:port@cport[,hostname]
+    let addr = next("address")?;
+    let addr = addr.split(&['@', ','][..]).next().unwrap_or_default();
+    let sep = addr
+        .rfind(':')
+        .ok_or_else(|| format!("CLUSTER NODES: invalid address {:?}", addr))?;
+    let host = addr[..sep].to_string();
+    let port = addr[sep + 1..]
+        .parse()
+        .map_err(|_| format!("CLUSTER NODES: invalid port {:?}", addr))?;
+
+    let flags = next("flags")?.split(',').map(str::to_string).collect();
+    let master_id = match next("master")? {
+        "-" => None,
+        id => Some(id.to_string()),
+    };
+    let _ping_sent = next("ping-sent")?;
+    let _pong_recv = next("pong-recv")?;
+    let config_epoch = next("config-epoch")?
+        .parse()
+        .map_err(|_| "CLUSTER NODES: invalid config epoch".to_string())?;
+    let connected = next("link-state")? == "connected";
+
+    let mut slots = Vec::new();
+    let mut migrating = Vec::new();
+    let mut importing = Vec::new();
+    for slot in fields {
+        let invalid = || format!("CLUSTER NODES: invalid slot {:?}", slot);
+        if slot.starts_with('[') && slot.ends_with(']') {
+            // [slot->-id] or [slot-<-id]
+            let state = &slot[1..slot.len() - 1];
+            let (list, sep) = if state.contains("->-") {
+                (&mut migrating, "->-")
+            } else {
+                (&mut importing, "-<-")
+            };
+            let mut parts = state.splitn(2, sep);
+            let number = parts
+                .next()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(invalid)?;
+            let id = parts.next().ok_or_else(invalid)?;
+            list.push((number, id.to_string()));
+            continue;
+        }
+        let mut range = slot.splitn(2, '-');
+        let start = range
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(invalid)?;
+        let end = match range.next() {
+            Some(end) => end.parse().map_err(|_| invalid())?,
+            None => start,
+        };
+        slots.push((start, end));
+    }
+
+    Ok(ClusterNode {
+        id,
+        host,
+        port,
+        flags,
+        master_id,
+        config_epoch,
+        connected,
+        slots,
+        migrating,
+        importing,
+    })
+}
+
+impl RedisCommand for ClusterNodes {
+    type Output = Vec<ClusterNode>;
+
+    fn serialize(self) -> RespValue {
+        resp_array!["CLUSTER", "NODES"]
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            RespValue::BulkString(s) => {
+                let nodes = String::from_utf8_lossy(&s)
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(parse_line)
+                    .collect::<Result<Vec<_>, _>>();
+                nodes.map_err(|e| DeserializeError::new(e, RespValue::BulkString(s)))
+            }
+            resp => Err(DeserializeError::new(
+                "invalid response to CLUSTER NODES",
+                resp,
+            )),
+        }
+    }
+}
+
+impl Message for ClusterNodes {
+    type Result = Result<Vec<ClusterNode>, Error>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_nodes() {
+        let resp = RespValue::BulkString(
+            b"07c37dfeb235213a872192d90877d0cd55635b91 127.0.0.1:30004@31004 slave \
+              e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 0 1426238317239 4 connected\n\
+              e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 127.0.0.1:30001@31001 myself,master \
+              - 0 0 1 connected 0-5460 5462 [5461->-292f8b365bb7edb5e285caf0b7e6ddc7265d2f4f]\n"
+                .to_vec(),
+        );
+
+        let nodes = ClusterNodes::deserialize(resp).unwrap();
+        assert_eq!(nodes.len(), 2);
+
+        assert!(!nodes[0].is_master());
+        assert_eq!(
+            nodes[0].master_id.as_deref(),
+            Some("e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca")
+        );
+        assert!(nodes[0].slots.is_empty());
+
+        assert!(nodes[1].is_myself());
+        assert!(nodes[1].is_master());
+        assert_eq!(nodes[1].host, "127.0.0.1");
+        assert_eq!(nodes[1].port, 30001);
+        assert_eq!(nodes[1].config_epoch, 1);
+        assert!(nodes[1].connected);
+        assert_eq!(nodes[1].slots, vec![(0, 5460), (5462, 5462)]);
+        assert_eq!(
+            nodes[1].migrating,
+            vec![(5461, "292f8b365bb7edb5e285caf0b7e6ddc7265d2f4f".to_string())]
+        );
+        assert!(nodes[1].importing.is_empty());
+    }
+}
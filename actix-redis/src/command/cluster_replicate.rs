@@ -0,0 +1,40 @@
+use super::{DeserializeError, RedisCommand};
+use crate::Error;
+
+use actix::Message;
+use redis_async::{resp::RespValue, resp_array};
+
+/// CLUSTER REPLICATE command.
+#[derive(Debug)]
+pub struct ClusterReplicate {
+    pub node_id: String,
+}
+
+/// CLUSTER REPLICATE command, making the node a replica of the master `node_id`.
+pub fn cluster_replicate<S: Into<String>>(node_id: S) -> ClusterReplicate {
+    ClusterReplicate {
+        node_id: node_id.into(),
+    }
+}
+
+impl RedisCommand for ClusterReplicate {
+    type Output = ();
+
+    fn serialize(self) -> RespValue {
+        resp_array!["CLUSTER", "REPLICATE", self.node_id]
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            RespValue::SimpleString(s) if s == "OK" => Ok(()),
+            resp => Err(DeserializeError::new(
+                "invalid response to CLUSTER REPLICATE",
+                resp,
+            )),
+        }
+    }
+}
+
+impl Message for ClusterReplicate {
+    type Result = Result<(), Error>;
+}
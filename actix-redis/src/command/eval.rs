@@ -0,0 +1,75 @@
+use super::{DeserializeError, RedisClusterCommand, RedisCommand};
+use crate::{slot::slot_keys, Error};
+
+use actix::Message;
+use redis_async::resp::RespValue;
+
+/// EVAL command.
+#[derive(Debug)]
+pub struct Eval {
+    pub script: String,
+    pub keys: Vec<Vec<u8>>,
+    pub args: Vec<Vec<u8>>,
+}
+
+impl Eval {
+    /// Add a key, accessible as `KEYS[n]` in the script.
+    pub fn key<K: Into<Vec<u8>>>(mut self, key: K) -> Self {
+        self.keys.push(key.into());
+        self
+    }
+
+    /// Add an argument, accessible as `ARGV[n]` in the script.
+    pub fn arg<V: Into<Vec<u8>>>(mut self, arg: V) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+}
+
+/// EVAL command with the specified Lua script.
+///
+/// To pass keys and arguments to the script, please see [`Eval`](struct.Eval.html#methods).
+/// On Redis Cluster, all the keys must belong to the same slot.
+pub fn eval<S: Into<String>>(script: S) -> Eval {
+    Eval {
+        script: script.into(),
+        keys: vec![],
+        args: vec![],
+    }
+}
+
+impl RedisCommand for Eval {
+    /// The value returned by the script.
+    type Output = RespValue;
+
+    fn serialize(self) -> RespValue {
+        let mut data = Vec::with_capacity(3 + self.keys.len() + self.args.len());
+        data.push("EVAL".into());
+        data.push(self.script.into());
+        data.push(self.keys.len().to_string().into());
+        data.extend(self.keys.into_iter().map(Into::into));
+        data.extend(self.args.into_iter().map(Into::into));
+
+        RespValue::Array(data)
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            RespValue::Error(e) => Err(DeserializeError::new(
+                "EVAL: script failed",
+                RespValue::Error(e),
+            )),
+            resp => Ok(resp),
+        }
+    }
+}
+
+impl RedisClusterCommand for Eval {
+    fn slot(&self) -> Result<u16, Vec<u16>> {
+        slot_keys(self.keys.iter())
+    }
+}
+
+impl Message for Eval {
+    type Result = Result<<Eval as RedisCommand>::Output, Error>;
+}
@@ -0,0 +1,56 @@
+use super::{DeserializeError, RedisClusterCommand, RedisCommand};
+use crate::{slot::slot_keys, Error};
+
+use actix::Message;
+use redis_async::resp::RespValue;
+use RespValue::*;
+
+/// EXISTS command.
+#[derive(Debug)]
+pub struct Exists {
+    pub keys: Vec<Vec<u8>>,
+}
+
+/// EXISTS command, single key.
+pub fn exists<K: Into<Vec<u8>>>(key: K) -> Exists {
+    Exists {
+        keys: vec![key.into()],
+    }
+}
+
+/// EXISTS command, multiple keys.
+pub fn exists_multiple<K: Into<Vec<u8>>, I: Iterator<Item = K>>(iter: I) -> Exists {
+    Exists {
+        keys: iter.map(Into::into).collect(),
+    }
+}
+
+impl RedisCommand for Exists {
+    /// Integer reply: the number of keys that exist
+    type Output = i64;
+
+    fn serialize(self) -> RespValue {
+        let mut data = Vec::with_capacity(1 + self.keys.len());
+        data.push("EXISTS".into());
+        data.extend(self.keys.into_iter().map(Into::into));
+
+        RespValue::Array(data)
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            Integer(num) => Ok(num),
+            resp => Err(DeserializeError::new("invalid response to EXISTS", resp)),
+        }
+    }
+}
+
+impl RedisClusterCommand for Exists {
+    fn slot(&self) -> Result<u16, Vec<u16>> {
+        slot_keys(self.keys.iter())
+    }
+}
+
+impl Message for Exists {
+    type Result = Result<<Exists as RedisCommand>::Output, Error>;
+}
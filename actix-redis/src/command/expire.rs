@@ -0,0 +1,49 @@
+use super::{DeserializeError, RedisClusterCommand, RedisCommand};
+use crate::{slot::slot, Error};
+
+use actix::Message;
+use redis_async::{resp::RespValue, resp_array};
+use RespValue::*;
+
+/// EXPIRE command.
+#[derive(Debug)]
+pub struct Expire {
+    pub key: Vec<u8>,
+    pub seconds: i64,
+}
+
+/// EXPIRE command, setting the time to live of the key in seconds.
+pub fn expire<K: Into<Vec<u8>>>(key: K, seconds: i64) -> Expire {
+    Expire {
+        key: key.into(),
+        seconds,
+    }
+}
+
+impl RedisCommand for Expire {
+    /// - `true` if the timeout was set
+    /// - `false` if the key does not exist
+    type Output = bool;
+
+    fn serialize(self) -> RespValue {
+        resp_array!["EXPIRE", self.key, self.seconds.to_string()]
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            Integer(1) => Ok(true),
+            Integer(0) => Ok(false),
+            resp => Err(DeserializeError::new("invalid response to EXPIRE", resp)),
+        }
+    }
+}
+
+impl RedisClusterCommand for Expire {
+    fn slot(&self) -> Result<u16, Vec<u16>> {
+        Ok(slot(&self.key))
+    }
+}
+
+impl Message for Expire {
+    type Result = Result<<Expire as RedisCommand>::Output, Error>;
+}
@@ -0,0 +1,65 @@
+use super::{DeserializeError, RedisClusterCommand, RedisCommand};
+use crate::{slot::slot, Error};
+
+use actix::Message;
+use redis_async::resp::RespValue;
+use RespValue::*;
+
+/// HDEL command.
+#[derive(Debug)]
+pub struct HDel {
+    pub key: Vec<u8>,
+    pub fields: Vec<Vec<u8>>,
+}
+
+/// HDEL command, single field.
+pub fn hdel<K: Into<Vec<u8>>, F: Into<Vec<u8>>>(key: K, field: F) -> HDel {
+    HDel {
+        key: key.into(),
+        fields: vec![field.into()],
+    }
+}
+
+/// HDEL command, multiple fields.
+pub fn hdel_multiple<K, F, I>(key: K, iter: I) -> HDel
+where
+    K: Into<Vec<u8>>,
+    F: Into<Vec<u8>>,
+    I: Iterator<Item = F>,
+{
+    HDel {
+        key: key.into(),
+        fields: iter.map(Into::into).collect(),
+    }
+}
+
+impl RedisCommand for HDel {
+    /// Integer reply: the number of fields that were removed
+    type Output = i64;
+
+    fn serialize(self) -> RespValue {
+        let mut data = Vec::with_capacity(2 + self.fields.len());
+        data.push("HDEL".into());
+        data.push(self.key.into());
+        data.extend(self.fields.into_iter().map(Into::into));
+
+        RespValue::Array(data)
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            Integer(num) => Ok(num),
+            resp => Err(DeserializeError::new("invalid response to HDEL", resp)),
+        }
+    }
+}
+
+impl RedisClusterCommand for HDel {
+    fn slot(&self) -> Result<u16, Vec<u16>> {
+        Ok(slot(&self.key))
+    }
+}
+
+impl Message for HDel {
+    type Result = Result<<HDel as RedisCommand>::Output, Error>;
+}
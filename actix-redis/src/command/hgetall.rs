@@ -0,0 +1,60 @@
+use super::{DeserializeError, RedisClusterCommand, RedisCommand};
+use crate::{slot::slot, Error};
+
+use actix::Message;
+use redis_async::{resp::RespValue, resp_array};
+use RespValue::*;
+
+/// HGETALL command.
+#[derive(Debug)]
+pub struct HGetAll {
+    pub key: Vec<u8>,
+}
+
+/// HGETALL command, returning all the fields and values of the hash.
+pub fn hgetall<K: Into<Vec<u8>>>(key: K) -> HGetAll {
+    HGetAll { key: key.into() }
+}
+
+impl RedisCommand for HGetAll {
+    /// Fields and values of the hash, empty if the key does not exist
+    type Output = Vec<(Vec<u8>, Vec<u8>)>;
+
+    fn serialize(self) -> RespValue {
+        resp_array!["HGETALL", self.key]
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            Array(values) if values.len() % 2 == 0 => {
+                let mut fields = Vec::with_capacity(values.len() / 2);
+                let mut values = values.into_iter();
+                while let (Some(field), Some(value)) = (values.next(), values.next()) {
+                    match (field, value) {
+                        (BulkString(field), BulkString(value)) => {
+                            fields.push((field, value))
+                        }
+                        (field, _) => {
+                            return Err(DeserializeError::new(
+                                "HGETALL: not a bulk string",
+                                field,
+                            ))
+                        }
+                    }
+                }
+                Ok(fields)
+            }
+            resp => Err(DeserializeError::new("invalid response to HGETALL", resp)),
+        }
+    }
+}
+
+impl RedisClusterCommand for HGetAll {
+    fn slot(&self) -> Result<u16, Vec<u16>> {
+        Ok(slot(&self.key))
+    }
+}
+
+impl Message for HGetAll {
+    type Result = Result<<HGetAll as RedisCommand>::Output, Error>;
+}
@@ -0,0 +1,78 @@
+use super::{DeserializeError, RedisClusterCommand, RedisCommand};
+use crate::{slot::slot, Error};
+
+use actix::Message;
+use redis_async::resp::RespValue;
+use RespValue::*;
+
+/// HSET command.
+#[derive(Debug)]
+pub struct HSet {
+    pub key: Vec<u8>,
+    pub fields: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// HSET command, single field.
+pub fn hset<K, F, V>(key: K, field: F, value: V) -> HSet
+where
+    K: Into<Vec<u8>>,
+    F: Into<Vec<u8>>,
+    V: Into<Vec<u8>>,
+{
+    HSet {
+        key: key.into(),
+        fields: vec![(field.into(), value.into())],
+    }
+}
+
+/// HSET command, multiple fields.
+///
+/// At least one field is required.
+pub fn hset_multiple<K, F, V, I>(key: K, iter: I) -> HSet
+where
+    K: Into<Vec<u8>>,
+    F: Into<Vec<u8>>,
+    V: Into<Vec<u8>>,
+    I: Iterator<Item = (F, V)>,
+{
+    HSet {
+        key: key.into(),
+        fields: iter
+            .map(|(field, value)| (field.into(), value.into()))
+            .collect(),
+    }
+}
+
+impl RedisCommand for HSet {
+    /// Integer reply: the number of fields that were added
+    type Output = i64;
+
+    fn serialize(self) -> RespValue {
+        let mut data = Vec::with_capacity(2 + 2 * self.fields.len());
+        data.push("HSET".into());
+        data.push(self.key.into());
+        for (field, value) in self.fields {
+            data.push(field.into());
+            data.push(value.into());
+        }
+
+        RespValue::Array(data)
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            Integer(num) => Ok(num),
+            resp => Err(DeserializeError::new("invalid response to HSET", resp)),
+        }
+    }
+}
+
+impl RedisClusterCommand for HSet {
+    fn slot(&self) -> Result<u16, Vec<u16>> {
+        Ok(slot(&self.key))
+    }
+}
+
+impl Message for HSet {
+    type Result = Result<<HSet as RedisCommand>::Output, Error>;
+}
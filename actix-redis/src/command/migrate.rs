@@ -50,3 +50,57 @@ impl RedisCommand for Migrate {
 impl Message for Migrate {
     type Result = Result<bool, Error>;
 }
+
+/// MIGRATE command moving several keys at once, with the `KEYS` option.
+#[derive(Debug)]
+pub struct MigrateKeys {
+    pub host: String,
+    pub port: u16,
+    pub keys: Vec<String>,
+    pub db: i64,
+    pub timeout: i64,
+}
+
+/// MIGRATE command, multiple keys.
+pub fn migrate_keys<I: IntoIterator<Item = String>>(
+    host: String,
+    port: u16,
+    keys: I,
+    db: i64,
+    timeout: i64,
+) -> MigrateKeys {
+    MigrateKeys {
+        host,
+        port,
+        keys: keys.into_iter().collect(),
+        db,
+        timeout,
+    }
+}
+
+impl RedisCommand for MigrateKeys {
+    /// true if some of the keys existed, false if none did.
+    type Output = bool;
+
+    fn serialize(self) -> RespValue {
+        let mut data = Vec::with_capacity(7 + self.keys.len());
+        data.push("MIGRATE".into());
+        data.push(self.host.into());
+        data.push(self.port.to_string().into());
+        data.push("".into());
+        data.push(self.db.to_string().into());
+        data.push(self.timeout.to_string().into());
+        data.push("KEYS".into());
+        data.extend(self.keys.into_iter().map(Into::into));
+
+        RespValue::Array(data)
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        Migrate::deserialize(resp)
+    }
+}
+
+impl Message for MigrateKeys {
+    type Result = Result<bool, Error>;
+}
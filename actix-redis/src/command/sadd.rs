@@ -0,0 +1,65 @@
+use super::{DeserializeError, RedisClusterCommand, RedisCommand};
+use crate::{slot::slot, Error};
+
+use actix::Message;
+use redis_async::resp::RespValue;
+use RespValue::*;
+
+/// SADD command.
+#[derive(Debug)]
+pub struct SAdd {
+    pub key: Vec<u8>,
+    pub members: Vec<Vec<u8>>,
+}
+
+/// SADD command, single member.
+pub fn sadd<K: Into<Vec<u8>>, M: Into<Vec<u8>>>(key: K, member: M) -> SAdd {
+    SAdd {
+        key: key.into(),
+        members: vec![member.into()],
+    }
+}
+
+/// SADD command, multiple members.
+pub fn sadd_multiple<K, M, I>(key: K, iter: I) -> SAdd
+where
+    K: Into<Vec<u8>>,
+    M: Into<Vec<u8>>,
+    I: Iterator<Item = M>,
+{
+    SAdd {
+        key: key.into(),
+        members: iter.map(Into::into).collect(),
+    }
+}
+
+impl RedisCommand for SAdd {
+    /// Integer reply: the number of members that were added to the set
+    type Output = i64;
+
+    fn serialize(self) -> RespValue {
+        let mut data = Vec::with_capacity(2 + self.members.len());
+        data.push("SADD".into());
+        data.push(self.key.into());
+        data.extend(self.members.into_iter().map(Into::into));
+
+        RespValue::Array(data)
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            Integer(num) => Ok(num),
+            resp => Err(DeserializeError::new("invalid response to SADD", resp)),
+        }
+    }
+}
+
+impl RedisClusterCommand for SAdd {
+    fn slot(&self) -> Result<u16, Vec<u16>> {
+        Ok(slot(&self.key))
+    }
+}
+
+impl Message for SAdd {
+    type Result = Result<<SAdd as RedisCommand>::Output, Error>;
+}
@@ -0,0 +1,103 @@
+use super::{DeserializeError, RedisCommand};
+use crate::Error;
+
+use actix::Message;
+use redis_async::resp::RespValue;
+use RespValue::*;
+
+/// SCAN command.
+///
+/// The command has no key, so it is sent to a node of Redis Cluster by wrapping it
+/// in [`DirectedTo`](struct.DirectedTo.html); each node only scans its own keys.
+#[derive(Debug)]
+pub struct Scan {
+    pub cursor: u64,
+    pub pattern: Option<Vec<u8>>,
+    pub count: Option<usize>,
+}
+
+impl Scan {
+    /// Only return the keys matching the glob-style `pattern`.
+    pub fn pattern<P: Into<Vec<u8>>>(self, pattern: P) -> Self {
+        Scan {
+            pattern: Some(pattern.into()),
+            ..self
+        }
+    }
+
+    /// Set the number of keys examined per call, which defaults to 10.
+    pub fn count(self, count: usize) -> Self {
+        Scan {
+            count: Some(count),
+            ..self
+        }
+    }
+}
+
+/// SCAN command, iterating over the keys from `cursor`. The iteration starts and
+/// ends with a cursor of `0`.
+///
+/// To filter the keys, please see [`Scan`](struct.Scan.html#methods).
+pub fn scan(cursor: u64) -> Scan {
+    Scan {
+        cursor,
+        pattern: None,
+        count: None,
+    }
+}
+
+impl RedisCommand for Scan {
+    /// The cursor of the next call, and a batch of keys which may contain duplicates
+    type Output = (u64, Vec<Vec<u8>>);
+
+    fn serialize(self) -> RespValue {
+        let mut data = vec!["SCAN".into(), self.cursor.to_string().into()];
+
+        if let Some(pattern) = self.pattern {
+            data.push("MATCH".into());
+            data.push(pattern.into());
+        }
+
+        if let Some(count) = self.count {
+            data.push("COUNT".into());
+            data.push(count.to_string().into());
+        }
+
+        RespValue::Array(data)
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        let (cursor, keys) = match resp {
+            Array(values) if values.len() == 2 => {
+                let mut values = values.into_iter();
+                (values.next().unwrap(), values.next().unwrap())
+            }
+            resp => return Err(DeserializeError::new("invalid response to SCAN", resp)),
+        };
+
+        let cursor = match cursor {
+            BulkString(cursor) => std::str::from_utf8(&cursor)
+                .ok()
+                .and_then(|cursor| cursor.parse().ok())
+                .ok_or_else(|| DeserializeError::message("SCAN: invalid cursor"))?,
+            resp => return Err(DeserializeError::new("SCAN: invalid cursor", resp)),
+        };
+
+        let keys = match keys {
+            Array(keys) => keys
+                .into_iter()
+                .map(|key| match key {
+                    BulkString(key) => Ok(key),
+                    resp => Err(DeserializeError::new("SCAN: not a bulk string", resp)),
+                })
+                .collect::<Result<_, _>>()?,
+            resp => return Err(DeserializeError::new("SCAN: not an array", resp)),
+        };
+
+        Ok((cursor, keys))
+    }
+}
+
+impl Message for Scan {
+    type Result = Result<<Scan as RedisCommand>::Output, Error>;
+}
@@ -0,0 +1,51 @@
+use super::{DeserializeError, RedisClusterCommand, RedisCommand};
+use crate::{slot::slot, Error};
+
+use actix::Message;
+use redis_async::{resp::RespValue, resp_array};
+use RespValue::*;
+
+/// SMEMBERS command.
+#[derive(Debug)]
+pub struct SMembers {
+    pub key: Vec<u8>,
+}
+
+/// SMEMBERS command, returning all the members of the set.
+pub fn smembers<K: Into<Vec<u8>>>(key: K) -> SMembers {
+    SMembers { key: key.into() }
+}
+
+impl RedisCommand for SMembers {
+    /// Members of the set, empty if the key does not exist
+    type Output = Vec<Vec<u8>>;
+
+    fn serialize(self) -> RespValue {
+        resp_array!["SMEMBERS", self.key]
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            Array(members) => members
+                .into_iter()
+                .map(|member| match member {
+                    BulkString(member) => Ok(member),
+                    resp => {
+                        Err(DeserializeError::new("SMEMBERS: not a bulk string", resp))
+                    }
+                })
+                .collect(),
+            resp => Err(DeserializeError::new("invalid response to SMEMBERS", resp)),
+        }
+    }
+}
+
+impl RedisClusterCommand for SMembers {
+    fn slot(&self) -> Result<u16, Vec<u16>> {
+        Ok(slot(&self.key))
+    }
+}
+
+impl Message for SMembers {
+    type Result = Result<<SMembers as RedisCommand>::Output, Error>;
+}
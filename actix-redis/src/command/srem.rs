@@ -0,0 +1,65 @@
+use super::{DeserializeError, RedisClusterCommand, RedisCommand};
+use crate::{slot::slot, Error};
+
+use actix::Message;
+use redis_async::resp::RespValue;
+use RespValue::*;
+
+/// SREM command.
+#[derive(Debug)]
+pub struct SRem {
+    pub key: Vec<u8>,
+    pub members: Vec<Vec<u8>>,
+}
+
+/// SREM command, single member.
+pub fn srem<K: Into<Vec<u8>>, M: Into<Vec<u8>>>(key: K, member: M) -> SRem {
+    SRem {
+        key: key.into(),
+        members: vec![member.into()],
+    }
+}
+
+/// SREM command, multiple members.
+pub fn srem_multiple<K, M, I>(key: K, iter: I) -> SRem
+where
+    K: Into<Vec<u8>>,
+    M: Into<Vec<u8>>,
+    I: Iterator<Item = M>,
+{
+    SRem {
+        key: key.into(),
+        members: iter.map(Into::into).collect(),
+    }
+}
+
+impl RedisCommand for SRem {
+    /// Integer reply: the number of members that were removed from the set
+    type Output = i64;
+
+    fn serialize(self) -> RespValue {
+        let mut data = Vec::with_capacity(2 + self.members.len());
+        data.push("SREM".into());
+        data.push(self.key.into());
+        data.extend(self.members.into_iter().map(Into::into));
+
+        RespValue::Array(data)
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            Integer(num) => Ok(num),
+            resp => Err(DeserializeError::new("invalid response to SREM", resp)),
+        }
+    }
+}
+
+impl RedisClusterCommand for SRem {
+    fn slot(&self) -> Result<u16, Vec<u16>> {
+        Ok(slot(&self.key))
+    }
+}
+
+impl Message for SRem {
+    type Result = Result<<SRem as RedisCommand>::Output, Error>;
+}
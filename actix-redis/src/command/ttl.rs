@@ -0,0 +1,45 @@
+use super::{DeserializeError, RedisClusterCommand, RedisCommand};
+use crate::{slot::slot, Error};
+
+use actix::Message;
+use redis_async::{resp::RespValue, resp_array};
+use RespValue::*;
+
+/// TTL command.
+#[derive(Debug)]
+pub struct Ttl {
+    pub key: Vec<u8>,
+}
+
+/// TTL command, returning the remaining time to live of the key in seconds.
+pub fn ttl<K: Into<Vec<u8>>>(key: K) -> Ttl {
+    Ttl { key: key.into() }
+}
+
+impl RedisCommand for Ttl {
+    /// - the remaining time to live in seconds
+    /// - `-1` if the key exists but has no associated expire
+    /// - `-2` if the key does not exist
+    type Output = i64;
+
+    fn serialize(self) -> RespValue {
+        resp_array!["TTL", self.key]
+    }
+
+    fn deserialize(resp: RespValue) -> Result<Self::Output, DeserializeError> {
+        match resp {
+            Integer(num) => Ok(num),
+            resp => Err(DeserializeError::new("invalid response to TTL", resp)),
+        }
+    }
+}
+
+impl RedisClusterCommand for Ttl {
+    fn slot(&self) -> Result<u16, Vec<u16>> {
+        Ok(slot(&self.key))
+    }
+}
+
+impl Message for Ttl {
+    type Result = Result<<Ttl as RedisCommand>::Output, Error>;
+}
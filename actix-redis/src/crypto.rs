@@ -0,0 +1,222 @@
+//! Encryption of the session state at rest.
+
+use std::borrow::Cow;
+use std::convert::TryInto;
+
+use actix_web::{error, Error};
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use hmac::{Hmac, Mac, NewMac};
+use log::warn;
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+
+/// Tag of an encrypted state, followed by the id of the key and the nonce.
+const ENCRYPTED: u8 = 3;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = 1 + 4 + NONCE_LEN;
+
+/// Keys encrypting the sessions stored by [`RedisSession`](struct.RedisSession.html)
+/// with AES-256-GCM, see
+/// [`RedisSession::encryption`](struct.RedisSession.html#method.encryption).
+///
+/// Sessions are encrypted with the current key, and decrypted with the key they
+/// were encrypted with, which is identified by its id. To rotate keys, create the
+/// key ring with the new key and add the current one as a previous key; sessions
+/// are encrypted with the new key on their next change.
+///
+/// Sessions stored before enabling encryption are rejected, unless
+/// [`allow_plaintext`](#method.allow_plaintext) is set while migrating.
+#[derive(Clone)]
+pub struct KeyRing {
+    current: u32,
+    keys: Vec<(u32, Aes256Gcm)>,
+    allow_plaintext: bool,
+}
+
+impl KeyRing {
+    /// Create a key ring encrypting with `key`, identified by `id`.
+    ///
+    /// Panics if `key` is not 32 bytes long.
+    pub fn new(id: u32, key: &[u8]) -> Self {
+        KeyRing {
+            current: id,
+            keys: vec![(id, cipher(key))],
+            allow_plaintext: false,
+        }
+    }
+
+    /// Add a key only used to decrypt the sessions encrypted before rotating keys.
+    ///
+    /// Panics if `key` is not 32 bytes long, or if `id` is already used.
+    pub fn previous_key(mut self, id: u32, key: &[u8]) -> Self {
+        assert!(
+            self.keys.iter().all(|(used, _)| *used != id),
+            "key id {} is already used",
+            id
+        );
+        self.keys.push((id, cipher(key)));
+        self
+    }
+
+    /// Accept the sessions stored in plaintext, before enabling encryption, so that
+    /// they are encrypted on their next change. Default is `false`.
+    ///
+    /// Only enable this while migrating: anyone able to write to Redis can then
+    /// plant a session state which is not authenticated.
+    pub fn allow_plaintext(mut self, allow_plaintext: bool) -> Self {
+        self.allow_plaintext = allow_plaintext;
+        self
+    }
+
+    /// Encrypts `data`, authenticating `aad` along with it.
+    pub(crate) fn encrypt(&self, aad: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut nonce = [0; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let payload = Payload { msg: data, aad };
+        let encrypted = self.keys[0]
+            .1
+            .encrypt(&nonce.into(), payload)
+            .map_err(|_| error::ErrorInternalServerError("cannot encrypt session"))?;
+
+        let mut buf = Vec::with_capacity(HEADER_LEN + encrypted.len());
+        buf.push(ENCRYPTED);
+        buf.extend_from_slice(&self.current.to_be_bytes());
+        buf.extend_from_slice(&nonce);
+        buf.extend_from_slice(&encrypted);
+        Ok(buf)
+    }
+
+    /// Decrypts `data`, which fails if it was encrypted with an unknown key or along
+    /// with another `aad`. Data stored before enabling encryption is returned as is
+    /// if allowed, and rejected otherwise.
+    pub(crate) fn decrypt<'a>(
+        &self,
+        aad: &[u8],
+        data: &'a [u8],
+    ) -> Option<Cow<'a, [u8]>> {
+        if data.first() != Some(&ENCRYPTED) {
+            if self.allow_plaintext {
+                return Some(Cow::Borrowed(data));
+            }
+            warn!("cannot decrypt session: not encrypted");
+            return None;
+        }
+        if data.len() < HEADER_LEN {
+            warn!("cannot decrypt session: truncated data");
+            return None;
+        }
+
+        let mut id = [0; 4];
+        id.copy_from_slice(&data[1..5]);
+        let id = u32::from_be_bytes(id);
+        let cipher = match self.keys.iter().find(|(key_id, _)| *key_id == id) {
+            Some((_, cipher)) => cipher,
+            None => {
+                warn!("cannot decrypt session: unknown key {}", id);
+                return None;
+            }
+        };
+
+        let mut nonce = [0; NONCE_LEN];
+        nonce.copy_from_slice(&data[5..HEADER_LEN]);
+        let payload = Payload {
+            msg: &data[HEADER_LEN..],
+            aad,
+        };
+        match cipher.decrypt(&nonce.into(), payload) {
+            Ok(data) => Some(Cow::Owned(data)),
+            Err(_) => {
+                warn!("cannot decrypt session: authentication failed");
+                None
+            }
+        }
+    }
+}
+
+fn cipher(key: &[u8]) -> Aes256Gcm {
+    let key: [u8; 32] = key
+        .try_into()
+        .expect("encryption key must be 32 bytes long");
+    Aes256Gcm::new(&key.into())
+}
+
+/// Hex encoded HMAC-SHA256 of `data`.
+pub(crate) fn hmac_hex(secret: &[u8], data: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret).expect("HMAC accepts any key");
+    mac.update(data);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encrypt() {
+        let keys = KeyRing::new(1, &[1; 32]);
+        let data = keys.encrypt(b"session:a", b"{\"counter\":\"1\"}").unwrap();
+        assert_eq!(data[0], ENCRYPTED);
+        assert_eq!(&data[1..5], &[0, 0, 0, 1]);
+        assert_ne!(&data[HEADER_LEN..], b"{\"counter\":\"1\"}");
+
+        let decrypted = keys.decrypt(b"session:a", &data).unwrap();
+        assert_eq!(&*decrypted, b"{\"counter\":\"1\"}");
+
+        // bound to the session, and to the key
+        assert!(keys.decrypt(b"session:b", &data).is_none());
+        assert!(KeyRing::new(1, &[2; 32])
+            .decrypt(b"session:a", &data)
+            .is_none());
+        assert!(KeyRing::new(2, &[1; 32])
+            .decrypt(b"session:a", &data)
+            .is_none());
+
+        let mut tampered = data;
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(keys.decrypt(b"session:a", &tampered).is_none());
+        assert!(keys.decrypt(b"session:a", &tampered[..8]).is_none());
+    }
+
+    #[test]
+    fn test_rotation() {
+        let old = KeyRing::new(1, &[1; 32]);
+        let data = old.encrypt(b"session:a", b"state").unwrap();
+
+        let new = KeyRing::new(2, &[2; 32]).previous_key(1, &[1; 32]);
+        assert_eq!(&*new.decrypt(b"session:a", &data).unwrap(), b"state");
+
+        let data = new.encrypt(b"session:a", b"state").unwrap();
+        assert_eq!(&data[1..5], &[0, 0, 0, 2]);
+        assert!(old.decrypt(b"session:a", &data).is_none());
+    }
+
+    #[test]
+    fn test_plaintext() {
+        let keys = KeyRing::new(1, &[1; 32]);
+        assert!(keys.decrypt(b"session:a", b"{}").is_none());
+
+        let keys = keys.allow_plaintext(true);
+        let decrypted = keys.decrypt(b"session:a", b"{}").unwrap();
+        assert!(matches!(decrypted, Cow::Borrowed(b"{}")));
+    }
+
+    #[test]
+    #[should_panic(expected = "already used")]
+    fn test_duplicate_id() {
+        KeyRing::new(1, &[1; 32]).previous_key(1, &[2; 32]);
+    }
+
+    #[test]
+    fn test_hmac_hex() {
+        let mac = hmac_hex(b"secret", b"session");
+        assert_eq!(mac.len(), 64);
+        assert_eq!(mac, hmac_hex(b"secret", b"session"));
+        assert_ne!(mac, hmac_hex(b"other", b"session"));
+    }
+}
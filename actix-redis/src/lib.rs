@@ -11,20 +11,43 @@
 
 mod cluster;
 pub mod command;
+pub mod lock;
 mod redis;
+pub mod reshard;
 pub mod slot;
 
 pub use cluster::RedisClusterActor;
-pub use redis::{Command, RedisActor};
+pub use redis::{Command, GracefulShutdown, RedisActor};
 
 use derive_more::{Display, From};
 
+#[cfg(feature = "web")]
+mod backend;
+#[cfg(feature = "web")]
+pub mod cache;
+#[cfg(feature = "web")]
+mod codec;
+#[cfg(feature = "encryption")]
+mod crypto;
+#[cfg(feature = "web")]
+pub mod ratelimit;
 #[cfg(feature = "web")]
 mod session;
 #[cfg(feature = "web")]
+pub use actix_session::SessionStoreDegraded;
+#[cfg(feature = "web")]
 pub use actix_web::cookie::SameSite;
 #[cfg(feature = "web")]
-pub use session::RedisSession;
+pub use backend::SessionBackend;
+#[cfg(feature = "web")]
+pub use codec::Codec;
+#[cfg(feature = "encryption")]
+pub use crypto::KeyRing;
+#[cfg(feature = "web")]
+pub use session::{
+    ConflictStrategy, RedisSession, SessionAdmin, SessionConflict, SessionInfo,
+    SessionPrincipal,
+};
 
 /// General purpose actix redis error
 #[derive(Debug, Display, From)]
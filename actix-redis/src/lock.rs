@@ -0,0 +1,486 @@
+//! Distributed locks.
+//!
+//! [`Lock`](struct.Lock.html) provides mutual exclusion across processes sharing a
+//! Redis server (or Redis Cluster). The lock is acquired with `SET key token NX PX ttl`
+//! and released or extended with Lua scripts which check the token first, so that a
+//! client never removes a lock held by somebody else.
+//!
+//! [`Redlock`](struct.Redlock.html) implements the
+//! [Redlock algorithm](https://redis.io/topics/distlock) over several independent
+//! Redis servers.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use actix_redis::{lock::Lock, RedisActor};
+//! use std::time::Duration;
+//!
+//! # async fn run() -> Result<(), actix_redis::Error> {
+//! let addr = RedisActor::start("127.0.0.1:6379");
+//! let lock = Lock::new(addr, "lock:report").ttl(Duration::from_secs(10));
+//!
+//! if let Some(guard) = lock.acquire().await? {
+//!     // ... critical section ...
+//!     guard.release().await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::convert::TryFrom;
+use std::iter;
+use std::time::{Duration, Instant};
+
+use actix::dev::ToEnvelope;
+use actix::prelude::*;
+use actix_rt::time::delay_for;
+use futures_util::future::join_all;
+use log::warn;
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use redis_async::resp::RespValue;
+
+use crate::command::{eval, set, Eval, Set};
+use crate::{Error, RedisActor};
+
+const RELEASE_SCRIPT: &str = r#"
+if redis.call("get", KEYS[1]) == ARGV[1] then
+    return redis.call("del", KEYS[1])
+else
+    return 0
+end
+"#;
+
+const EXTEND_SCRIPT: &str = r#"
+if redis.call("get", KEYS[1]) == ARGV[1] then
+    return redis.call("pexpire", KEYS[1], ARGV[2])
+else
+    return 0
+end
+"#;
+
+fn token() -> Vec<u8> {
+    iter::repeat(())
+        .map(|()| OsRng.sample(Alphanumeric) as u8)
+        .take(20)
+        .collect()
+}
+
+fn succeeded(res: &Result<bool, Error>) -> bool {
+    *res.as_ref().unwrap_or(&false)
+}
+
+fn millis(duration: Duration) -> i64 {
+    i64::try_from(duration.as_millis()).unwrap_or(i64::MAX)
+}
+
+// Random delay in `[delay, 2 * delay)` so that competing clients do not retry in lockstep.
+fn jitter(delay: Duration) -> Duration {
+    let max = millis(delay).max(1) as u64;
+    delay + Duration::from_millis(rand::thread_rng().gen_range(0, max))
+}
+
+async fn try_set<A>(
+    addr: &Addr<A>,
+    key: &[u8],
+    token: &[u8],
+    ttl: Duration,
+) -> Result<bool, Error>
+where
+    A: Actor + Handler<Set>,
+    A::Context: ToEnvelope<A, Set>,
+{
+    match addr.send(set(key, token).nx().px(millis(ttl))).await {
+        Ok(res) => res,
+        Err(_) => Err(Error::Disconnected),
+    }
+}
+
+async fn run_script<A>(addr: &Addr<A>, command: Eval) -> Result<bool, Error>
+where
+    A: Actor + Handler<Eval>,
+    A::Context: ToEnvelope<A, Eval>,
+{
+    match addr.send(command).await {
+        Ok(Ok(RespValue::Integer(n))) => Ok(n > 0),
+        Ok(Ok(_)) => Ok(false),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(Error::Disconnected),
+    }
+}
+
+async fn unlock<A>(addr: &Addr<A>, key: &[u8], token: &[u8]) -> Result<bool, Error>
+where
+    A: Actor + Handler<Eval>,
+    A::Context: ToEnvelope<A, Eval>,
+{
+    run_script(addr, eval(RELEASE_SCRIPT).key(key).arg(token)).await
+}
+
+async fn extend<A>(
+    addr: &Addr<A>,
+    key: &[u8],
+    token: &[u8],
+    ttl: Duration,
+) -> Result<bool, Error>
+where
+    A: Actor + Handler<Eval>,
+    A::Context: ToEnvelope<A, Eval>,
+{
+    let ttl = millis(ttl).to_string();
+    run_script(addr, eval(EXTEND_SCRIPT).key(key).arg(token).arg(ttl)).await
+}
+
+// End of the validity of a lease of `ttl` requested at `start`, allowing for the
+// clock drift between the servers.
+fn deadline(ttl: Duration, start: Instant) -> Instant {
+    let drift = ttl / 100 + Duration::from_millis(2);
+    start + ttl.checked_sub(drift).unwrap_or_default()
+}
+
+// Releases the lock in the background when a guard is dropped without `release`.
+// Outside of an actix system the lock is left to expire.
+fn unlock_on_drop<A>(addrs: Vec<Addr<A>>, key: Vec<u8>, token: Vec<u8>)
+where
+    A: Actor + Handler<Eval>,
+    A::Context: ToEnvelope<A, Eval>,
+{
+    if !System::is_set() {
+        warn!(
+            "lock {:?} dropped outside of an actix system, it expires after its ttl",
+            String::from_utf8_lossy(&key)
+        );
+        return;
+    }
+
+    actix_rt::spawn(async move {
+        let res = join_all(addrs.iter().map(|addr| unlock(addr, &key, &token))).await;
+        if !res.iter().any(succeeded) {
+            warn!(
+                "lock {:?} was lost before release: {:?}",
+                String::from_utf8_lossy(&key),
+                res
+            );
+        }
+    });
+}
+
+/// A lock on a single Redis server or Redis Cluster.
+///
+/// `A` is either [`RedisActor`](../struct.RedisActor.html) or
+/// [`RedisClusterActor`](../struct.RedisClusterActor.html).
+pub struct Lock<A: Actor = RedisActor> {
+    addr: Addr<A>,
+    key: Vec<u8>,
+    ttl: Duration,
+    retry_count: u32,
+    retry_delay: Duration,
+}
+
+impl<A> Lock<A>
+where
+    A: Actor + Handler<Set> + Handler<Eval>,
+    A::Context: ToEnvelope<A, Set> + ToEnvelope<A, Eval>,
+{
+    /// Create a lock stored at `key`.
+    ///
+    /// The lock expires after 30 seconds and is retried 3 times every 200 ms (plus
+    /// jitter) by default.
+    pub fn new<K: Into<Vec<u8>>>(addr: Addr<A>, key: K) -> Self {
+        Lock {
+            addr,
+            key: key.into(),
+            ttl: Duration::from_secs(30),
+            retry_count: 3,
+            retry_delay: Duration::from_millis(200),
+        }
+    }
+
+    /// Set the time after which the lock expires unless extended.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set how many times `acquire` retries when the lock is held by somebody else.
+    pub fn retry_count(mut self, retry_count: u32) -> Self {
+        self.retry_count = retry_count;
+        self
+    }
+
+    /// Set the base delay between retries. A random jitter of up to the same
+    /// duration is added to each delay.
+    pub fn retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Try to acquire the lock once.
+    ///
+    /// Returns `None` if the lock is held by somebody else.
+    pub async fn try_acquire(&self) -> Result<Option<LockGuard<A>>, Error> {
+        let token = token();
+        if try_set(&self.addr, &self.key, &token, self.ttl).await? {
+            Ok(Some(LockGuard {
+                addr: self.addr.clone(),
+                key: self.key.clone(),
+                token,
+                released: false,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Acquire the lock, retrying while it is held by somebody else.
+    ///
+    /// Returns `None` if the lock could not be acquired within the retries.
+    pub async fn acquire(&self) -> Result<Option<LockGuard<A>>, Error> {
+        for attempt in 0..=self.retry_count {
+            if attempt > 0 {
+                delay_for(jitter(self.retry_delay)).await;
+            }
+            if let Some(guard) = self.try_acquire().await? {
+                return Ok(Some(guard));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A held lock.
+///
+/// The lock is released when the guard is dropped within an actix system, otherwise
+/// it expires; a warning is logged if the lease expired in the meantime. Use [`release`](#method.release) to observe the result.
+pub struct LockGuard<A>
+where
+    A: Actor + Handler<Eval>,
+    A::Context: ToEnvelope<A, Eval>,
+{
+    addr: Addr<A>,
+    key: Vec<u8>,
+    token: Vec<u8>,
+    released: bool,
+}
+
+impl<A> LockGuard<A>
+where
+    A: Actor + Handler<Eval>,
+    A::Context: ToEnvelope<A, Eval>,
+{
+    /// The random token identifying this holder of the lock.
+    pub fn token(&self) -> &[u8] {
+        &self.token
+    }
+
+    /// Extend the lease to `ttl` from now.
+    ///
+    /// Returns `false` if the lease has already been lost.
+    pub async fn extend(&self, ttl: Duration) -> Result<bool, Error> {
+        extend(&self.addr, &self.key, &self.token, ttl).await
+    }
+
+    /// Release the lock.
+    ///
+    /// Returns `false` if the lease had already been lost.
+    pub async fn release(mut self) -> Result<bool, Error> {
+        self.released = true;
+        unlock(&self.addr, &self.key, &self.token).await
+    }
+}
+
+impl<A> Drop for LockGuard<A>
+where
+    A: Actor + Handler<Eval>,
+    A::Context: ToEnvelope<A, Eval>,
+{
+    fn drop(&mut self) {
+        if !self.released {
+            unlock_on_drop(
+                vec![self.addr.clone()],
+                self.key.clone(),
+                self.token.clone(),
+            );
+        }
+    }
+}
+
+/// A lock using the Redlock algorithm over independent Redis servers.
+///
+/// The lock is held when a majority of the servers accepted it, and only as long as
+/// the remaining validity time (see [`RedlockGuard::validity`](struct.RedlockGuard.html#method.validity)).
+pub struct Redlock<A: Actor = RedisActor> {
+    addrs: Vec<Addr<A>>,
+    key: Vec<u8>,
+    ttl: Duration,
+    retry_count: u32,
+    retry_delay: Duration,
+}
+
+impl<A> Redlock<A>
+where
+    A: Actor + Handler<Set> + Handler<Eval>,
+    A::Context: ToEnvelope<A, Set> + ToEnvelope<A, Eval>,
+{
+    /// Create a lock stored at `key` on each of the servers.
+    ///
+    /// Defaults are the same as for [`Lock`](struct.Lock.html).
+    pub fn new<K: Into<Vec<u8>>>(addrs: Vec<Addr<A>>, key: K) -> Self {
+        Redlock {
+            addrs,
+            key: key.into(),
+            ttl: Duration::from_secs(30),
+            retry_count: 3,
+            retry_delay: Duration::from_millis(200),
+        }
+    }
+
+    /// Set the time after which the lock expires unless extended.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set how many times `acquire` retries when the lock is held by somebody else.
+    pub fn retry_count(mut self, retry_count: u32) -> Self {
+        self.retry_count = retry_count;
+        self
+    }
+
+    /// Set the base delay between retries. A random jitter of up to the same
+    /// duration is added to each delay.
+    pub fn retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Try to acquire the lock on a majority of the servers once.
+    ///
+    /// Returns `None` if the majority could not be reached in time; the partially
+    /// acquired locks are released.
+    pub async fn try_acquire(&self) -> Result<Option<RedlockGuard<A>>, Error> {
+        let token = token();
+        let start = Instant::now();
+
+        let res = join_all(
+            self.addrs
+                .iter()
+                .map(|addr| try_set(addr, &self.key, &token, self.ttl)),
+        )
+        .await;
+        let held = res.iter().filter(|res| succeeded(res)).count();
+
+        let deadline = deadline(self.ttl, start);
+
+        if held > self.addrs.len() / 2 && deadline > Instant::now() {
+            Ok(Some(RedlockGuard {
+                addrs: self.addrs.clone(),
+                key: self.key.clone(),
+                token,
+                deadline,
+                released: false,
+            }))
+        } else {
+            join_all(
+                self.addrs
+                    .iter()
+                    .map(|addr| unlock(addr, &self.key, &token)),
+            )
+            .await;
+            Ok(None)
+        }
+    }
+
+    /// Acquire the lock, retrying while a majority cannot be reached.
+    ///
+    /// Returns `None` if the lock could not be acquired within the retries.
+    pub async fn acquire(&self) -> Result<Option<RedlockGuard<A>>, Error> {
+        for attempt in 0..=self.retry_count {
+            if attempt > 0 {
+                delay_for(jitter(self.retry_delay)).await;
+            }
+            if let Some(guard) = self.try_acquire().await? {
+                return Ok(Some(guard));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A held [`Redlock`](struct.Redlock.html).
+///
+/// The lock is released on all servers when the guard is dropped.
+pub struct RedlockGuard<A>
+where
+    A: Actor + Handler<Eval>,
+    A::Context: ToEnvelope<A, Eval>,
+{
+    addrs: Vec<Addr<A>>,
+    key: Vec<u8>,
+    token: Vec<u8>,
+    deadline: Instant,
+    released: bool,
+}
+
+impl<A> RedlockGuard<A>
+where
+    A: Actor + Handler<Eval>,
+    A::Context: ToEnvelope<A, Eval>,
+{
+    /// The random token identifying this holder of the lock.
+    pub fn token(&self) -> &[u8] {
+        &self.token
+    }
+
+    /// The remaining time during which the lock is guaranteed to be held, zero once
+    /// the lease acquired or last extended has expired.
+    pub fn validity(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Extend the lease to `ttl` from now, updating the [`validity`](#method.validity).
+    ///
+    /// Returns `false` unless a majority of the servers still held the lease, in
+    /// which case the validity is zero.
+    pub async fn extend(&mut self, ttl: Duration) -> Result<bool, Error> {
+        let start = Instant::now();
+        let res = join_all(
+            self.addrs
+                .iter()
+                .map(|addr| extend(addr, &self.key, &self.token, ttl)),
+        )
+        .await;
+        let extended = res.iter().filter(|res| succeeded(res)).count();
+        self.deadline = if extended > self.addrs.len() / 2 {
+            deadline(ttl, start)
+        } else {
+            Instant::now()
+        };
+        Ok(self.validity() > Duration::from_secs(0))
+    }
+
+    /// Release the lock on all servers.
+    ///
+    /// Returns `false` if no server held the lease anymore.
+    pub async fn release(mut self) -> Result<bool, Error> {
+        self.released = true;
+        let res = join_all(
+            self.addrs
+                .iter()
+                .map(|addr| unlock(addr, &self.key, &self.token)),
+        )
+        .await;
+        Ok(res.iter().any(succeeded))
+    }
+}
+
+impl<A> Drop for RedlockGuard<A>
+where
+    A: Actor + Handler<Eval>,
+    A::Context: ToEnvelope<A, Eval>,
+{
+    fn drop(&mut self) {
+        if !self.released {
+            unlock_on_drop(self.addrs.clone(), self.key.clone(), self.token.clone());
+        }
+    }
+}
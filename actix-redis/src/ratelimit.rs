@@ -0,0 +1,506 @@
+//! Rate limiting middleware.
+//!
+//! [`RateLimiter`](struct.RateLimiter.html) counts the requests of each client in
+//! Redis, so that the limit is shared by all instances of the application. The
+//! counters are updated atomically by Lua scripts touching a single key, which makes
+//! the middleware correct on Redis Cluster as well.
+//!
+//! Keys are named after the algorithm, e.g. `ratelimit:gcra:<client>`, so that
+//! switching algorithms does not read counters of the other algorithm.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix::dev::ToEnvelope;
+use actix::prelude::*;
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{error, Error, HttpResponse, ResponseError};
+use derive_more::Display;
+use futures_util::future::{ok, Future, Ready};
+use log::warn;
+use redis_async::resp::RespValue;
+
+use crate::command::{eval, Eval};
+use crate::redis::RedisActor;
+
+/// Fixed window: at most `limit` requests per window of `period`, starting with the
+/// first request of the window.
+const FIXED_WINDOW_SCRIPT: &str = r#"
+local limit = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local count = redis.call("incr", KEYS[1])
+local ttl = redis.call("pttl", KEYS[1])
+if ttl < 0 then
+    redis.call("pexpire", KEYS[1], period)
+    ttl = period
+end
+if count > limit then
+    return {0, 0, ttl, ttl}
+end
+return {1, limit - count, ttl, 0}
+"#;
+
+/// Generic cell rate algorithm: requests are spaced `period / limit` apart, with a
+/// burst of up to `limit` requests. The key stores the theoretical arrival time.
+const GCRA_SCRIPT: &str = r#"
+redis.replicate_commands()
+local limit = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local interval = period / limit
+local time = redis.call("time")
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local tat = tonumber(redis.call("get", KEYS[1])) or now
+if tat < now then
+    tat = now
+end
+local new_tat = tat + interval
+local allow_at = new_tat - period
+if allow_at > now then
+    return {0, 0, math.ceil(tat - now), math.ceil(allow_at - now)}
+end
+redis.call("set", KEYS[1], tostring(new_tat), "PX", math.ceil(new_tat - now))
+local remaining = math.floor((period - (new_tat - now)) / interval)
+return {1, remaining, math.ceil(new_tat - now), 0}
+"#;
+
+/// Rate limiting algorithm.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    /// Count the requests in fixed windows. Cheap, but allows up to twice the limit
+    /// around the boundary of two windows.
+    FixedWindow,
+    /// Generic cell rate algorithm, equivalent to a sliding window: the limit is
+    /// enforced over any period, with bursts of up to `limit` requests.
+    Gcra,
+}
+
+impl Algorithm {
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::FixedWindow => "fixed",
+            Algorithm::Gcra => "gcra",
+        }
+    }
+}
+
+/// Error returned when the limit is exceeded, rendered as `429 Too Many Requests`
+/// with `RateLimit-*` and `Retry-After` headers.
+#[derive(Debug, Display)]
+#[display(fmt = "Too many requests")]
+pub struct RateLimitExceeded {
+    /// Maximum number of requests per period.
+    pub limit: u64,
+    /// Time until the limit is fully or partially reset.
+    pub reset: Duration,
+    /// Time until the next request would be accepted.
+    pub retry_after: Duration,
+}
+
+impl ResponseError for RateLimitExceeded {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut res = HttpResponse::build(self.status_code());
+        res.header(header::RETRY_AFTER, seconds(self.retry_after));
+        let mut res = res.finish();
+        insert_headers(
+            res.headers_mut(),
+            &Decision {
+                allowed: false,
+                limit: self.limit,
+                remaining: 0,
+                reset: self.reset,
+                retry_after: self.retry_after,
+            },
+        );
+        res
+    }
+}
+
+// Rounds up to whole seconds, as required by the headers.
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+fn millis(duration: Duration) -> i64 {
+    i64::try_from(duration.as_millis()).unwrap_or(i64::MAX)
+}
+
+#[derive(Debug)]
+struct Decision {
+    allowed: bool,
+    limit: u64,
+    remaining: u64,
+    reset: Duration,
+    retry_after: Duration,
+}
+
+impl Decision {
+    fn parse(limit: u64, resp: RespValue) -> Result<Self, Error> {
+        let values = match resp {
+            RespValue::Array(values) => values
+                .into_iter()
+                .map(|value| match value {
+                    RespValue::Integer(n) => Ok(u64::try_from(n).unwrap_or(0)),
+                    _ => Err(()),
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .ok(),
+            _ => None,
+        };
+
+        match values.as_deref() {
+            Some(&[allowed, remaining, reset, retry_after]) => Ok(Decision {
+                allowed: allowed == 1,
+                limit,
+                remaining,
+                reset: Duration::from_millis(reset),
+                retry_after: Duration::from_millis(retry_after),
+            }),
+            _ => Err(error::ErrorInternalServerError(
+                "invalid response of rate limiting script",
+            )),
+        }
+    }
+}
+
+fn insert_headers(headers: &mut header::HeaderMap, decision: &Decision) {
+    let values = [
+        ("ratelimit-limit", decision.limit),
+        ("ratelimit-remaining", decision.remaining),
+        ("ratelimit-reset", seconds(decision.reset)),
+    ];
+    for (name, value) in values.iter() {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(*value));
+    }
+}
+
+/// Rate limiting middleware backed by Redis.
+///
+/// Requests are keyed by the peer IP address by default; use
+/// [`key`](#method.key) to limit by identity, API key or any other attribute.
+/// Accepted responses carry `RateLimit-Limit`, `RateLimit-Remaining` and
+/// `RateLimit-Reset` headers. Rejected requests fail with
+/// [`RateLimitExceeded`](struct.RateLimitExceeded.html), and requests fail with
+/// `503 Service Unavailable` while Redis cannot be reached, unless
+/// [`fail_open`](#method.fail_open) is set.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use actix_redis::{ratelimit::{Algorithm, RateLimiter}, RedisActor};
+/// use actix_web::{web, App, HttpResponse};
+/// use std::time::Duration;
+///
+/// let addr = RedisActor::start("127.0.0.1:6379");
+///
+/// let app = App::new()
+///     .wrap(
+///         // 100 requests per minute for each API key
+///         RateLimiter::new(addr, 100, Duration::from_secs(60))
+///             .algorithm(Algorithm::Gcra)
+///             .key(|req| {
+///                 req.headers()
+///                     .get("x-api-key")
+///                     .and_then(|key| key.to_str().ok())
+///                     .map(str::to_owned)
+///             }),
+///     )
+///     .service(web::resource("/").to(|| HttpResponse::Ok()));
+/// ```
+pub struct RateLimiter<A: Actor = RedisActor>(Rc<Inner<A>>);
+
+struct Inner<A: Actor> {
+    addr: Addr<A>,
+    limit: u64,
+    period: Duration,
+    algorithm: Algorithm,
+    prefix: String,
+    #[allow(clippy::type_complexity)]
+    key: Box<dyn Fn(&ServiceRequest) -> Option<String>>,
+    fail_open: bool,
+}
+
+impl<A: Actor> RateLimiter<A> {
+    /// Allow `limit` requests per `period` for each client.
+    ///
+    /// Panics if `limit` is zero.
+    pub fn new(addr: Addr<A>, limit: u64, period: Duration) -> Self {
+        assert!(limit > 0, "rate limit must be positive");
+
+        RateLimiter(Rc::new(Inner {
+            addr,
+            limit,
+            period,
+            algorithm: Algorithm::FixedWindow,
+            prefix: "ratelimit:".to_owned(),
+            key: Box::new(|req: &ServiceRequest| {
+                req.peer_addr().map(|addr| addr.ip().to_string())
+            }),
+            fail_open: false,
+        }))
+    }
+
+    /// Set the rate limiting algorithm. Default is `Algorithm::FixedWindow`.
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().algorithm = algorithm;
+        self
+    }
+
+    /// Set the function extracting the client key from the request.
+    ///
+    /// Requests for which the function returns `None` are not limited.
+    pub fn key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&ServiceRequest) -> Option<String> + 'static,
+    {
+        Rc::get_mut(&mut self.0).unwrap().key = Box::new(key);
+        self
+    }
+
+    /// Set the prefix of the Redis keys, followed by the name of the algorithm.
+    /// Default is `ratelimit:`.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().prefix = prefix.to_owned();
+        self
+    }
+
+    /// Set whether requests are let through, unlimited, when Redis cannot be reached.
+    ///
+    /// When `false`, such requests fail with `503 Service Unavailable`.
+    /// Default is `false`.
+    pub fn fail_open(mut self, fail_open: bool) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().fail_open = fail_open;
+        self
+    }
+}
+
+impl<A> Inner<A>
+where
+    A: Actor + Handler<Eval>,
+    A::Context: ToEnvelope<A, Eval>,
+{
+    async fn check(&self, key: String) -> Result<Decision, Error> {
+        let script = match self.algorithm {
+            Algorithm::FixedWindow => FIXED_WINDOW_SCRIPT,
+            Algorithm::Gcra => GCRA_SCRIPT,
+        };
+        let command = eval(script)
+            .key(format!("{}{}:{}", self.prefix, self.algorithm.name(), key))
+            .arg(self.limit.to_string())
+            .arg(millis(self.period).to_string());
+
+        match self.addr.send(command).await {
+            Ok(Ok(resp)) => Decision::parse(self.limit, resp),
+            Ok(Err(e)) => Err(error::ErrorServiceUnavailable(e)),
+            Err(e) => Err(error::ErrorServiceUnavailable(e)),
+        }
+    }
+}
+
+impl<S, B, A> Transform<S> for RateLimiter<A>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+    A: Actor + Handler<Eval>,
+    A::Context: ToEnvelope<A, Eval>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = S::Error;
+    type InitError = ();
+    type Transform = RateLimiterMiddleware<S, A>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimiterMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            inner: self.0.clone(),
+        })
+    }
+}
+
+/// Rate limiting middleware
+pub struct RateLimiterMiddleware<S: 'static, A: Actor> {
+    service: Rc<RefCell<S>>,
+    inner: Rc<Inner<A>>,
+}
+
+impl<S, B, A> Service for RateLimiterMiddleware<S, A>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+    A: Actor + Handler<Eval>,
+    A::Context: ToEnvelope<A, Eval>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let srv = self.service.clone();
+        let inner = self.inner.clone();
+
+        Box::pin(async move {
+            let key = match (inner.key)(&req) {
+                Some(key) => key,
+                None => {
+                    let fut = srv.borrow_mut().call(req);
+                    return fut.await;
+                }
+            };
+
+            let decision = match inner.check(key).await {
+                Ok(decision) => decision,
+                Err(err) => {
+                    // the details of the Redis error are only logged
+                    warn!("rate limiting is unavailable: {}", err);
+                    if !inner.fail_open {
+                        let err = error::ErrorServiceUnavailable("Service Unavailable");
+                        return Ok(req.error_response(err));
+                    }
+                    let fut = srv.borrow_mut().call(req);
+                    return fut.await;
+                }
+            };
+
+            if !decision.allowed {
+                return Ok(req.error_response(RateLimitExceeded {
+                    limit: decision.limit,
+                    reset: decision.reset,
+                    retry_after: decision.retry_after,
+                }));
+            }
+
+            let fut = srv.borrow_mut().call(req);
+            let mut res = fut.await?;
+            insert_headers(res.headers_mut(), &decision);
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    fn header(res: &ServiceResponse, name: &str) -> String {
+        res.headers()
+            .get(name)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    async fn test_algorithm(algorithm: Algorithm, key: String) {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let mut app = test::init_service(
+            App::new()
+                .wrap(
+                    RateLimiter::new(addr, 2, Duration::from_secs(60))
+                        .algorithm(algorithm)
+                        .key(move |_| Some(key.clone())),
+                )
+                .service(web::resource("/").to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res =
+            test::call_service(&mut app, test::TestRequest::get().to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header(&res, "ratelimit-limit"), "2");
+        assert_eq!(header(&res, "ratelimit-remaining"), "1");
+
+        let res =
+            test::call_service(&mut app, test::TestRequest::get().to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header(&res, "ratelimit-remaining"), "0");
+
+        let res =
+            test::call_service(&mut app, test::TestRequest::get().to_request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&res, "ratelimit-remaining"), "0");
+        assert!(header(&res, "retry-after").parse::<u64>().unwrap() > 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_fixed_window() {
+        let key = format!("test-fixed-window-{}", rand::random::<u32>());
+        test_algorithm(Algorithm::FixedWindow, key).await;
+    }
+
+    #[actix_rt::test]
+    async fn test_gcra() {
+        let key = format!("test-gcra-{}", rand::random::<u32>());
+        test_algorithm(Algorithm::Gcra, key).await;
+    }
+
+    #[actix_rt::test]
+    async fn test_switch_algorithm() {
+        // the counters of each algorithm are kept apart
+        let key = format!("test-switch-{}", rand::random::<u32>());
+        test_algorithm(Algorithm::FixedWindow, key.clone()).await;
+        test_algorithm(Algorithm::Gcra, key).await;
+    }
+
+    #[actix_rt::test]
+    async fn test_fail_open() {
+        let addr = RedisActor::start("localhost:54000");
+        let mut app = test::init_service(
+            App::new()
+                .wrap(
+                    RateLimiter::new(addr, 2, Duration::from_secs(60))
+                        .key(|_| Some("test-fail-open".to_owned()))
+                        .fail_open(true),
+                )
+                .service(web::resource("/").to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res =
+            test::call_service(&mut app, test::TestRequest::get().to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_fail_closed() {
+        let addr = RedisActor::start("localhost:54000");
+        let mut app = test::init_service(
+            App::new()
+                .wrap(
+                    RateLimiter::new(addr, 2, Duration::from_secs(60))
+                        .key(|_| Some("test-fail-closed".to_owned()))
+                        .fail_open(false),
+                )
+                .service(web::resource("/").to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res =
+            test::call_service(&mut app, test::TestRequest::get().to_request()).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = test::read_body(res).await;
+        assert_eq!(body, "Service Unavailable".as_bytes());
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::VecDeque;
 use std::io;
+use std::time::Duration;
 
 use actix::actors::resolver::{Connect, Resolver};
 use actix::prelude::*;
@@ -25,12 +26,32 @@ impl Message for Command {
     type Result = Result<RespValue, Error>;
 }
 
+/// Gracefully closes the connection to Redis.
+///
+/// This is a control message for the actor, not the Redis `SHUTDOWN` command.
+/// Once received, new commands are rejected with `Error::NotConnected`, replies
+/// to the commands already sent are awaited for up to `timeout`, and then the
+/// connection is flushed and closed. The actor does not reconnect afterwards.
+///
+/// Commands still waiting for a reply when the timeout elapses receive
+/// `Error::Disconnected`.
+#[derive(Debug)]
+pub struct GracefulShutdown {
+    pub timeout: Duration,
+}
+
+impl Message for GracefulShutdown {
+    type Result = ();
+}
+
 /// Redis comminucation actor
 pub struct RedisActor {
     addr: String,
     backoff: ExponentialBackoff,
     cell: Option<actix::io::FramedWrite<RespValue, WriteHalf<TcpStream>, RespCodec>>,
     queue: VecDeque<oneshot::Sender<Result<RespValue, Error>>>,
+    closing: bool,
+    closed: Vec<oneshot::Sender<()>>,
 }
 
 impl RedisActor {
@@ -46,14 +67,30 @@ impl RedisActor {
             cell: None,
             backoff,
             queue: VecDeque::new(),
+            closing: false,
+            closed: Vec::new(),
         })
     }
+
+    // Closes the write side of the connection, or stops the actor right away
+    // if there is nothing to flush.
+    fn close(&mut self, ctx: &mut Context<Self>) {
+        match self.cell {
+            Some(ref mut cell) if !cell.closed() => cell.close(),
+            _ => ctx.stop(),
+        }
+    }
 }
 
 impl Actor for RedisActor {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Context<Self>) {
+        // do not reconnect after a graceful shutdown
+        if self.closing {
+            return;
+        }
+
         Resolver::from_registry()
             .send(Connect::host(self.addr.as_str()))
             .into_actor(self)
@@ -101,6 +138,9 @@ impl Supervised for RedisActor {
         for tx in self.queue.drain(..) {
             let _ = tx.send(Err(Error::Disconnected));
         }
+        for tx in self.closed.drain(..) {
+            let _ = tx.send(());
+        }
     }
 }
 
@@ -109,6 +149,13 @@ impl actix::io::WriteHandler<io::Error> for RedisActor {
         warn!("Redis connection dropped: {} error: {}", self.addr, err);
         Running::Stop
     }
+
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        if self.closing {
+            info!("Closed connection to redis server: {}", self.addr);
+        }
+        ctx.stop()
+    }
 }
 
 impl StreamHandler<Result<RespValue, RespError>> for RedisActor {
@@ -124,8 +171,45 @@ impl StreamHandler<Result<RespValue, RespError>> for RedisActor {
                 if let Some(tx) = self.queue.pop_front() {
                     let _ = tx.send(Ok(val));
                 }
+                if self.closing && self.queue.is_empty() {
+                    self.close(ctx);
+                }
+            }
+        }
+    }
+}
+
+impl Handler<GracefulShutdown> for RedisActor {
+    type Result = ResponseFuture<()>;
+
+    fn handle(
+        &mut self,
+        msg: GracefulShutdown,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        // already shut down
+        if self.closing && self.cell.is_none() {
+            return Box::pin(async {});
+        }
+
+        let (tx, rx) = oneshot::channel();
+        self.closed.push(tx);
+
+        if !self.closing {
+            self.closing = true;
+            if self.queue.is_empty() {
+                self.close(ctx);
+            } else {
+                info!(
+                    "Waiting for {} replies from redis server: {}",
+                    self.queue.len(),
+                    self.addr
+                );
+                ctx.run_later(msg.timeout, |act, ctx| act.close(ctx));
             }
         }
+
+        Box::pin(rx.map(|_| ()))
     }
 }
 
@@ -134,7 +218,9 @@ impl Handler<Command> for RedisActor {
 
     fn handle(&mut self, msg: Command, _: &mut Self::Context) -> Self::Result {
         let (tx, rx) = oneshot::channel();
-        if let Some(ref mut cell) = self.cell {
+        if self.closing {
+            let _ = tx.send(Err(Error::NotConnected));
+        } else if let Some(ref mut cell) = self.cell {
             self.queue.push_back(tx);
             cell.write(msg.0);
         } else {
@@ -157,7 +243,9 @@ where
 
     fn handle(&mut self, msg: T, _: &mut Self::Context) -> Self::Result {
         let (tx, rx) = oneshot::channel();
-        if let Some(ref mut cell) = self.cell {
+        if self.closing {
+            let _ = tx.send(Err(Error::NotConnected));
+        } else if let Some(ref mut cell) = self.cell {
             self.queue.push_back(tx);
             let msg = msg.serialize();
             cell.write(msg);
@@ -0,0 +1,242 @@
+//! Moving a hash slot between the nodes of Redis Cluster.
+//!
+//! [`reshard_slot`](fn.reshard_slot.html) runs the sequence described in the
+//! [CLUSTER SETSLOT](https://redis.io/commands/cluster-setslot) documentation:
+//!
+//! 1. `CLUSTER SETSLOT <slot> IMPORTING <source>` on the destination node;
+//! 2. `CLUSTER SETSLOT <slot> MIGRATING <destination>` on the source node;
+//! 3. `CLUSTER GETKEYSINSLOT` and `MIGRATE` in batches until the slot is empty;
+//! 4. `CLUSTER SETSLOT <slot> NODE <destination>` on both nodes.
+//!
+//! When a step fails before any key was moved, `CLUSTER SETSLOT <slot> STABLE` is
+//! sent to both nodes, so that the slot is not left importing and migrating.
+//! Otherwise the slot is left importing and migrating, so that clients are still
+//! redirected to the moved keys with `ASK`, and calling
+//! [`reshard_slot`](fn.reshard_slot.html) again resumes the migration.
+
+use actix::prelude::*;
+use derive_more::Display;
+use log::{info, warn};
+
+use crate::command::{
+    cluster_get_keys_in_slot, cluster_nodes, cluster_setslot, migrate_keys, ClusterNode,
+    RedisCommand,
+};
+use crate::{Error, RedisActor, RespError};
+
+/// Number of keys fetched by `CLUSTER GETKEYSINSLOT` per batch.
+const BATCH_SIZE: i64 = 100;
+/// Timeout of `MIGRATE`, in milliseconds.
+const MIGRATE_TIMEOUT: i64 = 5000;
+
+/// Progress of [`reshard_slot`](fn.reshard_slot.html).
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReshardProgress {
+    /// The destination node is importing the slot.
+    Importing,
+    /// The source node is migrating the slot.
+    Migrating,
+    /// A batch of keys has been moved.
+    Moved {
+        /// Number of keys moved so far.
+        migrated: usize,
+    },
+    /// The slot has been assigned to the destination node.
+    Finished {
+        /// Total number of keys moved.
+        migrated: usize,
+    },
+}
+
+/// Error of [`reshard_slot`](fn.reshard_slot.html).
+#[derive(Debug, Display)]
+#[display(fmt = "{} while resharding slot {}", error, slot)]
+pub struct ReshardError {
+    /// The slot being moved.
+    pub slot: u16,
+    /// Number of keys moved before the failure.
+    pub migrated: usize,
+    /// Whether the slot was left importing on the destination node and migrating on
+    /// the source node, or assigned to the destination node only, because keys were
+    /// already moved. Calling [`reshard_slot`](fn.reshard_slot.html) again with the
+    /// same nodes resumes the migration.
+    pub resumable: bool,
+    /// The error of the failed step.
+    pub error: Error,
+}
+
+/// State of a migration, to decide whether it can be rolled back.
+#[derive(Default)]
+struct Migration {
+    migrated: usize,
+    // whether keys may have been moved, or the slot assigned to the destination
+    started: bool,
+}
+
+async fn send<C>(addr: &Addr<RedisActor>, command: C) -> Result<C::Output, Error>
+where
+    C: RedisCommand + Message<Result = Result<<C as RedisCommand>::Output, Error>>,
+    C: Send + 'static,
+    C::Output: Send + 'static,
+{
+    match addr.send(command).await {
+        Ok(res) => res,
+        Err(_) => Err(Error::Disconnected),
+    }
+}
+
+// Returns the `myself` entry of CLUSTER NODES of the node.
+async fn myself(addr: &Addr<RedisActor>) -> Result<ClusterNode, Error> {
+    send(addr, cluster_nodes())
+        .await?
+        .into_iter()
+        .find(ClusterNode::is_myself)
+        .ok_or_else(|| {
+            Error::Redis(RespError::RESP(
+                "CLUSTER NODES: no entry for myself".into(),
+                None,
+            ))
+        })
+}
+
+/// Moves `slot` from the master node connected by `from` to the master node
+/// connected by `to`, calling `progress` after each step.
+///
+/// Returns the number of keys moved. When a step fails before any key was moved,
+/// the importing and migrating states of the slot are cleared on both nodes.
+/// Otherwise they are kept, so that the moved keys stay reachable, and the error
+/// is [`resumable`](struct.ReshardError.html#structfield.resumable): calling
+/// `reshard_slot` again moves the remaining keys.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use actix_redis::{reshard::reshard_slot, RedisActor};
+///
+/// # async fn run() -> Result<(), actix_redis::reshard::ReshardError> {
+/// let from = RedisActor::start("127.0.0.1:7000");
+/// let to = RedisActor::start("127.0.0.1:7001");
+///
+/// reshard_slot(3121, &from, &to, |progress| println!("{:?}", progress)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn reshard_slot<F>(
+    slot: u16,
+    from: &Addr<RedisActor>,
+    to: &Addr<RedisActor>,
+    mut progress: F,
+) -> Result<usize, ReshardError>
+where
+    F: FnMut(ReshardProgress),
+{
+    let failed = |error| ReshardError {
+        slot,
+        migrated: 0,
+        resumable: false,
+        error,
+    };
+    let source = myself(from).await.map_err(failed)?;
+    let destination = myself(to).await.map_err(failed)?;
+    info!(
+        "resharding slot {} from {} to {}",
+        slot, source.id, destination.id
+    );
+
+    let mut migration = Migration::default();
+    let res = migrate_slot(
+        slot,
+        from,
+        to,
+        &source,
+        &destination,
+        &mut migration,
+        &mut progress,
+    )
+    .await;
+    let error = match res {
+        Ok(()) => return Ok(migration.migrated),
+        Err(error) => error,
+    };
+
+    if !migration.started {
+        for addr in &[to, from] {
+            if let Err(err) = send(addr, cluster_setslot::stable(slot)).await {
+                warn!("failed to clear the state of slot {}: {}", slot, err);
+            }
+        }
+    }
+    Err(ReshardError {
+        slot,
+        migrated: migration.migrated,
+        resumable: migration.started,
+        error,
+    })
+}
+
+async fn migrate_slot<F>(
+    slot: u16,
+    from: &Addr<RedisActor>,
+    to: &Addr<RedisActor>,
+    source: &ClusterNode,
+    destination: &ClusterNode,
+    migration: &mut Migration,
+    progress: &mut F,
+) -> Result<(), Error>
+where
+    F: FnMut(ReshardProgress),
+{
+    // resuming a migration which moved keys, or assigned the slot to the destination
+    let owned = destination
+        .slots
+        .iter()
+        .any(|&(start, end)| start <= slot && slot <= end);
+    migration.started = owned || source.migrating.iter().any(|(s, _)| *s == slot);
+    if !owned {
+        send(to, cluster_setslot::importing(slot, source.id.clone())).await?;
+        progress(ReshardProgress::Importing);
+
+        send(
+            from,
+            cluster_setslot::migrating(slot, destination.id.clone()),
+        )
+        .await?;
+        progress(ReshardProgress::Migrating);
+
+        loop {
+            let keys = send(from, cluster_get_keys_in_slot(slot, BATCH_SIZE)).await?;
+            if keys.is_empty() {
+                break;
+            }
+
+            let len = keys.len();
+            migration.started = true;
+            let moved = send(
+                from,
+                migrate_keys(
+                    destination.host.clone(),
+                    destination.port,
+                    keys,
+                    0,
+                    MIGRATE_TIMEOUT,
+                ),
+            )
+            .await?;
+            if moved {
+                migration.migrated += len;
+            }
+            progress(ReshardProgress::Moved {
+                migrated: migration.migrated,
+            });
+        }
+
+        migration.started = true;
+        send(to, cluster_setslot::node(slot, destination.id.clone())).await?;
+    }
+    send(from, cluster_setslot::node(slot, destination.id.clone())).await?;
+    progress(ReshardProgress::Finished {
+        migrated: migration.migrated,
+    });
+
+    Ok(())
+}
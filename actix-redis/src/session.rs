@@ -1,22 +1,33 @@
-use std::cell::RefCell;
-use std::pin::Pin;
-use std::task::{Context, Poll};
-use std::{collections::HashMap, iter, rc::Rc};
+use std::{borrow::Cow, collections::HashMap, iter};
 
 use actix::prelude::*;
 use actix_service::{Service, Transform};
-use actix_session::{Session, SessionStatus};
-use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use actix_session::{
+    Session, SessionChanges, SessionLifetime, SessionStore, StoreSession,
+    StoreSessionMiddleware,
+};
+use actix_web::cookie::SameSite;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
-use actix_web::http::header::{self, HeaderValue};
-use actix_web::{error, Error, HttpMessage};
-use futures_util::future::{ok, Future, Ready};
+use actix_web::http::StatusCode;
+use actix_web::{error, Error, ResponseError};
+use derive_more::Display;
+use futures_util::future::{join_all, FutureExt, LocalBoxFuture, Ready};
+use log::warn;
 use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
-use time::{self, Duration, OffsetDateTime};
+use redis_async::resp::RespValue;
+use time::Duration;
 
+use crate::backend::SessionBackend;
 use crate::cluster::RedisClusterActor;
-use crate::command::{del, get, set};
+use crate::codec::{self, Codec};
+use crate::command::{
+    del, eval, exists, expire, get, hdel_multiple, hgetall, hset_multiple, sadd, scan,
+    set, smembers, srem, srem_multiple, ttl, DirectedTo, RedisClusterCommand,
+};
+#[cfg(feature = "encryption")]
+use crate::crypto::{hmac_hex, KeyRing};
 use crate::redis::RedisActor;
+use crate::RespError;
 
 /// Use redis as session storage.
 ///
@@ -25,7 +36,167 @@ use crate::redis::RedisActor;
 /// session, When this value is changed, all session data is lost.
 ///
 /// Constructor panics if key length is less than 32 bytes.
-pub struct RedisSession<R: Actor = RedisActor>(Rc<Inner<R>>);
+///
+/// Sessions associated with a principal through
+/// [`SessionPrincipal::set_principal`](trait.SessionPrincipal.html#tymethod.set_principal)
+/// are indexed, so that all the sessions of a user can be listed and revoked. Keep a
+/// clone of the fully configured `RedisSession` to access the index.
+///
+/// `RedisSession` is a
+/// [`StoreSession`](../actix_session/struct.StoreSession.html) over a Redis session
+/// store.
+pub struct RedisSession<R: Actor = RedisActor>(StoreSession<RedisSessionStore<R>>);
+
+impl<R: Actor> Clone for RedisSession<R> {
+    fn clone(&self) -> Self {
+        RedisSession(self.0.clone())
+    }
+}
+
+/// What to do when a session was changed by a concurrent request, see
+/// [`RedisSession::optimistic_concurrency`](struct.RedisSession.html#method.optimistic_concurrency).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConflictStrategy {
+    /// Apply the keys changed by the request to the current state of the session.
+    Merge,
+    /// Fail the request with [`SessionConflict`](struct.SessionConflict.html).
+    Reject,
+}
+
+/// Error returned when the session of a request was changed or removed by a
+/// concurrent request, rendered as `409 Conflict`.
+///
+/// The changes of the request to the session are discarded.
+#[derive(Debug, Display)]
+#[display(fmt = "Session was changed by a concurrent request")]
+pub struct SessionConflict;
+
+impl ResponseError for SessionConflict {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::CONFLICT
+    }
+}
+
+/// Reserved session key holding the principal.
+const PRINCIPAL_KEY: &str = "actix-redis.principal";
+/// Field of a session hash holding the version of the session.
+const VERSION_KEY: &str = "actix-redis.version";
+/// Field written to every session hash, so that empty sessions exist as well.
+const HASH_MARKER: &str = "actix-redis.session";
+/// Number of attempts to merge the changes of a request into a session changed
+/// concurrently.
+const MERGE_ATTEMPTS: usize = 3;
+/// Number of keys examined by each `SCAN` of the session administration.
+const SCAN_COUNT: usize = 100;
+
+/// Writes a serialized session if its version is still `ARGV[1]`.
+const CAS_SCRIPT: &str = r#"
+local current = redis.call("get", KEYS[1])
+local header = ""
+if current and string.byte(current, 1) == 0 then
+    header = string.sub(current, 1, 9)
+end
+if header ~= ARGV[1] then
+    return 0
+end
+redis.call("set", KEYS[1], ARGV[2], "ex", ARGV[3])
+return 1
+"#;
+
+/// Writes the fields of a session hash if its version field `ARGV[1]` is still
+/// `ARGV[2]`. `ARGV[4]` tells whether the hash is cleared first, `ARGV[5]` is the
+/// number of fields removed, followed by those fields and the fields to set.
+const HASH_CAS_SCRIPT: &str = r#"
+local current = redis.call("hget", KEYS[1], ARGV[1]) or ""
+if current ~= ARGV[2] then
+    return 0
+end
+if ARGV[4] == "1" then
+    redis.call("del", KEYS[1])
+end
+local removed = tonumber(ARGV[5])
+for i = 6, 5 + removed do
+    redis.call("hdel", KEYS[1], ARGV[i])
+end
+for i = 6 + removed, #ARGV, 2 do
+    redis.call("hset", KEYS[1], ARGV[i], ARGV[i + 1])
+end
+redis.call("expire", KEYS[1], ARGV[3])
+return 1
+"#;
+
+/// Associates a [`Session`](../actix_session/struct.Session.html) with a principal,
+/// e.g. a user id, in the index maintained by [`RedisSession`](struct.RedisSession.html).
+pub trait SessionPrincipal {
+    /// Set the principal of the session.
+    fn set_principal(&self, principal: &str) -> Result<(), Error>;
+
+    /// Get the principal of the session.
+    fn principal(&self) -> Result<Option<String>, Error>;
+}
+
+impl SessionPrincipal for Session {
+    fn set_principal(&self, principal: &str) -> Result<(), Error> {
+        self.set(PRINCIPAL_KEY, principal)
+    }
+
+    fn principal(&self) -> Result<Option<String>, Error> {
+        self.get(PRINCIPAL_KEY)
+    }
+}
+
+fn principal_of(state: &HashMap<String, String>) -> Option<String> {
+    state
+        .get(PRINCIPAL_KEY)
+        .and_then(|value| serde_json::from_str(value).ok())
+}
+
+/// applies the keys changed by a request to the current state of the session
+fn merge(
+    mut current: HashMap<String, String>,
+    state: &HashMap<String, String>,
+    changes: &SessionChanges,
+) -> HashMap<String, String> {
+    if changes.cleared {
+        current.retain(|key, _| Session::is_metadata_key(key));
+    }
+    for key in &changes.removed {
+        current.remove(key);
+    }
+    for key in &changes.inserted {
+        if let Some(value) = state.get(key) {
+            current.insert(key.clone(), value.clone());
+        }
+    }
+    current
+}
+
+/// data authenticated along with a field of a session stored as a hash
+fn field_aad(cachekey: &str, field: &[u8]) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(cachekey.len() + 1 + field.len());
+    aad.extend_from_slice(cachekey.as_bytes());
+    aad.push(0);
+    aad.extend_from_slice(field);
+    aad
+}
+
+async fn send<R, C>(addr: &Addr<R>, command: C) -> Result<C::Output, Error>
+where
+    R: SessionBackend,
+    C: RedisClusterCommand,
+{
+    let slot = command.slot().map_err(|slots| {
+        error::ErrorInternalServerError(crate::Error::DifferentSlots(slots))
+    })?;
+    let resp = R::execute(addr, slot, command.serialize())
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    C::deserialize(resp).map_err(|e| {
+        error::ErrorInternalServerError(crate::Error::Redis(RespError::RESP(
+            e.message, e.resp,
+        )))
+    })
+}
 
 impl RedisSession<RedisActor> {
     /// Create new redis session backend
@@ -40,19 +211,7 @@ impl RedisSession<RedisActor> {
     ///
     /// * `addr` - Addr of the redis actor
     pub fn from_redis(addr: Addr<RedisActor>, key: &[u8]) -> Self {
-        RedisSession(Rc::new(Inner {
-            key: Key::derive_from(key),
-            cache_keygen: Box::new(|key: &str| format!("session:{}", &key)),
-            ttl: 7200,
-            addr,
-            name: "actix-session".to_owned(),
-            path: "/".to_owned(),
-            domain: None,
-            secure: false,
-            max_age: Some(Duration::days(7)),
-            same_site: None,
-            http_only: Some(true),
-        }))
+        RedisSession::from_store(StoreSession::new(RedisSessionStore::new(addr), key))
     }
 }
 
@@ -69,362 +228,963 @@ impl RedisSession<RedisClusterActor> {
     ///
     /// * `addr` - Addr of the redis cluster actor
     pub fn from_cluster(addr: Addr<RedisClusterActor>, key: &[u8]) -> Self {
-        RedisSession(Rc::new(Inner {
-            key: Key::derive_from(key),
-            cache_keygen: Box::new(|key: &str| format!("session:{}", &key)),
-            ttl: 7200,
-            addr,
-            name: "actix-session".to_owned(),
-            path: "/".to_owned(),
-            domain: None,
-            secure: false,
-            max_age: Some(Duration::days(7)),
-            same_site: None,
-            http_only: Some(true),
-        }))
+        RedisSession::from_store(StoreSession::new(RedisSessionStore::new(addr), key))
+    }
+}
+
+impl<R: SessionBackend> RedisSession<R> {
+    /// Create new redis session backend storing the sessions with any actor
+    /// executing Redis commands, see [`SessionBackend`](trait.SessionBackend.html).
+    pub fn from_backend(addr: Addr<R>, key: &[u8]) -> Self {
+        RedisSession::from_store(StoreSession::new(RedisSessionStore::new(addr), key))
     }
 }
 
 impl<R: Actor> RedisSession<R> {
-    /// Set time to live in seconds for session value
+    fn from_store(session: StoreSession<RedisSessionStore<R>>) -> Self {
+        RedisSession(
+            session
+                .cookie_secure(false)
+                .cookie_max_age(Duration::days(7)),
+        )
+    }
+
+    /// Set the keys used before the current one, e.g. when rotating keys.
+    ///
+    /// Cookies signed with a previous key are still accepted, and issued again with
+    /// the current key. Panics if a key is less than 32 bytes long.
+    pub fn previous_keys(self, keys: &[&[u8]]) -> Self {
+        RedisSession(self.0.previous_keys(keys))
+    }
+
+    /// Encrypt the session id cookie instead of only signing it. Default is `false`.
+    ///
+    /// Cookies issued before changing this option are still accepted, and issued
+    /// again.
+    pub fn cookie_private(self, private: bool) -> Self {
+        RedisSession(self.0.cookie_private(private))
+    }
+
+    /// Set whether requests are served when Redis cannot be reached.
+    ///
+    /// When `true`, such requests get an empty session which is not written back,
+    /// and carry the [`SessionStoreDegraded`](struct.SessionStoreDegraded.html)
+    /// extension. When `false`, they fail with `500 Internal Server Error`. Default
+    /// is `false`.
+    pub fn fail_open(self, fail_open: bool) -> Self {
+        RedisSession(self.0.fail_open(fail_open))
+    }
+
+    /// When true, prevents creating sessions until they contain data. Default is
+    /// `false`, every new client gets a session.
+    pub fn lazy(self, lazy: bool) -> Self {
+        RedisSession(self.0.lazy(lazy))
+    }
+
+    /// Fetch the session from Redis only when a handler first extracts it. Default
+    /// is `false`.
+    ///
+    /// Requests whose handlers never extract the [`Session`], e.g. for static
+    /// assets or health checks, then do not reach Redis at all, and their session
+    /// is neither written back nor extended. Sessions obtained with
+    /// `UserSession::get_session` are empty in this mode, and are loaded only once
+    /// changed, to be written back; leave this `false` when middleware reads the
+    /// session.
+    ///
+    /// [`Session`]: ../actix_session/struct.Session.html
+    pub fn lazy_load(self, lazy_load: bool) -> Self {
+        RedisSession(self.0.lazy_load(lazy_load))
+    }
+
+    /// Set time to live in seconds for session value.
+    ///
+    /// Sessions with a persistent lifetime, see
+    /// [`Session::set_lifetime`](../actix_session/struct.Session.html#method.set_lifetime),
+    /// live for that lifetime instead.
     pub fn ttl(mut self, ttl: i64) -> Self {
-        Rc::get_mut(&mut self.0).unwrap().ttl = ttl;
+        self.0.store_mut().ttl = ttl;
         self
     }
 
     /// Set custom cookie name for session id
-    pub fn cookie_name(mut self, name: &str) -> Self {
-        Rc::get_mut(&mut self.0).unwrap().name = name.to_owned();
-        self
+    pub fn cookie_name(self, name: &str) -> Self {
+        RedisSession(self.0.cookie_name(name))
     }
 
     /// Set custom cookie path
-    pub fn cookie_path(mut self, path: &str) -> Self {
-        Rc::get_mut(&mut self.0).unwrap().path = path.to_owned();
-        self
+    pub fn cookie_path(self, path: &str) -> Self {
+        RedisSession(self.0.cookie_path(path))
     }
 
     /// Set custom cookie domain
-    pub fn cookie_domain(mut self, domain: &str) -> Self {
-        Rc::get_mut(&mut self.0).unwrap().domain = Some(domain.to_owned());
-        self
+    pub fn cookie_domain(self, domain: &str) -> Self {
+        RedisSession(self.0.cookie_domain(domain))
     }
 
     /// Set custom cookie secure
     /// If the `secure` field is set, a cookie will only be transmitted when the
     /// connection is secure - i.e. `https`
-    pub fn cookie_secure(mut self, secure: bool) -> Self {
-        Rc::get_mut(&mut self.0).unwrap().secure = secure;
-        self
+    pub fn cookie_secure(self, secure: bool) -> Self {
+        RedisSession(self.0.cookie_secure(secure))
     }
 
     /// Set custom cookie max-age
-    pub fn cookie_max_age(mut self, max_age: Duration) -> Self {
-        Rc::get_mut(&mut self.0).unwrap().max_age = Some(max_age);
-        self
+    pub fn cookie_max_age(self, max_age: Duration) -> Self {
+        RedisSession(self.0.cookie_max_age(max_age))
     }
 
     /// Set custom cookie SameSite
-    pub fn cookie_same_site(mut self, same_site: SameSite) -> Self {
-        Rc::get_mut(&mut self.0).unwrap().same_site = Some(same_site);
-        self
+    pub fn cookie_same_site(self, same_site: SameSite) -> Self {
+        RedisSession(self.0.cookie_same_site(same_site))
     }
 
     /// Set custom cookie HttpOnly policy
-    pub fn cookie_http_only(mut self, http_only: bool) -> Self {
-        Rc::get_mut(&mut self.0).unwrap().http_only = Some(http_only);
+    pub fn cookie_http_only(self, http_only: bool) -> Self {
+        RedisSession(self.0.cookie_http_only(http_only))
+    }
+
+    /// Set the serialization format of the session state. Default is `Codec::Json`.
+    ///
+    /// Sessions stored with another codec are still loaded, see
+    /// [`Codec`](enum.Codec.html).
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.0.store_mut().codec = codec;
         self
     }
 
+    /// Extend the TTL of the session whenever it is accessed, not only when it
+    /// changes, and refresh the max-age of the session cookie to match. The state
+    /// is not written again.
+    ///
+    /// The TTL is only extended once `threshold` (a fraction between 0 and 1) of it
+    /// has elapsed, which saves a round trip on most requests; with `0.0` it is
+    /// extended on every access.
+    ///
+    /// Panics if `threshold` is not between 0 and 1.
+    pub fn sliding_ttl(mut self, threshold: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&threshold),
+            "sliding TTL threshold must be between 0 and 1"
+        );
+        self.0.store_mut().sliding_ttl = threshold;
+        RedisSession(self.0.sliding_expiration(true))
+    }
+
+    /// Protect sessions against lost updates by concurrent requests. By default, the
+    /// last write wins.
+    ///
+    /// A version is stored alongside each session, and a session is only written
+    /// back if its version did not change since it was loaded, which is checked by a
+    /// Lua script. Otherwise, `strategy` decides whether the keys changed by the
+    /// request are applied to the current state of the session, or whether the
+    /// request fails with [`SessionConflict`](struct.SessionConflict.html). Merging
+    /// fails as well if the session was removed meanwhile, or if it keeps
+    /// conflicting.
+    pub fn optimistic_concurrency(mut self, strategy: ConflictStrategy) -> Self {
+        self.0.store_mut().concurrency = Some(strategy);
+        self
+    }
+
+    /// Store each session as a Redis hash with a field per session key, instead of
+    /// a single serialized value.
+    ///
+    /// Only the keys changed by a request are then written, so that concurrent
+    /// requests changing different keys do not overwrite each other. The codec is not
+    /// used in this mode. Default is `false`.
+    pub fn hash_storage(mut self, hash_storage: bool) -> Self {
+        self.0.store_mut().hash_storage = hash_storage;
+        self
+    }
+
+    /// Encrypt the sessions stored in Redis with AES-256-GCM, using the keys of
+    /// `key_ring`. Requires the `encryption` feature.
+    ///
+    /// The serialized state is encrypted, or each value in hash storage mode, and
+    /// bound to the Redis key it is stored under. Sessions which cannot be decrypted,
+    /// e.g. because their key was removed from the key ring, are treated as missing.
+    /// So are sessions stored before enabling encryption, unless
+    /// [`KeyRing::allow_plaintext`](struct.KeyRing.html#method.allow_plaintext) is
+    /// set, in which case they are encrypted on their next change.
+    #[cfg(feature = "encryption")]
+    pub fn encryption(mut self, key_ring: KeyRing) -> Self {
+        self.0.store_mut().key_ring = Some(key_ring);
+        self
+    }
+
+    /// Name the Redis keys of the sessions after the HMAC-SHA256 of their id with
+    /// `secret`, so that the keys do not reveal the session ids. Requires the
+    /// `encryption` feature.
+    ///
+    /// Existing sessions are lost when enabling this option or changing `secret`.
+    /// The index of the sessions of a principal holds their Redis keys rather than
+    /// their ids, see [`principal_sessions`](#method.principal_sessions).
+    #[cfg(feature = "encryption")]
+    pub fn hmac_cache_keys(self, secret: &[u8]) -> Self {
+        let secret = secret.to_vec();
+        let mut session = self.cache_keygen(Box::new(move |key: &str| {
+            format!("session:{}", hmac_hex(&secret, key.as_bytes()))
+        }));
+        session.0.store_mut().index_cache_keys = true;
+        session
+    }
+
     /// Set a custom cache key generation strategy, expecting session key as input
     pub fn cache_keygen(mut self, keygen: Box<dyn Fn(&str) -> String>) -> Self {
-        Rc::get_mut(&mut self.0).unwrap().cache_keygen = keygen;
+        let store = self.0.store_mut();
+        store.cache_keygen = keygen;
+        store.index_cache_keys = false;
+        self
+    }
+
+    /// Set a custom key generation strategy for the index of the sessions of a
+    /// principal, expecting the principal as input
+    pub fn principal_keygen(mut self, keygen: Box<dyn Fn(&str) -> String>) -> Self {
+        self.0.store_mut().principal_keygen = keygen;
         self
     }
+
+    /// Serve the session as `Session<N>`, so that it can be used next to other
+    /// session middlewares; each of them needs its own cookie name.
+    pub fn namespace<N: 'static>(self) -> Self {
+        RedisSession(self.0.namespace::<N>())
+    }
 }
 
-/// Cookie session middleware
-pub struct RedisSessionMiddleware<S: 'static, R: Actor> {
-    service: Rc<RefCell<S>>,
-    inner: Rc<Inner<R>>,
+impl<S, B, R> Transform<S> for RedisSession<R>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+    R: SessionBackend,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RedisSessionMiddleware<S, R>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        self.0.new_transform(service)
+    }
 }
 
-struct Inner<R: Actor> {
-    key: Key,
-    cache_keygen: Box<dyn Fn(&str) -> String>,
-    ttl: i64,
+/// Redis session middleware
+pub type RedisSessionMiddleware<S, R> = StoreSessionMiddleware<S, RedisSessionStore<R>>;
+
+impl<R: Actor> RedisSession<R> {
+    /// Handle to administrate the sessions stored with this configuration, e.g. for
+    /// support staff to list and remove live sessions.
+    pub fn admin(&self) -> SessionAdmin<R> {
+        SessionAdmin {
+            session: self.0.clone(),
+            pattern: "session:*".to_owned(),
+        }
+    }
+}
+
+/// A stored session, see [`SessionAdmin::inspect`](struct.SessionAdmin.html#method.inspect).
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionInfo {
+    /// Serialized values of the session by key, without the metadata of the
+    /// session, see [`Session::entries`].
+    ///
+    /// [`Session::entries`]: ../actix_session/struct.Session.html#method.entries
+    pub state: HashMap<String, String>,
+    /// Remaining time to live, `None` if the session does not expire.
+    pub ttl: Option<Duration>,
+}
+
+/// Administration of the sessions stored by a
+/// [`RedisSession`](struct.RedisSession.html), obtained with
+/// [`RedisSession::admin`](struct.RedisSession.html#method.admin).
+///
+/// Sessions are identified by their Redis key, which is found with `SCAN`, on every
+/// master node of Redis Cluster. Their state is decoded with the codec, storage mode
+/// and keys of the `RedisSession`.
+pub struct SessionAdmin<R: Actor = RedisActor> {
+    session: StoreSession<RedisSessionStore<R>>,
+    pattern: String,
+}
+
+impl<R: Actor> Clone for SessionAdmin<R> {
+    fn clone(&self) -> Self {
+        SessionAdmin {
+            session: self.session.clone(),
+            pattern: self.pattern.clone(),
+        }
+    }
+}
+
+impl<R: Actor> SessionAdmin<R> {
+    /// Set the glob-style pattern matching the Redis keys of the sessions, and no
+    /// other key. Default is `session:*`, which matches the default
+    /// [`cache_keygen`](struct.RedisSession.html#method.cache_keygen).
+    pub fn pattern(mut self, pattern: &str) -> Self {
+        self.pattern = pattern.to_owned();
+        self
+    }
+
+    /// Redis key of the session with id `id`, e.g. one of the
+    /// [`principal_sessions`](struct.RedisSession.html#method.principal_sessions)
+    /// unless they are Redis keys already.
+    pub fn cache_key(&self, id: &str) -> String {
+        (self.session.store().cache_keygen)(id)
+    }
+}
+
+/// Session store keeping the sessions in Redis, used by
+/// [`RedisSession`](struct.RedisSession.html).
+pub struct RedisSessionStore<R: Actor> {
     addr: Addr<R>,
-    name: String,
-    path: String,
-    domain: Option<String>,
-    secure: bool,
-    max_age: Option<Duration>,
-    same_site: Option<SameSite>,
-    http_only: Option<bool>,
+    ttl: i64,
+    cache_keygen: Box<dyn Fn(&str) -> String>,
+    principal_keygen: Box<dyn Fn(&str) -> String>,
+    /// whether the index of a principal holds the Redis keys of the sessions
+    /// rather than their ids
+    index_cache_keys: bool,
+    codec: Codec,
+    sliding_ttl: f64,
+    hash_storage: bool,
+    concurrency: Option<ConflictStrategy>,
+    #[cfg(feature = "encryption")]
+    key_ring: Option<KeyRing>,
 }
 
-macro_rules! impl_methods {
-    ($R:ty) => {
-        impl<S, B> Transform<S> for RedisSession<$R>
-        where
-            S: Service<
-                    Request = ServiceRequest,
-                    Response = ServiceResponse<B>,
-                    Error = Error,
-                > + 'static,
-            S::Future: 'static,
-            B: 'static,
+impl<R: Actor> RedisSessionStore<R> {
+    fn new(addr: Addr<R>) -> Self {
+        RedisSessionStore {
+            addr,
+            ttl: 7200,
+            cache_keygen: Box::new(|key: &str| format!("session:{}", &key)),
+            principal_keygen: Box::new(|principal: &str| {
+                format!("session-index:{}", principal)
+            }),
+            index_cache_keys: false,
+            codec: Codec::Json,
+            sliding_ttl: 0.0,
+            hash_storage: false,
+            concurrency: None,
+            #[cfg(feature = "encryption")]
+            key_ring: None,
+        }
+    }
+
+    /// the time to live of a session, following its lifetime when persistent
+    fn ttl_of(&self, state: &HashMap<String, String>) -> i64 {
+        match SessionLifetime::from_state(state) {
+            Some(SessionLifetime::Persistent(lifetime)) => {
+                lifetime.whole_seconds().max(1)
+            }
+            _ => self.ttl,
+        }
+    }
+
+    /// encrypts a serialized value stored under `aad`, when encryption is enabled
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    fn seal(&self, aad: &[u8], data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        #[cfg(feature = "encryption")]
         {
-            type Request = ServiceRequest;
-            type Response = ServiceResponse<B>;
-            type Error = S::Error;
-            type InitError = ();
-            type Transform = RedisSessionMiddleware<S, $R>;
-            type Future = Ready<Result<Self::Transform, Self::InitError>>;
-
-            fn new_transform(&self, service: S) -> Self::Future {
-                ok(RedisSessionMiddleware {
-                    service: Rc::new(RefCell::new(service)),
-                    inner: self.0.clone(),
-                })
+            if let Some(ref key_ring) = self.key_ring {
+                return key_ring.encrypt(aad, &data);
             }
         }
+        Ok(data)
+    }
 
-        impl<S, B> Service for RedisSessionMiddleware<S, $R>
-        where
-            S: Service<
-                    Request = ServiceRequest,
-                    Response = ServiceResponse<B>,
-                    Error = Error,
-                > + 'static,
-            S::Future: 'static,
-            B: 'static,
+    /// decrypts a serialized value stored under `aad`, when encryption is enabled
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    fn open<'a>(&self, aad: &[u8], data: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        #[cfg(feature = "encryption")]
         {
-            type Request = ServiceRequest;
-            type Response = ServiceResponse<B>;
-            type Error = Error;
-            #[allow(clippy::type_complexity)]
-            type Future =
-                Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
-
-            fn poll_ready(
-                &mut self,
-                cx: &mut Context<'_>,
-            ) -> Poll<Result<(), Self::Error>> {
-                self.service.borrow_mut().poll_ready(cx)
+            if let Some(ref key_ring) = self.key_ring {
+                return key_ring.decrypt(aad, data);
             }
+        }
+        Some(Cow::Borrowed(data))
+    }
 
-            fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
-                let mut srv = self.service.clone();
-                let inner = self.inner.clone();
-
-                Box::pin(async move {
-                    let state = inner.load(&req).await?;
-                    let value = if let Some((state, value)) = state {
-                        Session::set_session(state.into_iter(), &mut req);
-                        Some(value)
-                    } else {
-                        None
-                    };
-
-                    let mut res = srv.call(req).await?;
-
-                    match Session::get_changes(&mut res) {
-                        (SessionStatus::Unchanged, None) => Ok(res),
-                        (SessionStatus::Unchanged, Some(state)) => {
-                            if value.is_none() {
-                                // implies the session is new
-                                inner.update(res, state, value).await
-                            } else {
-                                Ok(res)
-                            }
-                        }
-                        (SessionStatus::Changed, Some(state)) => {
-                            inner.update(res, state, value).await
-                        }
-                        (SessionStatus::Purged, Some(_)) => {
-                            if let Some(val) = value {
-                                inner.clear_cache(val).await?;
-                                match inner.remove_cookie(&mut res) {
-                                    Ok(_) => Ok(res),
-                                    Err(_err) => {
-                                        Err(error::ErrorInternalServerError(_err))
-                                    }
-                                }
-                            } else {
-                                Err(error::ErrorInternalServerError("unexpected"))
-                            }
-                        }
-                        (SessionStatus::Renewed, Some(state)) => {
-                            if let Some(val) = value {
-                                inner.clear_cache(val).await?;
-                                inner.update(res, state, None).await
-                            } else {
-                                inner.update(res, state, None).await
-                            }
-                        }
-                        (_, None) => unreachable!(),
-                    }
-                })
+    /// decodes a serialized session, and its version if any
+    fn decode(
+        &self,
+        cachekey: &str,
+        data: &[u8],
+    ) -> Option<(HashMap<String, String>, Option<u64>)> {
+        let (version, data) = codec::unversioned(data);
+        let data = self.open(cachekey.as_bytes(), data)?;
+        Some((Codec::decode(&data)?, version))
+    }
+}
+
+impl<R: SessionBackend> SessionStore for RedisSessionStore<R> {
+    fn load<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Option<HashMap<String, String>>, Error>> {
+        self.fetch((self.cache_keygen)(key)).boxed_local()
+    }
+
+    fn load_versioned<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Option<(HashMap<String, String>, Option<u64>)>, Error>>
+    {
+        self.fetch_versioned((self.cache_keygen)(key)).boxed_local()
+    }
+
+    fn save(
+        &self,
+        state: HashMap<String, String>,
+    ) -> LocalBoxFuture<'_, Result<String, Error>> {
+        async move {
+            let key: String = iter::repeat(())
+                .map(|()| OsRng.sample(Alphanumeric))
+                .take(32)
+                .collect();
+
+            let cachekey = (self.cache_keygen)(&key);
+            if self.concurrency.is_some() {
+                if !self.compare_and_set(cachekey, &state, None, None).await? {
+                    return Err(SessionConflict.into());
+                }
+            } else {
+                self.store(cachekey, &state, None).await?;
+            }
+            if let Some(principal) = principal_of(&state) {
+                self.index(&principal, &key, self.ttl_of(&state)).await?;
             }
+            Ok(key)
         }
+        .boxed_local()
+    }
 
-        impl Inner<$R> {
-            async fn load(
-                &self,
-                req: &ServiceRequest,
-            ) -> Result<Option<(HashMap<String, String>, String)>, Error> {
-                if let Ok(cookies) = req.cookies() {
-                    for cookie in cookies.iter() {
-                        if cookie.name() == self.name {
-                            let mut jar = CookieJar::new();
-                            jar.add_original(cookie.clone());
-                            if let Some(cookie) = jar.signed(&self.key).get(&self.name) {
-                                let value = cookie.value().to_owned();
-                                let cachekey = (self.cache_keygen)(&cookie.value());
-                                return match self.addr.send(get(cachekey)).await {
-                                    Err(e) => Err(Error::from(e)),
-                                    Ok(res) => match res {
-                                        Ok(val) => {
-                                            if let Some(val) = val {
-                                                if let Ok(val) =
-                                                    serde_json::from_slice(&val)
-                                                {
-                                                    return Ok(Some((val, value)));
-                                                }
-                                            }
-                                            Ok(None)
-                                        }
-                                        Err(err) => {
-                                            Err(error::ErrorInternalServerError(err))
-                                        }
-                                    },
-                                };
-                            } else {
-                                return Ok(None);
-                            }
-                        }
-                    }
+    fn update(
+        &self,
+        key: String,
+        state: HashMap<String, String>,
+        changes: SessionChanges,
+    ) -> LocalBoxFuture<'_, Result<String, Error>> {
+        self.update_versioned(key, state, changes, None)
+    }
+
+    fn update_versioned(
+        &self,
+        key: String,
+        state: HashMap<String, String>,
+        changes: SessionChanges,
+        version: Option<u64>,
+    ) -> LocalBoxFuture<'_, Result<String, Error>> {
+        async move {
+            let cachekey = (self.cache_keygen)(&key);
+
+            // the previous principal is only read when it may have changed
+            let principal_changed = changes.cleared
+                || changes.inserted.contains(PRINCIPAL_KEY)
+                || changes.removed.contains(PRINCIPAL_KEY);
+            let previous = if principal_changed {
+                let state = self.fetch(cachekey.clone()).await?;
+                state.as_ref().and_then(principal_of)
+            } else {
+                None
+            };
+
+            let state = match self.concurrency {
+                Some(strategy) => {
+                    self.store_versioned(cachekey, state, &changes, version, strategy)
+                        .await?
                 }
-                Ok(None)
+                None => {
+                    self.store(cachekey, &state, Some(changes)).await?;
+                    state
+                }
+            };
+
+            let principal = principal_of(&state);
+            if principal_changed && previous != principal {
+                self.unindex(previous, &key).await;
+            }
+            if let Some(principal) = principal {
+                self.index(&principal, &key, self.ttl_of(&state)).await?;
             }
+            Ok(key)
+        }
+        .boxed_local()
+    }
 
-            async fn update<B>(
-                &self,
-                mut res: ServiceResponse<B>,
-                state: impl Iterator<Item = (String, String)>,
-                value: Option<String>,
-            ) -> Result<ServiceResponse<B>, Error> {
-                let (value, jar) = if let Some(value) = value {
-                    (value, None)
-                } else {
-                    let value: String = iter::repeat(())
-                        .map(|()| OsRng.sample(Alphanumeric))
-                        .take(32)
-                        .collect();
-
-                    // prepare session id cookie
-                    let mut cookie = Cookie::new(self.name.clone(), value.clone());
-                    cookie.set_path(self.path.clone());
-                    cookie.set_secure(self.secure);
-                    cookie.set_http_only(self.http_only.unwrap_or(true));
-
-                    if let Some(ref domain) = self.domain {
-                        cookie.set_domain(domain.clone());
-                    }
+    fn delete(&self, key: String) -> LocalBoxFuture<'_, Result<(), Error>> {
+        async move {
+            let cachekey = (self.cache_keygen)(&key);
+            let state = self.fetch(cachekey.clone()).await?;
 
-                    if let Some(max_age) = self.max_age {
-                        cookie.set_max_age(max_age);
-                    }
+            // the session may have been removed meanwhile, e.g. revoked or expired
+            send(&self.addr, del(cachekey)).await?;
+            self.unindex(state.as_ref().and_then(principal_of), &key)
+                .await;
+            Ok(())
+        }
+        .boxed_local()
+    }
 
-                    if let Some(same_site) = self.same_site {
-                        cookie.set_same_site(same_site);
+    /// The TTL is only extended once the sliding TTL threshold has elapsed.
+    fn ttl<'a>(
+        &'a self,
+        key: &'a str,
+        state: &'a HashMap<String, String>,
+    ) -> LocalBoxFuture<'a, Result<bool, Error>> {
+        async move {
+            let cachekey = (self.cache_keygen)(key);
+
+            let session_ttl = self.ttl_of(state);
+            if self.sliding_ttl > 0.0 {
+                match send(&self.addr, ttl(cachekey.clone())).await {
+                    Ok(remaining) if remaining >= 0 => {
+                        let elapsed = (session_ttl - remaining) as f64;
+                        if elapsed < self.sliding_ttl * session_ttl as f64 {
+                            return Ok(false);
+                        }
+                    }
+                    // the session has expired in the meantime
+                    Ok(_) => return Ok(false),
+                    Err(e) => {
+                        warn!("failed to refresh session TTL: {}", e);
+                        return Ok(false);
                     }
+                }
+            }
 
-                    // set cookie
-                    let mut jar = CookieJar::new();
-                    jar.signed(&self.key).add(cookie);
-
-                    (value, Some(jar))
-                };
-
-                let cachekey = (self.cache_keygen)(&value);
-
-                let state: HashMap<_, _> = state.collect();
-                match serde_json::to_string(&state) {
-                    Err(e) => Err(e.into()),
-                    Ok(body) => {
-                        match self.addr.send(set(cachekey, body).ex(self.ttl)).await {
-                            Err(e) => Err(Error::from(e)),
-                            Ok(redis_result) => match redis_result {
-                                Ok(_) => {
-                                    if let Some(jar) = jar {
-                                        for cookie in jar.delta() {
-                                            let val = HeaderValue::from_str(
-                                                &cookie.to_string(),
-                                            )?;
-                                            res.headers_mut()
-                                                .append(header::SET_COOKIE, val);
-                                        }
-                                    }
-                                    Ok(res)
-                                }
-                                Err(err) => Err(error::ErrorInternalServerError(err)),
-                            },
+            match send(&self.addr, expire(cachekey, session_ttl)).await {
+                Ok(true) => {
+                    if let Some(principal) = principal_of(state) {
+                        let index = (self.principal_keygen)(&principal);
+                        let index_ttl = session_ttl.max(self.ttl);
+                        if let Err(e) = send(&self.addr, expire(index, index_ttl)).await
+                        {
+                            warn!("failed to refresh session index TTL: {}", e);
                         }
                     }
+                    Ok(true)
+                }
+                Ok(false) => Ok(false),
+                Err(e) => {
+                    warn!("failed to refresh session TTL: {}", e);
+                    Ok(false)
                 }
             }
+        }
+        .boxed_local()
+    }
+}
 
-            /// removes cache entry
-            async fn clear_cache(&self, key: String) -> Result<(), Error> {
-                let cachekey = (self.cache_keygen)(&key);
-
-                match self.addr.send(del(cachekey)).await {
-                    Err(e) => Err(Error::from(e)),
-                    Ok(res) => match res {
-                        Ok(x) if x > 0 => Ok(()),
-                        _ => Err(error::ErrorInternalServerError(
-                            "failed to remove session from cache",
-                        )),
-                    },
+impl<R: SessionBackend> RedisSession<R> {
+    /// Ids of the live sessions of `principal`, or their Redis keys with
+    /// [`hmac_cache_keys`](#method.hmac_cache_keys).
+    ///
+    /// Expired or removed sessions are dropped from the index.
+    pub async fn principal_sessions(
+        &self,
+        principal: &str,
+    ) -> Result<Vec<String>, Error> {
+        self.0.store().sessions(principal).await
+    }
+
+    /// Number of live sessions of `principal`.
+    pub async fn count_principal_sessions(
+        &self,
+        principal: &str,
+    ) -> Result<usize, Error> {
+        Ok(self.0.store().sessions(principal).await?.len())
+    }
+
+    /// Remove all the sessions of `principal`, e.g. to log a user out
+    /// everywhere. Returns the number of sessions removed.
+    pub async fn revoke_principal_sessions(
+        &self,
+        principal: &str,
+    ) -> Result<usize, Error> {
+        self.0.store().revoke(principal).await
+    }
+}
+
+impl<R: SessionBackend> SessionAdmin<R> {
+    /// Redis keys of the live sessions.
+    pub async fn keys(&self) -> Result<Vec<String>, Error> {
+        let mut keys = self.session.store().scan(&self.pattern).await?;
+        // SCAN may return a key more than once
+        keys.sort_unstable();
+        keys.dedup();
+        Ok(keys
+            .into_iter()
+            .map(|key| String::from_utf8_lossy(&key).into_owned())
+            .collect())
+    }
+
+    /// Number of live sessions.
+    pub async fn count(&self) -> Result<usize, Error> {
+        Ok(self.keys().await?.len())
+    }
+
+    /// Decoded state and time to live of the session stored under `key`,
+    /// `None` if there is no such session or it cannot be decoded.
+    pub async fn inspect(&self, key: &str) -> Result<Option<SessionInfo>, Error> {
+        let store = self.session.store();
+        let mut state = match store.fetch(key.to_owned()).await? {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+        state.retain(|key, _| !Session::is_metadata_key(key));
+
+        let ttl = match send(&store.addr, ttl(key)).await? {
+            // removed meanwhile
+            -2 => return Ok(None),
+            -1 => None,
+            secs => Some(Duration::seconds(secs)),
+        };
+        Ok(Some(SessionInfo { state, ttl }))
+    }
+
+    /// Remove the session stored under `key`, which logs its client out.
+    /// Returns `false` if there is no such session.
+    ///
+    /// The session is dropped from the index of its principal when the index
+    /// is next read.
+    pub async fn delete(&self, key: &str) -> Result<bool, Error> {
+        Ok(send(&self.session.store().addr, del(key)).await? > 0)
+    }
+
+    /// Set the time to live in seconds of the session stored under `key`.
+    /// Returns `false` if there is no such session.
+    pub async fn expire(&self, key: &str, ttl: i64) -> Result<bool, Error> {
+        send(&self.session.store().addr, expire(key, ttl)).await
+    }
+}
+
+impl<R: SessionBackend> RedisSessionStore<R> {
+    /// reads the session state
+    async fn fetch(
+        &self,
+        cachekey: String,
+    ) -> Result<Option<HashMap<String, String>>, Error> {
+        Ok(self
+            .fetch_versioned(cachekey)
+            .await?
+            .map(|(state, _)| state))
+    }
+
+    /// reads the session state, and its version if any
+    async fn fetch_versioned(
+        &self,
+        cachekey: String,
+    ) -> Result<Option<(HashMap<String, String>, Option<u64>)>, Error> {
+        if !self.hash_storage {
+            let val = send(&self.addr, get(cachekey.clone())).await?;
+            return Ok(val.and_then(|val| self.decode(&cachekey, &val)));
+        }
+
+        let fields = send(&self.addr, hgetall(cachekey.clone())).await?;
+        if fields.is_empty() {
+            return Ok(None);
+        }
+        let mut version = None;
+        let state = fields
+            .into_iter()
+            .filter(|(field, _)| field != HASH_MARKER.as_bytes())
+            .filter_map(|(field, value)| {
+                // the version is written by the compare-and-set script, in plaintext
+                if field == VERSION_KEY.as_bytes() {
+                    version = String::from_utf8_lossy(&value).parse().ok();
+                    return None;
+                }
+                Some((field, value))
+            })
+            .map(|(field, value)| {
+                let value = self.open(&field_aad(&cachekey, &field), &value)?;
+                Some((
+                    String::from_utf8_lossy(&field).into_owned(),
+                    String::from_utf8_lossy(&value).into_owned(),
+                ))
+            })
+            .collect::<Option<_>>();
+        Ok(state.map(|state| (state, version)))
+    }
+
+    /// writes the session state, or only the changed keys when known
+    async fn store(
+        &self,
+        cachekey: String,
+        state: &HashMap<String, String>,
+        changes: Option<SessionChanges>,
+    ) -> Result<(), Error> {
+        if !self.hash_storage {
+            let body = self.codec.encode(state)?;
+            let body = self.seal(cachekey.as_bytes(), body)?;
+            send(&self.addr, set(cachekey, body).ex(self.ttl_of(state))).await?;
+            return Ok(());
+        }
+
+        let fields: Vec<_> = match changes {
+            Some(changes) if !changes.cleared => {
+                if !changes.removed.is_empty() {
+                    let removed = changes.removed.into_iter();
+                    send(&self.addr, hdel_multiple(cachekey.clone(), removed)).await?;
+                }
+                changes
+                    .inserted
+                    .into_iter()
+                    .filter_map(|key| state.get(&key).map(|val| (key, val.clone())))
+                    .collect()
+            }
+            changes => {
+                if changes.is_some() {
+                    // cleared: drop the keys not in the state anymore
+                    send(&self.addr, del(cachekey.clone())).await?;
+                }
+                state.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+            }
+        };
+
+        let fields = fields
+            .into_iter()
+            .map(|(key, val)| {
+                let aad = field_aad(&cachekey, key.as_bytes());
+                Ok((key, self.seal(&aad, val.into_bytes())?))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let marker = (HASH_MARKER.to_owned(), Vec::new());
+        let fields = iter::once(marker).chain(fields);
+        send(&self.addr, hset_multiple(cachekey.clone(), fields)).await?;
+        send(&self.addr, expire(cachekey, self.ttl_of(state))).await?;
+        Ok(())
+    }
+
+    /// writes the session state, or only the changed keys when known, if its
+    /// stored version is still `expected`; returns `false` otherwise
+    async fn compare_and_set(
+        &self,
+        cachekey: String,
+        state: &HashMap<String, String>,
+        changes: Option<&SessionChanges>,
+        expected: Option<u64>,
+    ) -> Result<bool, Error> {
+        let version = expected.map_or(1, |version| version + 1);
+        let ttl = self.ttl_of(state);
+
+        let script = if !self.hash_storage {
+            let body = self.codec.encode(state)?;
+            let body = self.seal(cachekey.as_bytes(), body)?;
+            eval(CAS_SCRIPT)
+                .key(cachekey)
+                .arg(expected.map(codec::version_header).unwrap_or_default())
+                .arg(codec::versioned(version, &body))
+                .arg(ttl.to_string())
+        } else {
+            let (cleared, removed, fields): (_, Vec<_>, Vec<_>) = match changes {
+                Some(changes) if !changes.cleared => (
+                    false,
+                    changes.removed.iter().collect(),
+                    changes
+                        .inserted
+                        .iter()
+                        .filter_map(|key| state.get_key_value(key))
+                        .collect(),
+                ),
+                changes => (changes.is_some(), Vec::new(), state.iter().collect()),
+            };
+
+            let mut script = eval(HASH_CAS_SCRIPT)
+                .key(cachekey.clone())
+                .arg(VERSION_KEY)
+                .arg(expected.map(|v| v.to_string()).unwrap_or_default())
+                .arg(ttl.to_string())
+                .arg(if cleared { "1" } else { "0" })
+                .arg(removed.len().to_string());
+            for key in removed {
+                script = script.arg(key.as_str());
+            }
+            script = script
+                .arg(HASH_MARKER)
+                .arg("")
+                .arg(VERSION_KEY)
+                .arg(version.to_string());
+            for (key, value) in fields {
+                if key != VERSION_KEY {
+                    let aad = field_aad(&cachekey, key.as_bytes());
+                    let value = self.seal(&aad, value.clone().into_bytes())?;
+                    script = script.arg(key.as_str()).arg(value);
                 }
             }
+            script
+        };
 
-            /// invalidates session cookie
-            fn remove_cookie<B>(
-                &self,
-                res: &mut ServiceResponse<B>,
-            ) -> Result<(), Error> {
-                let mut cookie = Cookie::named(self.name.clone());
-                cookie.set_value("");
-                cookie.set_max_age(Duration::zero());
-                cookie.set_expires(OffsetDateTime::now_utc() - Duration::days(365));
-
-                let val = HeaderValue::from_str(&cookie.to_string())
-                    .map_err(error::ErrorInternalServerError)?;
-                res.headers_mut().append(header::SET_COOKIE, val);
-
-                Ok(())
+        let res = send(&self.addr, script).await?;
+        Ok(res == RespValue::Integer(1))
+    }
+
+    /// writes the session state loaded at version `expected` with optimistic
+    /// concurrency, returning the state written
+    async fn store_versioned(
+        &self,
+        cachekey: String,
+        mut state: HashMap<String, String>,
+        changes: &SessionChanges,
+        mut expected: Option<u64>,
+        strategy: ConflictStrategy,
+    ) -> Result<HashMap<String, String>, Error> {
+        for _ in 0..MERGE_ATTEMPTS {
+            if self
+                .compare_and_set(cachekey.clone(), &state, Some(changes), expected)
+                .await?
+            {
+                return Ok(state);
+            }
+            if strategy == ConflictStrategy::Reject {
+                break;
+            }
+            match self.fetch_versioned(cachekey.clone()).await? {
+                Some((current, version)) => {
+                    state = merge(current, &state, changes);
+                    expected = version;
+                }
+                // removed meanwhile, e.g. revoked
+                None => break,
+            }
+        }
+        Err(SessionConflict.into())
+    }
+
+    /// entry of the session `id` in the index of its principal
+    fn index_entry(&self, id: &str) -> String {
+        if self.index_cache_keys {
+            (self.cache_keygen)(id)
+        } else {
+            id.to_owned()
+        }
+    }
+
+    /// Redis key of the session of an entry of the index of a principal
+    fn entry_cache_key(&self, entry: &str) -> String {
+        if self.index_cache_keys {
+            entry.to_owned()
+        } else {
+            (self.cache_keygen)(entry)
+        }
+    }
+
+    /// adds the session to the index of the principal, which lives at least as
+    /// long as the session
+    async fn index(&self, principal: &str, id: &str, ttl: i64) -> Result<(), Error> {
+        let index = (self.principal_keygen)(principal);
+        send(&self.addr, sadd(index.clone(), self.index_entry(id))).await?;
+        send(&self.addr, expire(index, ttl.max(self.ttl))).await?;
+        Ok(())
+    }
+
+    /// removes the session from the index of the principal
+    async fn unindex(&self, principal: Option<String>, id: &str) {
+        if let Some(principal) = principal {
+            let index = (self.principal_keygen)(&principal);
+            if let Err(e) = send(&self.addr, srem(index, self.index_entry(id))).await {
+                warn!("failed to remove session from index: {}", e);
+            }
+        }
+    }
+
+    /// lists the live sessions of the principal, removing stale ones
+    async fn sessions(&self, principal: &str) -> Result<Vec<String>, Error> {
+        let index = (self.principal_keygen)(principal);
+        let ids: Vec<String> = send(&self.addr, smembers(index.clone()))
+            .await?
+            .into_iter()
+            .map(|id| String::from_utf8_lossy(&id).into_owned())
+            .collect();
+
+        let found = join_all(
+            ids.iter()
+                .map(|id| send(&self.addr, exists(self.entry_cache_key(id)))),
+        )
+        .await;
+
+        let mut live = Vec::with_capacity(ids.len());
+        let mut stale = Vec::new();
+        for (id, found) in ids.into_iter().zip(found) {
+            if found? > 0 {
+                live.push(id);
+            } else {
+                stale.push(id);
             }
         }
-    };
-}
 
-impl_methods!(RedisActor);
-impl_methods!(RedisClusterActor);
+        if !stale.is_empty() {
+            send(&self.addr, srem_multiple(index, stale.into_iter())).await?;
+        }
+        Ok(live)
+    }
+
+    /// removes all the sessions of the principal
+    async fn revoke(&self, principal: &str) -> Result<usize, Error> {
+        let ids = self.sessions(principal).await?;
+        let removed = join_all(
+            ids.iter()
+                .map(|id| send(&self.addr, del(self.entry_cache_key(id)))),
+        )
+        .await;
+        let mut count = 0;
+        for removed in removed {
+            count += removed? as usize;
+        }
+
+        send(&self.addr, del((self.principal_keygen)(principal))).await?;
+        Ok(count)
+    }
+
+    /// lists the keys matching `pattern` on every node
+    async fn scan(&self, pattern: &str) -> Result<Vec<Vec<u8>>, Error> {
+        let slots = R::nodes(&self.addr)
+            .await
+            .map_err(error::ErrorInternalServerError)?;
+        let scans = slots.into_iter().map(|slot| self.scan_node(slot, pattern));
+        let mut keys = Vec::new();
+        for node_keys in join_all(scans).await {
+            keys.extend(node_keys?);
+        }
+        Ok(keys)
+    }
+
+    /// lists the keys matching `pattern` on the node serving `slot`
+    async fn scan_node(&self, slot: u16, pattern: &str) -> Result<Vec<Vec<u8>>, Error> {
+        let mut keys = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let command = scan(cursor).pattern(pattern).count(SCAN_COUNT);
+            let (next, batch) = send(&self.addr, DirectedTo { command, slot }).await?;
+            keys.extend(batch);
+            if next == 0 {
+                return Ok(keys);
+            }
+            cursor = next;
+        }
+    }
+}
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::command::hset;
+    use crate::{Command, SessionStoreDegraded};
     use actix_session::Session;
+    use actix_web::cookie::{Cookie, CookieJar, Key};
+    use actix_web::http::StatusCode;
+    use actix_web::HttpMessage;
     use actix_web::{
         middleware, test, web,
         web::{get, post, resource},
-        App, HttpResponse, Result,
+        App, HttpRequest, HttpResponse, Result,
     };
     use serde::{Deserialize, Serialize};
     use serde_json::json;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use time::OffsetDateTime;
 
     #[derive(Serialize, Deserialize, Debug, PartialEq)]
     pub struct IndexResponse {
@@ -521,6 +1281,872 @@ mod test {
         test_workflow_helper(srv_cluster).await;
     }
 
+    #[actix_rt::test]
+    async fn test_workflow_hash() {
+        let srv = test::start(|| {
+            App::new()
+                .wrap(
+                    RedisSession::new("127.0.0.1:6379", &[0; 32])
+                        .cookie_name("test-session")
+                        .hash_storage(true),
+                )
+                .wrap(middleware::Logger::default())
+                .service(resource("/").route(get().to(index)))
+                .service(resource("/do_something").route(post().to(do_something)))
+                .service(resource("/login").route(post().to(login)))
+                .service(resource("/logout").route(post().to(logout)))
+        });
+        test_workflow_helper(srv).await;
+    }
+
+    #[actix_rt::test]
+    async fn test_hash_storage_partial_write() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let addr2 = addr.clone();
+        let mut app = test::init_service(
+            App::new()
+                .wrap(
+                    RedisSession::from_redis(addr.clone(), &[0; 32])
+                        .cookie_name("test-session")
+                        .hash_storage(true),
+                )
+                .service(resource("/do_something").route(post().to(do_something)))
+                .service(resource("/concurrent").route(post().to(
+                    move |req: HttpRequest, session: Session| {
+                        let addr = addr2.clone();
+                        async move {
+                            // another request changes the counter meanwhile
+                            let cookie = req.cookie("test-session").unwrap();
+                            let value = &cookie.value()[cookie.value().len() - 32..];
+                            let cachekey = format!("session:{}", value);
+                            addr.send(hset(cachekey, "counter", "10"))
+                                .await
+                                .unwrap()
+                                .unwrap();
+
+                            session.set("user_id", "ferris")?;
+                            session.remove("missing");
+                            Ok::<_, Error>(HttpResponse::Ok().finish())
+                        }
+                    },
+                ))),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/do_something").to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+
+        let req = test::TestRequest::post()
+            .uri("/concurrent")
+            .cookie(cookie.clone())
+            .to_request();
+        test::call_service(&mut app, req).await;
+
+        // both changes are kept
+        let req = test::TestRequest::post()
+            .uri("/do_something")
+            .cookie(cookie)
+            .to_request();
+        let res: IndexResponse = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            res,
+            IndexResponse {
+                user_id: Some("ferris".into()),
+                counter: 11
+            }
+        );
+    }
+
+    #[cfg(feature = "encryption")]
+    #[actix_rt::test]
+    async fn test_encryption() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        for &(hash_storage, concurrency) in &[
+            (false, None),
+            (false, Some(ConflictStrategy::Merge)),
+            (true, None),
+            (true, Some(ConflictStrategy::Reject)),
+        ] {
+            let app = |key_ring| {
+                let mut session = RedisSession::from_redis(addr.clone(), &[0; 32])
+                    .cookie_name("test-session")
+                    .hash_storage(hash_storage)
+                    .encryption(key_ring)
+                    .hmac_cache_keys(b"secret");
+                if let Some(strategy) = concurrency {
+                    session = session.optimistic_concurrency(strategy);
+                }
+                App::new()
+                    .wrap(session)
+                    .service(resource("/do_something").route(post().to(do_something)))
+            };
+            let mut srv = test::init_service(app(KeyRing::new(1, &[1; 32]))).await;
+
+            let req = test::TestRequest::post().uri("/do_something").to_request();
+            let res = test::call_service(&mut srv, req).await;
+            let cookie = res.response().cookies().next().unwrap().into_owned();
+            let id = &cookie.value()[cookie.value().len() - 32..];
+
+            // the key does not reveal the session id, nor the value its state
+            let cachekey = format!("session:{}", hmac_hex(b"secret", id.as_bytes()));
+            let found = addr.send(exists(format!("session:{}", id))).await;
+            assert_eq!(found.unwrap().unwrap(), 0);
+            if hash_storage {
+                let fields = addr.send(hgetall(cachekey)).await.unwrap().unwrap();
+                let (_, counter) = fields
+                    .iter()
+                    .find(|(field, _)| field == b"counter")
+                    .unwrap();
+                assert_ne!(counter, b"1");
+            } else {
+                let value = addr.send(crate::command::get(cachekey)).await;
+                let value = value.unwrap().unwrap().unwrap();
+                assert!(!String::from_utf8_lossy(&value).contains("counter"));
+            }
+
+            // sessions encrypted with a previous key are still loaded
+            let key_ring = KeyRing::new(2, &[2; 32]).previous_key(1, &[1; 32]);
+            let mut rotated = test::init_service(app(key_ring)).await;
+            let req = test::TestRequest::post()
+                .uri("/do_something")
+                .cookie(cookie.clone())
+                .to_request();
+            let res: IndexResponse = test::read_response_json(&mut rotated, req).await;
+            assert_eq!(res.counter, 2);
+
+            let req = test::TestRequest::post()
+                .uri("/do_something")
+                .cookie(cookie.clone())
+                .to_request();
+            let res: IndexResponse = test::read_response_json(&mut rotated, req).await;
+            assert_eq!(res.counter, 3);
+
+            // but not once the key is removed
+            let mut removed = test::init_service(app(KeyRing::new(3, &[3; 32]))).await;
+            let req = test::TestRequest::post()
+                .uri("/do_something")
+                .cookie(cookie)
+                .to_request();
+            let res: IndexResponse = test::read_response_json(&mut removed, req).await;
+            assert_eq!(res.counter, 1);
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[actix_rt::test]
+    async fn test_plaintext_migration() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let app = |session: RedisSession| {
+            App::new()
+                .wrap(session.cookie_name("test-session"))
+                .service(resource("/do_something").route(post().to(do_something)))
+        };
+        let plain = RedisSession::from_redis(addr.clone(), &[0; 32]);
+        let mut plain = test::init_service(app(plain)).await;
+        let req = test::TestRequest::post().uri("/do_something").to_request();
+        let res = test::call_service(&mut plain, req).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+
+        // plaintext sessions are rejected, unless migrating
+        let session = |allow_plaintext| {
+            let key_ring = KeyRing::new(1, &[1; 32]).allow_plaintext(allow_plaintext);
+            RedisSession::from_redis(addr.clone(), &[0; 32]).encryption(key_ring)
+        };
+        let mut strict = test::init_service(app(session(false))).await;
+        let mut migrating = test::init_service(app(session(true))).await;
+
+        let req = test::TestRequest::post()
+            .uri("/do_something")
+            .cookie(cookie.clone())
+            .to_request();
+        let res: IndexResponse = test::read_response_json(&mut strict, req).await;
+        assert_eq!(res.counter, 1);
+
+        let req = test::TestRequest::post()
+            .uri("/do_something")
+            .cookie(cookie)
+            .to_request();
+        let res: IndexResponse = test::read_response_json(&mut migrating, req).await;
+        assert_eq!(res.counter, 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_optimistic_concurrency() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let app = |strategy, hash_storage| {
+            let addr2 = addr.clone();
+            App::new()
+                .wrap(
+                    RedisSession::from_redis(addr.clone(), &[0; 32])
+                        .cookie_name("test-session")
+                        .hash_storage(hash_storage)
+                        .optimistic_concurrency(strategy),
+                )
+                .service(resource("/").route(get().to(index)))
+                .service(resource("/do_something").route(post().to(do_something)))
+                .service(resource("/concurrent").route(post().to(
+                    move |req: HttpRequest, session: Session| {
+                        let addr = addr2.clone();
+                        async move {
+                            // another request changes the counter meanwhile
+                            let cookie = req.cookie("test-session").unwrap();
+                            let value = &cookie.value()[cookie.value().len() - 32..];
+                            let cachekey = format!("session:{}", value);
+                            if hash_storage {
+                                let fields = vec![(VERSION_KEY, "3"), ("counter", "10")];
+                                addr.send(hset_multiple(cachekey, fields.into_iter()))
+                                    .await
+                                    .unwrap()
+                                    .unwrap();
+                            } else {
+                                let mut state = HashMap::new();
+                                state.insert("counter".to_owned(), "10".to_owned());
+                                let body = Codec::Json.encode(&state).unwrap();
+                                let body = codec::versioned(3, &body);
+                                addr.send(set(cachekey, body)).await.unwrap().unwrap();
+                            }
+
+                            session.set("user_id", "ferris")?;
+                            Ok::<_, Error>(HttpResponse::Ok().finish())
+                        }
+                    },
+                )))
+        };
+        let mut merge = test::init_service(app(ConflictStrategy::Merge, false)).await;
+        let mut reject = test::init_service(app(ConflictStrategy::Reject, true)).await;
+
+        for (app, merged) in [(&mut merge, true), (&mut reject, false)] {
+            let req = test::TestRequest::post().uri("/do_something").to_request();
+            let res = test::call_service(app, req).await;
+            let cookie = res.response().cookies().next().unwrap().into_owned();
+
+            // no conflict
+            let req = test::TestRequest::post()
+                .uri("/do_something")
+                .cookie(cookie.clone())
+                .to_request();
+            let res: IndexResponse = test::read_response_json(app, req).await;
+            assert_eq!(res.counter, 2);
+
+            let req = test::TestRequest::post()
+                .uri("/concurrent")
+                .cookie(cookie.clone())
+                .to_request();
+            if merged {
+                test::call_service(app, req).await;
+            } else {
+                let err = app.call(req).await.err().unwrap();
+                assert!(err.as_error::<SessionConflict>().is_some());
+                let status = err.as_response_error().status_code();
+                assert_eq!(status, StatusCode::CONFLICT);
+            }
+
+            // the concurrent change is kept
+            let req = test::TestRequest::get().cookie(cookie).to_request();
+            let res: IndexResponse = test::read_response_json(app, req).await;
+            assert_eq!(
+                res,
+                IndexResponse {
+                    user_id: if merged { Some("ferris".into()) } else { None },
+                    counter: 10
+                }
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_optimistic_concurrency_clear() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        for &hash_storage in &[false, true] {
+            let mut app = test::init_service(
+                App::new()
+                    .wrap(
+                        RedisSession::from_redis(addr.clone(), &[0; 32])
+                            .cookie_name("test-session")
+                            .hash_storage(hash_storage)
+                            .optimistic_concurrency(ConflictStrategy::Reject),
+                    )
+                    .service(resource("/").route(get().to(index)))
+                    .service(resource("/do_something").route(post().to(do_something)))
+                    .service(resource("/switch").route(post().to(
+                        |session: Session| async move {
+                            // the version is not part of the session state
+                            assert_eq!(session.entries().len(), 1);
+                            session.clear();
+                            session.set("user_id", "ferris")?;
+                            Ok::<_, Error>(HttpResponse::Ok().finish())
+                        },
+                    ))),
+            )
+            .await;
+
+            let req = test::TestRequest::post().uri("/do_something").to_request();
+            let res = test::call_service(&mut app, req).await;
+            let cookie = res.response().cookies().next().unwrap().into_owned();
+
+            let req = test::TestRequest::post()
+                .uri("/do_something")
+                .cookie(cookie.clone())
+                .to_request();
+            let res: IndexResponse = test::read_response_json(&mut app, req).await;
+            assert_eq!(res.counter, 2);
+
+            let req = test::TestRequest::post()
+                .uri("/switch")
+                .cookie(cookie.clone())
+                .to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+
+            let req = test::TestRequest::get().cookie(cookie).to_request();
+            let res: IndexResponse = test::read_response_json(&mut app, req).await;
+            assert_eq!(
+                res,
+                IndexResponse {
+                    user_id: Some("ferris".into()),
+                    counter: 0
+                }
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_key_rotation() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let app = |session: RedisSession| {
+            App::new()
+                .wrap(session.cookie_name("test-session"))
+                .service(resource("/").route(get().to(index)))
+                .service(resource("/do_something").route(post().to(do_something)))
+        };
+        let mut old_app =
+            test::init_service(app(RedisSession::from_redis(addr.clone(), &[1; 32])))
+                .await;
+        let mut new_app =
+            test::init_service(app(RedisSession::from_redis(addr.clone(), &[2; 32])
+                .previous_keys(&[&[1; 32]])))
+            .await;
+        let mut private_app =
+            test::init_service(app(
+                RedisSession::from_redis(addr.clone(), &[2; 32]).cookie_private(true)
+            ))
+            .await;
+
+        let req = test::TestRequest::post().uri("/do_something").to_request();
+        let res = test::call_service(&mut old_app, req).await;
+        let old_cookie = res.response().cookies().next().unwrap().into_owned();
+
+        // the session is kept and its cookie is signed with the new key
+        let req = test::TestRequest::get()
+            .cookie(old_cookie.clone())
+            .to_request();
+        let res = test::call_service(&mut new_app, req).await;
+        let new_cookie = res.response().cookies().next().unwrap().into_owned();
+        assert_ne!(new_cookie.value(), old_cookie.value());
+        let body: IndexResponse = test::read_body_json(res).await;
+        assert_eq!(body.counter, 1);
+
+        let req = test::TestRequest::post()
+            .uri("/do_something")
+            .cookie(new_cookie.clone())
+            .to_request();
+        let res = test::call_service(&mut new_app, req).await;
+        assert_eq!(res.response().cookies().count(), 0);
+        let body: IndexResponse = test::read_body_json(res).await;
+        assert_eq!(body.counter, 2);
+
+        // the signed cookie is replaced by a private one
+        let req = test::TestRequest::get()
+            .cookie(new_cookie.clone())
+            .to_request();
+        let res = test::call_service(&mut private_app, req).await;
+        let private_cookie = res.response().cookies().next().unwrap().into_owned();
+        assert!(!private_cookie.value().contains(&new_cookie.value()[44..]));
+        let body: IndexResponse = test::read_body_json(res).await;
+        assert_eq!(body.counter, 2);
+
+        let req = test::TestRequest::get().cookie(private_cookie).to_request();
+        let res = test::call_service(&mut private_app, req).await;
+        assert_eq!(res.response().cookies().count(), 0);
+        let body: IndexResponse = test::read_body_json(res).await;
+        assert_eq!(body.counter, 2);
+
+        // unknown keys are rejected
+        let req = test::TestRequest::get().cookie(new_cookie).to_request();
+        let res = test::call_service(&mut old_app, req).await;
+        let body: IndexResponse = test::read_body_json(res).await;
+        assert_eq!(body.counter, 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_fail_open() {
+        let addr = RedisActor::start("localhost:54000");
+        let app = |fail_open| {
+            App::new()
+                .wrap(
+                    RedisSession::from_redis(addr.clone(), &[0; 32])
+                        .cookie_name("test-session")
+                        .fail_open(fail_open),
+                )
+                .service(resource("/").route(get().to(
+                    |req: HttpRequest, session: Session| async move {
+                        session.set("counter", 1)?;
+                        let degraded =
+                            req.extensions().contains::<SessionStoreDegraded>();
+                        Ok::<_, Error>(HttpResponse::Ok().json(degraded))
+                    },
+                )))
+        };
+        let mut fail_open = test::init_service(app(true)).await;
+        let mut fail_closed = test::init_service(app(false)).await;
+
+        let mut jar = CookieJar::new();
+        jar.signed(&Key::derive_from(&[0; 32]))
+            .add(Cookie::new("test-session", "a".repeat(32)));
+        let cookie = jar.get("test-session").unwrap().clone();
+
+        // the session cannot be loaded
+        let req = test::TestRequest::get().cookie(cookie.clone()).to_request();
+        let res = test::call_service(&mut fail_open, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.response().cookies().count(), 0);
+        let degraded: bool = test::read_body_json(res).await;
+        assert!(degraded);
+
+        let req = test::TestRequest::get().cookie(cookie).to_request();
+        let err = fail_closed.call(req).await.err().unwrap();
+        let status = err.as_response_error().status_code();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+        // the session cannot be written
+        let req = test::TestRequest::get().to_request();
+        let res = test::call_service(&mut fail_open, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.response().cookies().count(), 0);
+        let degraded: bool = test::read_body_json(res).await;
+        assert!(!degraded);
+
+        let req = test::TestRequest::get().to_request();
+        let err = fail_closed.call(req).await.err().unwrap();
+        let status = err.as_response_error().status_code();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[actix_rt::test]
+    async fn test_lazy_load() {
+        let addr = RedisActor::start("localhost:54000");
+        let app = |lazy_load| {
+            App::new()
+                .wrap(
+                    RedisSession::from_redis(addr.clone(), &[0; 32])
+                        .cookie_name("test-session")
+                        .lazy_load(lazy_load),
+                )
+                .service(resource("/").route(get().to(|| async { "static" })))
+        };
+        let mut lazy = test::init_service(app(true)).await;
+        let mut eager = test::init_service(app(false)).await;
+
+        let mut jar = CookieJar::new();
+        jar.signed(&Key::derive_from(&[0; 32]))
+            .add(Cookie::new("test-session", "a".repeat(32)));
+        let cookie = jar.get("test-session").unwrap().clone();
+
+        // requests which do not use the session do not reach Redis
+        let req = test::TestRequest::get().cookie(cookie.clone()).to_request();
+        let res = test::call_service(&mut lazy, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.response().cookies().count(), 0);
+
+        let req = test::TestRequest::get().cookie(cookie).to_request();
+        let err = eager.call(req).await.err().unwrap();
+        let status = err.as_response_error().status_code();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[actix_rt::test]
+    async fn test_sliding_ttl() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let session = |threshold| {
+            RedisSession::from_redis(addr.clone(), &[0; 32])
+                .cookie_name("test-session")
+                .ttl(100)
+                .sliding_ttl(threshold)
+        };
+        let mut app = test::init_service(
+            App::new()
+                .wrap(session(0.0))
+                .service(resource("/").route(get().to(index)))
+                .service(resource("/do_something").route(post().to(do_something))),
+        )
+        .await;
+        let mut app_threshold = test::init_service(
+            App::new()
+                .wrap(session(0.5))
+                .service(resource("/").route(get().to(index))),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/do_something").to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = res
+            .response()
+            .cookies()
+            .find(|c| c.name() == "test-session")
+            .unwrap()
+            .into_owned();
+        // the signed cookie ends with the session id
+        let value = &cookie.value()[cookie.value().len() - 32..];
+        let cachekey = format!("session:{}", value);
+
+        // less than half of the TTL elapsed: nothing to do
+        addr.send(expire(cachekey.clone(), 60))
+            .await
+            .unwrap()
+            .unwrap();
+        let req = test::TestRequest::get().cookie(cookie.clone()).to_request();
+        let res = test::call_service(&mut app_threshold, req).await;
+        assert_eq!(res.response().cookies().count(), 0);
+        let remaining = addr.send(ttl(cachekey.clone())).await.unwrap().unwrap();
+        assert!(remaining <= 60);
+
+        // more than half of the TTL elapsed: the TTL and the cookie are refreshed
+        addr.send(expire(cachekey.clone(), 40))
+            .await
+            .unwrap()
+            .unwrap();
+        let req = test::TestRequest::get().cookie(cookie.clone()).to_request();
+        let res = test::call_service(&mut app_threshold, req).await;
+        let refreshed = res
+            .response()
+            .cookies()
+            .find(|c| c.name() == "test-session")
+            .unwrap();
+        assert_eq!(refreshed.value(), cookie.value());
+        assert_eq!(refreshed.max_age(), Some(Duration::days(7)));
+        let remaining = addr.send(ttl(cachekey.clone())).await.unwrap().unwrap();
+        assert!(remaining > 60);
+
+        // no threshold: refreshed on every access
+        addr.send(expire(cachekey.clone(), 90))
+            .await
+            .unwrap()
+            .unwrap();
+        let req = test::TestRequest::get().cookie(cookie.clone()).to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.response().cookies().count(), 1);
+        let remaining = addr.send(ttl(cachekey)).await.unwrap().unwrap();
+        assert!(remaining > 90);
+    }
+
+    #[actix_rt::test]
+    async fn test_session_lifetime() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let mut app = test::init_service(
+            App::new()
+                .wrap(
+                    RedisSession::from_redis(addr.clone(), &[0; 32])
+                        .cookie_name("test-session")
+                        .ttl(100),
+                )
+                .service(resource("/").route(get().to(index)))
+                .service(resource("/remember").to(|session: Session| async move {
+                    session
+                        .set_lifetime(SessionLifetime::Persistent(Duration::days(30)))?;
+                    Ok::<_, Error>(HttpResponse::Ok().finish())
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::get().to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+        assert_eq!(cookie.max_age(), Some(Duration::days(7)));
+        let cachekey =
+            format!("session:{}", &cookie.value()[cookie.value().len() - 32..]);
+        let remaining = addr.send(ttl(cachekey.clone())).await.unwrap().unwrap();
+        assert!(remaining <= 100);
+
+        // the session key is unchanged, but the cookie is issued again
+        let req = test::TestRequest::get()
+            .uri("/remember")
+            .cookie(cookie.clone())
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        let remembered = res.response().cookies().next().unwrap().into_owned();
+        assert_eq!(remembered.value(), cookie.value());
+        assert_eq!(remembered.max_age(), Some(Duration::days(30)));
+        let remaining = addr.send(ttl(cachekey)).await.unwrap().unwrap();
+        assert!(remaining > Duration::days(29).whole_seconds());
+    }
+
+    #[actix_rt::test]
+    async fn test_session_admin() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let session = RedisSession::from_redis(addr, &[0; 32])
+            .cookie_name("test-session")
+            .cache_keygen(Box::new(|key: &str| format!("admin-session:{}", key)));
+        let admin = session.admin().pattern("admin-session:*");
+        for key in admin.keys().await.unwrap() {
+            assert!(admin.delete(&key).await.unwrap());
+        }
+        assert_eq!(admin.count().await.unwrap(), 0);
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap(session)
+                .service(resource("/do_something").route(post().to(do_something))),
+        )
+        .await;
+
+        let mut cookies = Vec::new();
+        for _ in 0..2 {
+            let req = test::TestRequest::post().uri("/do_something").to_request();
+            let res = test::call_service(&mut app, req).await;
+            cookies.push(res.response().cookies().next().unwrap().into_owned());
+        }
+        let cookie = cookies.pop().unwrap();
+        let id = &cookie.value()[cookie.value().len() - 32..];
+        let key = admin.cache_key(id);
+        assert_eq!(key, format!("admin-session:{}", id));
+
+        let keys = admin.keys().await.unwrap();
+        assert_eq!(keys.len(), 2);
+        assert!(keys.contains(&key));
+
+        let info = admin.inspect(&key).await.unwrap().unwrap();
+        assert_eq!(info.state.get("counter").unwrap(), "1");
+        let ttl = info.ttl.unwrap();
+        assert!(Duration::zero() < ttl && ttl <= Duration::seconds(7200));
+
+        assert!(admin.expire(&key, 10).await.unwrap());
+        let info = admin.inspect(&key).await.unwrap().unwrap();
+        assert!(info.ttl.unwrap() <= Duration::seconds(10));
+
+        assert!(admin.delete(&key).await.unwrap());
+        assert!(!admin.delete(&key).await.unwrap());
+        assert!(!admin.expire(&key, 10).await.unwrap());
+        assert_eq!(admin.inspect(&key).await.unwrap(), None);
+        assert_eq!(admin.count().await.unwrap(), 1);
+
+        // the client of the removed session gets a new one
+        let req = test::TestRequest::post()
+            .uri("/do_something")
+            .cookie(cookie)
+            .to_request();
+        let res: IndexResponse = test::read_response_json(&mut app, req).await;
+        assert_eq!(res.counter, 1);
+        assert_eq!(admin.count().await.unwrap(), 2);
+    }
+
+    /// Forwards the commands to Redis, counting them.
+    struct Proxy {
+        redis: Addr<RedisActor>,
+        commands: Rc<Cell<usize>>,
+    }
+
+    impl Actor for Proxy {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Command> for Proxy {
+        type Result = ResponseFuture<Result<RespValue, crate::Error>>;
+
+        fn handle(&mut self, msg: Command, _: &mut Self::Context) -> Self::Result {
+            self.commands.set(self.commands.get() + 1);
+            let res = self.redis.send(msg);
+            Box::pin(async move { res.await.unwrap_or(Err(crate::Error::Disconnected)) })
+        }
+    }
+
+    impl SessionBackend for Proxy {
+        fn execute(
+            addr: &Addr<Self>,
+            _: u16,
+            command: RespValue,
+        ) -> LocalBoxFuture<'static, Result<RespValue, crate::Error>> {
+            let res = addr.send(Command(command));
+            async move { res.await.unwrap_or(Err(crate::Error::Disconnected)) }
+                .boxed_local()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_session_backend() {
+        let commands = Rc::new(Cell::new(0));
+        let proxy = Proxy {
+            redis: RedisActor::start("127.0.0.1:6379"),
+            commands: commands.clone(),
+        }
+        .start();
+        let session = RedisSession::from_backend(proxy, &[0; 32])
+            .cookie_name("test-session")
+            .cache_keygen(Box::new(|key: &str| format!("proxy-session:{}", key)));
+        let admin = session.admin().pattern("proxy-session:*");
+        let mut app = test::init_service(
+            App::new()
+                .wrap(session)
+                .service(resource("/do_something").route(post().to(do_something))),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/do_something").to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+        let req = test::TestRequest::post()
+            .uri("/do_something")
+            .cookie(cookie.clone())
+            .to_request();
+        let res: IndexResponse = test::read_response_json(&mut app, req).await;
+        assert_eq!(res.counter, 2);
+        assert!(commands.get() > 0);
+
+        let id = &cookie.value()[cookie.value().len() - 32..];
+        assert!(admin.keys().await.unwrap().contains(&admin.cache_key(id)));
+    }
+
+    #[actix_rt::test]
+    async fn test_principal_index() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let alice = format!("alice-{}", rand::random::<u32>());
+        let bob = format!("bob-{}", rand::random::<u32>());
+        let session =
+            RedisSession::from_redis(addr.clone(), &[0; 32]).cookie_name("test-session");
+        let mut app = test::init_service(
+            App::new()
+                .wrap(session.clone())
+                .service(resource("/").route(get().to(index)))
+                .service(resource("/login/{user}").route(post().to(
+                    |user: web::Path<String>, session: Session| async move {
+                        session.set_principal(&user)?;
+                        session.set("user_id", user.into_inner())?;
+                        Ok::<_, Error>(HttpResponse::Ok().finish())
+                    },
+                ))),
+        )
+        .await;
+
+        let mut cookies = Vec::new();
+        for _ in 0..3 {
+            let req = test::TestRequest::post()
+                .uri(&format!("/login/{}", alice))
+                .to_request();
+            let res = test::call_service(&mut app, req).await;
+            let cookie = res.response().cookies().next().unwrap().into_owned();
+            cookies.push(cookie);
+        }
+        let req = test::TestRequest::post()
+            .uri(&format!("/login/{}", bob))
+            .to_request();
+        test::call_service(&mut app, req).await;
+
+        let ids = session.principal_sessions(&alice).await.unwrap();
+        assert_eq!(ids.len(), 3);
+        for cookie in &cookies {
+            assert!(ids.iter().any(|id| cookie.value().ends_with(id.as_str())));
+        }
+
+        // stale ids are dropped from the index
+        addr.send(del(format!("session:{}", ids[0])))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.count_principal_sessions(&alice).await.unwrap(), 2);
+        let index = format!("session-index:{}", alice);
+        let members = addr.send(smembers(index.clone())).await.unwrap().unwrap();
+        assert_eq!(members.len(), 2);
+
+        // log out everywhere
+        assert_eq!(session.revoke_principal_sessions(&alice).await.unwrap(), 2);
+        assert_eq!(session.count_principal_sessions(&alice).await.unwrap(), 0);
+        for cookie in cookies {
+            let req = test::TestRequest::get().cookie(cookie).to_request();
+            let res: IndexResponse = test::read_response_json(&mut app, req).await;
+            assert_eq!(res.user_id, None);
+        }
+        assert_eq!(session.count_principal_sessions(&bob).await.unwrap(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_purge_revoked() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let cachekey = format!("revoked-session:{}", rand::random::<u32>());
+        let keygen = cachekey.clone();
+        let session = RedisSession::from_redis(addr.clone(), &[0; 32])
+            .cookie_name("test-session")
+            .cache_keygen(Box::new(move |_| keygen.clone()));
+        let mut app = test::init_service(
+            App::new()
+                .wrap(session)
+                .service(resource("/do_something").route(post().to(do_something)))
+                .service(resource("/logout").route(post().to(
+                    move |session: Session| {
+                        let addr = addr.clone();
+                        let cachekey = cachekey.clone();
+                        async move {
+                            // the session is revoked during the request
+                            addr.send(del(cachekey)).await.unwrap().unwrap();
+                            session.purge();
+                            Ok::<_, Error>(HttpResponse::Ok().finish())
+                        }
+                    },
+                ))),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/do_something").to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+
+        let req = test::TestRequest::post()
+            .uri("/logout")
+            .cookie(cookie)
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let cookie = res.response().cookies().next().unwrap();
+        assert_eq!(cookie.value(), "");
+    }
+
+    #[cfg(feature = "encryption")]
+    #[actix_rt::test]
+    async fn test_principal_index_hmac() {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let user = format!("carol-{}", rand::random::<u32>());
+        let session = RedisSession::from_redis(addr.clone(), &[0; 32])
+            .cookie_name("test-session")
+            .hmac_cache_keys(b"secret");
+        let mut app = test::init_service(App::new().wrap(session.clone()).service(
+            resource("/login/{user}").route(post().to(
+                |user: web::Path<String>, session: Session| async move {
+                    session.set_principal(&user)?;
+                    Ok::<_, Error>(HttpResponse::Ok().finish())
+                },
+            )),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/login/{}", user))
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+        let id = &cookie.value()[cookie.value().len() - 32..];
+
+        // the index does not reveal the session ids
+        let index = format!("session-index:{}", user);
+        let members = addr.send(smembers(index)).await.unwrap().unwrap();
+        let cachekey = format!("session:{}", hmac_hex(b"secret", id.as_bytes()));
+        assert_eq!(members, vec![cachekey.clone().into_bytes()]);
+        assert!(members.iter().all(|member| member != id.as_bytes()));
+
+        let keys = session.principal_sessions(&user).await.unwrap();
+        assert_eq!(keys, vec![cachekey]);
+        assert_eq!(session.revoke_principal_sessions(&user).await.unwrap(), 1);
+        assert_eq!(session.count_principal_sessions(&user).await.unwrap(), 0);
+    }
+
     async fn test_workflow_helper(srv: test::TestServer) {
         // Step 1:  GET index
         //   - set-cookie actix-session will be in response (session cookie #1)
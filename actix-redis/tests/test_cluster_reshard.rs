@@ -0,0 +1,81 @@
+use actix_redis::reshard::{reshard_slot, ReshardProgress};
+use actix_redis::{command::*, RedisActor, RedisClusterActor};
+use std::fmt::Debug;
+use std::time::Duration;
+use tokio::time::delay_for;
+
+fn success<T: Debug, E1: Debug, E2: Debug>(res: Result<Result<T, E1>, E2>) -> T {
+    match res {
+        Ok(Ok(x)) => x,
+        _ => panic!("Should not happen {:?}", res),
+    }
+}
+
+#[actix_rt::test]
+async fn test_cluster_reshard() {
+    env_logger::init();
+
+    let addr = RedisClusterActor::start("127.0.0.1:7000");
+
+    let set = set("test-reshard", "value");
+    let slot = set.slot().unwrap();
+    success(addr.send(set).await);
+
+    let nodes = success(
+        addr.send(DirectedTo {
+            command: cluster_nodes(),
+            slot,
+        })
+        .await,
+    );
+    let masters: Vec<_> = nodes.into_iter().filter(ClusterNode::is_master).collect();
+
+    let source = masters
+        .iter()
+        .find(|node| {
+            node.slots
+                .iter()
+                .any(|&(start, end)| start <= slot && slot <= end)
+        })
+        .unwrap();
+    let destination = masters.iter().find(|node| node.id != source.id).unwrap();
+
+    let source = RedisActor::start(format!("{}:{}", source.host, source.port));
+    let destination =
+        RedisActor::start(format!("{}:{}", destination.host, destination.port));
+
+    let mut reports = vec![];
+    let migrated = reshard_slot(slot, &source, &destination, |progress| {
+        reports.push(progress)
+    })
+    .await
+    .unwrap();
+    assert!(migrated >= 1);
+    assert_eq!(reports[0], ReshardProgress::Importing);
+    assert_eq!(reports[1], ReshardProgress::Migrating);
+    assert_eq!(
+        reports.last(),
+        Some(&ReshardProgress::Finished { migrated })
+    );
+
+    // wait until the cluster agrees upon the new configuration
+    delay_for(Duration::from_secs(3)).await;
+
+    assert_eq!(
+        success(addr.send(get("test-reshard")).await).unwrap(),
+        b"value"
+    );
+
+    // resuming a finished migration is a no-op
+    assert_eq!(
+        reshard_slot(slot, &source, &destination, |_| ())
+            .await
+            .unwrap(),
+        0
+    );
+
+    // move the slot back
+    reshard_slot(slot, &destination, &source, |_| ())
+        .await
+        .unwrap();
+}
@@ -0,0 +1,87 @@
+use actix_redis::lock::{Lock, Redlock};
+use actix_redis::RedisActor;
+use std::time::Duration;
+
+#[actix_rt::test]
+async fn test_lock() {
+    let addr = RedisActor::start("127.0.0.1:6379");
+    let lock = Lock::new(addr.clone(), "test-lock")
+        .ttl(Duration::from_secs(5))
+        .retry_count(1)
+        .retry_delay(Duration::from_millis(10));
+
+    let guard = lock.acquire().await.unwrap().unwrap();
+
+    // the lock is exclusive
+    let other = Lock::new(addr, "test-lock").retry_count(0);
+    assert!(other.try_acquire().await.unwrap().is_none());
+
+    assert!(guard.extend(Duration::from_secs(10)).await.unwrap());
+    assert!(guard.release().await.unwrap());
+
+    // released locks can be acquired again
+    let guard = other.try_acquire().await.unwrap().unwrap();
+    assert!(guard.release().await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_lock_lost() {
+    let addr = RedisActor::start("127.0.0.1:6379");
+    let lock = Lock::new(addr, "test-lock-lost").ttl(Duration::from_millis(100));
+
+    let guard = lock.try_acquire().await.unwrap().unwrap();
+    actix_rt::time::delay_for(Duration::from_millis(200)).await;
+
+    // the lease expired, so neither extending nor releasing succeeds
+    assert!(!guard.extend(Duration::from_secs(1)).await.unwrap());
+    assert!(!guard.release().await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_redlock() {
+    let addrs = vec![
+        RedisActor::start("127.0.0.1:6379"),
+        RedisActor::start("127.0.0.1:6379"),
+        RedisActor::start("127.0.0.1:6379"),
+    ];
+
+    // the same server three times: only one instance accepts the lock
+    let lock = Redlock::new(addrs.clone(), "test-redlock").retry_count(0);
+    assert!(lock.try_acquire().await.unwrap().is_none());
+
+    let lock = Redlock::new(addrs[..1].to_vec(), "test-redlock");
+    let mut guard = lock.acquire().await.unwrap().unwrap();
+    assert!(guard.validity() > Duration::from_secs(29));
+    assert!(guard.validity() <= Duration::from_secs(30));
+
+    assert!(guard.extend(Duration::from_secs(60)).await.unwrap());
+    assert!(guard.validity() > Duration::from_secs(59));
+    assert!(guard.release().await.unwrap());
+}
+
+#[actix_rt::test]
+async fn test_redlock_validity_expires() {
+    let addr = RedisActor::start("127.0.0.1:6379");
+    let lock =
+        Redlock::new(vec![addr], "test-redlock-expires").ttl(Duration::from_millis(200));
+
+    let guard = lock.try_acquire().await.unwrap().unwrap();
+    let validity = guard.validity();
+    actix_rt::time::delay_for(Duration::from_millis(50)).await;
+    assert!(guard.validity() <= validity - Duration::from_millis(50));
+
+    actix_rt::time::delay_for(Duration::from_millis(200)).await;
+    assert_eq!(guard.validity(), Duration::from_secs(0));
+}
+
+#[test]
+fn test_lock_dropped_outside_system() {
+    let guard = actix_rt::System::new("test").block_on(async {
+        let addr = RedisActor::start("127.0.0.1:6379");
+        let lock = Lock::new(addr, "test-lock-dropped").ttl(Duration::from_millis(100));
+        lock.try_acquire().await.unwrap().unwrap()
+    });
+
+    // no actix system runs on this thread, the lock is left to expire
+    std::thread::spawn(move || drop(guard)).join().unwrap();
+}
@@ -1,7 +1,8 @@
 extern crate redis_async;
 
 use actix_redis::command::{get, set};
-use actix_redis::{Error, RedisActor};
+use actix_redis::{Error, GracefulShutdown, RedisActor};
+use std::time::Duration;
 
 #[actix_rt::test]
 async fn test_error_connect() {
@@ -38,3 +39,27 @@ async fn test_redis() {
         _ => panic!("Should not happen {:?}", res),
     }
 }
+
+#[actix_rt::test]
+async fn test_graceful_shutdown() {
+    let addr = RedisActor::start("127.0.0.1:6379");
+
+    let pending = addr.send(set("test-shutdown", "value"));
+    addr.send(GracefulShutdown {
+        timeout: Duration::from_secs(1),
+    })
+    .await
+    .unwrap();
+
+    // the command sent before the shutdown is completed
+    match pending.await {
+        Ok(Ok(true)) => (),
+        res => panic!("Should not happen {:?}", res),
+    }
+
+    // new commands are refused
+    match addr.send(get("test-shutdown")).await {
+        Ok(Err(Error::NotConnected)) => (),
+        res => panic!("Should not happen {:?}", res),
+    }
+}
@@ -4,7 +4,9 @@
 //! uses cookies as session storage. `CookieSession` creates sessions
 //! which are limited to storing fewer than 4000 bytes of data, as the payload
 //! must fit into a single cookie. An internal server error is generated if a
-//! session contains more than 4000 bytes.
+//! session contains more than 4000 bytes, unless the session is compressed or
+//! spread over several cookies, see
+//! [`CookieSession::chunked`](struct.CookieSession.html#method.chunked).
 //!
 //! A cookie may have a security policy of *signed* or *private*. Each has
 //! a respective `CookieSession` constructor.
@@ -16,20 +18,17 @@
 //! for cookie session - when this value is changed, all session data is lost.
 
 use std::collections::HashMap;
-use std::rc::Rc;
-use std::task::{Context, Poll};
 
 use actix_service::{Service, Transform};
-use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use actix_web::cookie::SameSite;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
-use actix_web::http::{header::SET_COOKIE, HeaderValue};
-use actix_web::{Error, HttpMessage, ResponseError};
+use actix_web::{Error, ResponseError};
 use derive_more::{Display, From};
-use futures_util::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use futures_util::future::{ok, ready, FutureExt, LocalBoxFuture, Ready};
 use serde_json::error::Error as JsonError;
-use time::{Duration, OffsetDateTime};
+use time::Duration;
 
-use crate::{Session, SessionStatus};
+use crate::{SessionChanges, SessionStore, StoreSession, StoreSessionMiddleware};
 
 /// Errors that can occur during handling cookie session
 #[derive(Debug, From, Display)]
@@ -44,130 +43,132 @@ pub enum CookieSessionError {
 
 impl ResponseError for CookieSessionError {}
 
-enum CookieSecurity {
-    Signed,
-    Private,
+/// Keep the whole session state in the session cookie.
+///
+/// The key of a session is its serialized state, so that the key changes with the
+/// state and nothing is stored server side. Used by
+/// [`CookieSession`](struct.CookieSession.html).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CookieSessionStore {
+    compress: bool,
+    chunked: bool,
 }
 
-struct CookieSessionInner {
-    key: Key,
-    security: CookieSecurity,
-    name: String,
-    path: String,
-    domain: Option<String>,
-    lazy: bool,
-    secure: bool,
-    http_only: bool,
-    max_age: Option<Duration>,
-    expires_in: Option<Duration>,
-    same_site: Option<SameSite>,
-}
+/// Tag of a compressed state, followed by the base64 encoded deflated state.
+#[cfg(feature = "cookie-compression")]
+const COMPRESSED: char = '~';
 
-impl CookieSessionInner {
-    fn new(key: &[u8], security: CookieSecurity) -> CookieSessionInner {
-        CookieSessionInner {
-            security,
-            key: Key::derive_from(key),
-            name: "actix-session".to_owned(),
-            path: "/".to_owned(),
-            domain: None,
-            lazy: false,
-            secure: true,
-            http_only: true,
-            max_age: None,
-            expires_in: None,
-            same_site: None,
-        }
+impl CookieSessionStore {
+    /// Compress the state with deflate, when it makes it smaller. Default is
+    /// `false`.
+    ///
+    /// Sessions issued before changing this option are still accepted. Avoid it with
+    /// private cookies holding secrets, see
+    /// [`CookieSession::compress`](struct.CookieSession.html#method.compress).
+    #[cfg(feature = "cookie-compression")]
+    pub fn compress(self, compress: bool) -> Self {
+        CookieSessionStore { compress, ..self }
     }
 
-    fn set_cookie<B>(
-        &self,
-        res: &mut ServiceResponse<B>,
-        state: impl Iterator<Item = (String, String)>,
-    ) -> Result<(), Error> {
-        let state: HashMap<String, String> = state.collect();
+    /// Do not limit the size of the state to a single cookie, for use with
+    /// [`StoreSession::cookie_chunked`](struct.StoreSession.html#method.cookie_chunked).
+    /// Default is `false`.
+    pub fn chunked(self, chunked: bool) -> Self {
+        CookieSessionStore { chunked, ..self }
+    }
 
-        if self.lazy && state.is_empty() {
-            return Ok(());
+    fn serialize(&self, state: &HashMap<String, String>) -> Result<String, Error> {
+        let mut value =
+            serde_json::to_string(state).map_err(CookieSessionError::Serialize)?;
+        if self.compress {
+            value = compress(value)?;
         }
-
-        let value =
-            serde_json::to_string(&state).map_err(CookieSessionError::Serialize)?;
-        if value.len() > 4064 {
+        if value.len() > 4064 && !self.chunked {
             return Err(CookieSessionError::Overflow.into());
         }
+        Ok(value)
+    }
 
-        let mut cookie = Cookie::new(self.name.clone(), value);
-        cookie.set_path(self.path.clone());
-        cookie.set_secure(self.secure);
-        cookie.set_http_only(self.http_only);
-
-        if let Some(ref domain) = self.domain {
-            cookie.set_domain(domain.clone());
-        }
-
-        if let Some(expires_in) = self.expires_in {
-            cookie.set_expires(OffsetDateTime::now_utc() + expires_in);
-        }
-
-        if let Some(max_age) = self.max_age {
-            cookie.set_max_age(max_age);
-        }
-
-        if let Some(same_site) = self.same_site {
-            cookie.set_same_site(same_site);
+    fn deserialize(value: &str) -> Option<HashMap<String, String>> {
+        #[cfg(feature = "cookie-compression")]
+        {
+            if value.starts_with(COMPRESSED) {
+                let state = decompress(&value[COMPRESSED.len_utf8()..])?;
+                return serde_json::from_slice(&state).ok();
+            }
         }
+        serde_json::from_str(value).ok()
+    }
+}
 
-        let mut jar = CookieJar::new();
+#[cfg(feature = "cookie-compression")]
+fn compress(value: String) -> Result<String, Error> {
+    use flate2::{write::DeflateEncoder, Compression};
+    use std::io::Write;
+
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(value.as_bytes())?;
+    let compressed = base64::encode_config(encoder.finish()?, base64::URL_SAFE_NO_PAD);
+    if compressed.len() + 1 < value.len() {
+        Ok(format!("{}{}", COMPRESSED, compressed))
+    } else {
+        Ok(value)
+    }
+}
 
-        match self.security {
-            CookieSecurity::Signed => jar.signed(&self.key).add(cookie),
-            CookieSecurity::Private => jar.private(&self.key).add(cookie),
-        }
+#[cfg(not(feature = "cookie-compression"))]
+fn compress(value: String) -> Result<String, Error> {
+    Ok(value)
+}
 
-        for cookie in jar.delta() {
-            let val = HeaderValue::from_str(&cookie.encoded().to_string())?;
-            res.headers_mut().append(SET_COOKIE, val);
-        }
+#[cfg(feature = "cookie-compression")]
+fn decompress(value: &str) -> Option<Vec<u8>> {
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+
+    let compressed = base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()?;
+    let mut decompressed = Vec::new();
+    DeflateDecoder::new(&compressed[..])
+        .read_to_end(&mut decompressed)
+        .ok()?;
+    Some(decompressed)
+}
 
-        Ok(())
+impl SessionStore for CookieSessionStore {
+    fn load<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Option<HashMap<String, String>>, Error>> {
+        ok(Self::deserialize(key)).boxed_local()
     }
 
-    /// invalidates session cookie
-    fn remove_cookie<B>(&self, res: &mut ServiceResponse<B>) -> Result<(), Error> {
-        let mut cookie = Cookie::named(self.name.clone());
-        cookie.set_value("");
-        cookie.set_max_age(Duration::zero());
-        cookie.set_expires(OffsetDateTime::now_utc() - Duration::days(365));
-
-        let val = HeaderValue::from_str(&cookie.to_string())?;
-        res.headers_mut().append(SET_COOKIE, val);
+    fn save(
+        &self,
+        state: HashMap<String, String>,
+    ) -> LocalBoxFuture<'_, Result<String, Error>> {
+        ready(self.serialize(&state)).boxed_local()
+    }
 
-        Ok(())
+    fn update(
+        &self,
+        _: String,
+        state: HashMap<String, String>,
+        _: SessionChanges,
+    ) -> LocalBoxFuture<'_, Result<String, Error>> {
+        ready(self.serialize(&state)).boxed_local()
     }
 
-    fn load(&self, req: &ServiceRequest) -> (bool, HashMap<String, String>) {
-        if let Ok(cookies) = req.cookies() {
-            for cookie in cookies.iter() {
-                if cookie.name() == self.name {
-                    let mut jar = CookieJar::new();
-                    jar.add_original(cookie.clone());
+    fn delete(&self, _: String) -> LocalBoxFuture<'_, Result<(), Error>> {
+        ok(()).boxed_local()
+    }
 
-                    let cookie_opt = match self.security {
-                        CookieSecurity::Signed => jar.signed(&self.key).get(&self.name),
-                        CookieSecurity::Private => {
-                            jar.private(&self.key).get(&self.name)
-                        }
-                    };
-                    if let Some(cookie) = cookie_opt {
-                        if let Ok(val) = serde_json::from_str(cookie.value()) {
-                            return (false, val);
-                        }
-                    }
-                }
-            }
-        }
-        (true, HashMap::new())
+    /// The cookie is always issued again, extending its expiration.
+    fn ttl<'a>(
+        &'a self,
+        _: &'a str,
+        _: &'a HashMap<String, String>,
+    ) -> LocalBoxFuture<'a, Result<bool, Error>> {
+        ok(true).boxed_local()
     }
 }
 
@@ -197,6 +198,9 @@ impl CookieSessionInner {
 /// By default all cookies are percent encoded, but certain symbols may
 /// cause troubles when reading cookie, if they are not properly percent encoded.
 ///
+/// `CookieSession` is a [`StoreSession`](struct.StoreSession.html) over the
+/// [`CookieSessionStore`](struct.CookieSessionStore.html).
+///
 /// # Example
 ///
 /// ```rust
@@ -211,75 +215,113 @@ impl CookieSessionInner {
 ///         .secure(true))
 ///     .service(web::resource("/").to(|| HttpResponse::Ok()));
 /// ```
-pub struct CookieSession(Rc<CookieSessionInner>);
+pub struct CookieSession(StoreSession<CookieSessionStore>);
 
 impl CookieSession {
     /// Construct new *signed* `CookieSessionBackend` instance.
     ///
     /// Panics if key length is less than 32 bytes.
     pub fn signed(key: &[u8]) -> CookieSession {
-        CookieSession(Rc::new(CookieSessionInner::new(
-            key,
-            CookieSecurity::Signed,
-        )))
+        CookieSession(StoreSession::new(CookieSessionStore::default(), key))
     }
 
     /// Construct new *private* `CookieSessionBackend` instance.
     ///
     /// Panics if key length is less than 32 bytes.
     pub fn private(key: &[u8]) -> CookieSession {
-        CookieSession(Rc::new(CookieSessionInner::new(
-            key,
-            CookieSecurity::Private,
-        )))
+        CookieSession(
+            StoreSession::new(CookieSessionStore::default(), key).cookie_private(true),
+        )
     }
 
-    /// Sets the `path` field in the session cookie being built.
-    pub fn path<S: Into<String>>(mut self, value: S) -> CookieSession {
-        Rc::get_mut(&mut self.0).unwrap().path = value.into();
+    /// Set the keys used before the current one, tried when the session cookie
+    /// cannot be verified with the current key.
+    ///
+    /// A session cookie verified with a previous key is issued again with the
+    /// current key, so that keys can be rotated without dropping sessions: deploy
+    /// the new key with the old one as a previous key, and remove the old key once
+    /// the cookies it protects have expired. Panics if a key is less than 32 bytes
+    /// long.
+    ///
+    /// ```rust
+    /// use actix_session::CookieSession;
+    ///
+    /// let session = CookieSession::private(&[2; 32]).previous_keys(&[&[1; 32]]);
+    /// ```
+    pub fn previous_keys(self, keys: &[&[u8]]) -> CookieSession {
+        CookieSession(self.0.previous_keys(keys))
+    }
+
+    /// Compress the session state with deflate before signing or encrypting it, when
+    /// it makes it smaller. Default is `false`.
+    ///
+    /// Sessions issued before changing this option are still accepted.
+    ///
+    /// # Security
+    ///
+    /// Encryption does not hide the length of the compressed state, which depends
+    /// on how much of it repeats. With a *private* cookie, a client able to get
+    /// chosen values stored next to a secret, e.g. a CSRF token, can guess the
+    /// secret byte by byte from the size of the cookie, as in the CRIME attack.
+    /// Only enable compression for private cookies whose state mixes no secret with
+    /// data controlled by the client.
+    #[cfg(feature = "cookie-compression")]
+    pub fn compress(mut self, value: bool) -> CookieSession {
+        let store = self.0.store_mut();
+        *store = store.compress(value);
         self
     }
 
+    /// Spread sessions larger than 4000 bytes over several cookies, named after the
+    /// session cookie: `actix-session.0`, `actix-session.1` and so on. Default is
+    /// `false`.
+    ///
+    /// See [`StoreSession::cookie_chunked`](struct.StoreSession.html#method.cookie_chunked).
+    pub fn chunked(mut self, value: bool) -> CookieSession {
+        let store = self.0.store_mut();
+        *store = store.chunked(value);
+        CookieSession(self.0.cookie_chunked(value))
+    }
+
+    /// Sets the `path` field in the session cookie being built.
+    pub fn path<S: Into<String>>(self, value: S) -> CookieSession {
+        CookieSession(self.0.cookie_path(value))
+    }
+
     /// Sets the `name` field in the session cookie being built.
-    pub fn name<S: Into<String>>(mut self, value: S) -> CookieSession {
-        Rc::get_mut(&mut self.0).unwrap().name = value.into();
-        self
+    pub fn name<S: Into<String>>(self, value: S) -> CookieSession {
+        CookieSession(self.0.cookie_name(value))
     }
 
     /// Sets the `domain` field in the session cookie being built.
-    pub fn domain<S: Into<String>>(mut self, value: S) -> CookieSession {
-        Rc::get_mut(&mut self.0).unwrap().domain = Some(value.into());
-        self
+    pub fn domain<S: Into<String>>(self, value: S) -> CookieSession {
+        CookieSession(self.0.cookie_domain(value))
     }
 
     /// When true, prevents adding session cookies to responses until
     /// the session contains data. Default is `false`.
     ///
     /// Useful when trying to comply with laws that require consent for setting cookies.
-    pub fn lazy(mut self, value: bool) -> CookieSession {
-        Rc::get_mut(&mut self.0).unwrap().lazy = value;
-        self
+    pub fn lazy(self, value: bool) -> CookieSession {
+        CookieSession(self.0.lazy(value))
     }
 
     /// Sets the `secure` field in the session cookie being built.
     ///
     /// If the `secure` field is set, a cookie will only be transmitted when the
     /// connection is secure - i.e. `https`
-    pub fn secure(mut self, value: bool) -> CookieSession {
-        Rc::get_mut(&mut self.0).unwrap().secure = value;
-        self
+    pub fn secure(self, value: bool) -> CookieSession {
+        CookieSession(self.0.cookie_secure(value))
     }
 
     /// Sets the `http_only` field in the session cookie being built.
-    pub fn http_only(mut self, value: bool) -> CookieSession {
-        Rc::get_mut(&mut self.0).unwrap().http_only = value;
-        self
+    pub fn http_only(self, value: bool) -> CookieSession {
+        CookieSession(self.0.cookie_http_only(value))
     }
 
     /// Sets the `same_site` field in the session cookie being built.
-    pub fn same_site(mut self, value: SameSite) -> CookieSession {
-        Rc::get_mut(&mut self.0).unwrap().same_site = Some(value);
-        self
+    pub fn same_site(self, value: SameSite) -> CookieSession {
+        CookieSession(self.0.cookie_same_site(value))
     }
 
     /// Sets the `max-age` field in the session cookie being built.
@@ -288,9 +330,8 @@ impl CookieSession {
     }
 
     /// Sets the `max-age` field in the session cookie being built.
-    pub fn max_age_time(mut self, value: time::Duration) -> CookieSession {
-        Rc::get_mut(&mut self.0).unwrap().max_age = Some(value);
-        self
+    pub fn max_age_time(self, value: time::Duration) -> CookieSession {
+        CookieSession(self.0.cookie_max_age(value))
     }
 
     /// Sets the `expires` field in the session cookie being built.
@@ -299,106 +340,93 @@ impl CookieSession {
     }
 
     /// Sets the `expires` field in the session cookie being built.
-    pub fn expires_in_time(mut self, value: Duration) -> CookieSession {
-        Rc::get_mut(&mut self.0).unwrap().expires_in = Some(value);
-        self
+    ///
+    /// The expiration is prolonged on every request.
+    pub fn expires_in_time(self, value: Duration) -> CookieSession {
+        CookieSession(self.0.cookie_expires_in(value).sliding_expiration(true))
+    }
+
+    /// Expire sessions which are not accessed for `value`, regardless of the
+    /// expiration of the cookie, so that a stolen cookie does not stay valid
+    /// forever.
+    ///
+    /// The cookie is issued again on every request, carrying the time of the last
+    /// access. See [`StoreSession::idle_timeout`](struct.StoreSession.html#method.idle_timeout).
+    pub fn idle_timeout(self, value: Duration) -> CookieSession {
+        CookieSession(self.0.idle_timeout(value))
+    }
+
+    /// Expire sessions `value` after their creation, however often they are
+    /// accessed. See
+    /// [`StoreSession::absolute_timeout`](struct.StoreSession.html#method.absolute_timeout).
+    pub fn absolute_timeout(self, value: Duration) -> CookieSession {
+        CookieSession(self.0.absolute_timeout(value))
+    }
+
+    /// Serve the session as `Session<N>` instead of `Session`, e.g. to keep an
+    /// admin session next to the user session. Each namespace needs its own
+    /// cookie name. See [`Session`](struct.Session.html#named-sessions).
+    pub fn namespace<N: 'static>(self) -> CookieSession {
+        CookieSession(self.0.namespace::<N>())
     }
 }
 
 impl<S, B: 'static> Transform<S> for CookieSession
 where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>>,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
     S::Future: 'static,
-    S::Error: 'static,
 {
     type Request = ServiceRequest;
     type Response = ServiceResponse<B>;
-    type Error = S::Error;
+    type Error = Error;
     type InitError = ();
     type Transform = CookieSessionMiddleware<S>;
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ok(CookieSessionMiddleware {
-            service,
-            inner: self.0.clone(),
-        })
+        self.0.new_transform(service)
     }
 }
 
 /// Cookie session middleware
-pub struct CookieSessionMiddleware<S> {
-    service: S,
-    inner: Rc<CookieSessionInner>,
-}
-
-impl<S, B: 'static> Service for CookieSessionMiddleware<S>
-where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>>,
-    S::Future: 'static,
-    S::Error: 'static,
-{
-    type Request = ServiceRequest;
-    type Response = ServiceResponse<B>;
-    type Error = S::Error;
-    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
-
-    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.service.poll_ready(cx)
-    }
-
-    /// On first request, a new session cookie is returned in response, regardless
-    /// of whether any session state is set.  With subsequent requests, if the
-    /// session state changes, then set-cookie is returned in response.  As
-    /// a user logs out, call session.purge() to set SessionStatus accordingly
-    /// and this will trigger removal of the session cookie in the response.
-    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
-        let inner = self.inner.clone();
-        let (is_new, state) = self.inner.load(&req);
-        let prolong_expiration = self.inner.expires_in.is_some();
-        Session::set_session(state, &mut req);
-
-        let fut = self.service.call(req);
-
-        async move {
-            fut.await.map(|mut res| {
-                match Session::get_changes(&mut res) {
-                    (SessionStatus::Changed, Some(state))
-                    | (SessionStatus::Renewed, Some(state)) => {
-                        res.checked_expr(|res| inner.set_cookie(res, state))
-                    }
-                    (SessionStatus::Unchanged, Some(state)) if prolong_expiration => {
-                        res.checked_expr(|res| inner.set_cookie(res, state))
-                    }
-                    (SessionStatus::Unchanged, _) =>
-                    // set a new session cookie upon first request (new client)
-                    {
-                        if is_new {
-                            let state: HashMap<String, String> = HashMap::new();
-                            res.checked_expr(|res| {
-                                inner.set_cookie(res, state.into_iter())
-                            })
-                        } else {
-                            res
-                        }
-                    }
-                    (SessionStatus::Purged, _) => {
-                        let _ = inner.remove_cookie(&mut res);
-                        res
-                    }
-                    _ => res,
-                }
-            })
-        }
-        .boxed_local()
-    }
-}
+pub type CookieSessionMiddleware<S> = StoreSessionMiddleware<S, CookieSessionStore>;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Session;
+    use actix_web::cookie::{Cookie, CookieJar, Key};
     use actix_web::{test, web, App};
     use bytes::Bytes;
+    use time::OffsetDateTime;
+
+    /// a session cookie holding `state`, with timestamps `created` and `accessed`
+    /// seconds ago
+    fn aged_cookie(
+        state: &[(&str, &str)],
+        created: Option<i64>,
+        accessed: Option<i64>,
+    ) -> Cookie<'static> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut state: HashMap<String, String> = state
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        if let Some(created) = created {
+            state.insert(crate::CREATED_KEY.to_owned(), (now - created).to_string());
+        }
+        if let Some(accessed) = accessed {
+            state.insert(crate::ACCESSED_KEY.to_owned(), (now - accessed).to_string());
+        }
+
+        let mut jar = CookieJar::new();
+        jar.signed(&Key::derive_from(&[0; 32])).add(Cookie::new(
+            "actix-session",
+            serde_json::to_string(&state).unwrap(),
+        ));
+        jar.get("actix-session").unwrap().clone()
+    }
 
     #[actix_rt::test]
     async fn cookie_session() {
@@ -567,4 +595,399 @@ mod tests {
 
         assert!(expires_2 - expires_1 >= Duration::seconds(1));
     }
+
+    #[actix_rt::test]
+    async fn session_timeouts() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(
+                    CookieSession::signed(&[0; 32])
+                        .secure(false)
+                        .idle_timeout(Duration::minutes(30))
+                        .absolute_timeout(Duration::hours(8)),
+                )
+                .service(web::resource("/").to(|ses: Session| async move {
+                    let counter: i32 = ses.get("counter").unwrap().unwrap_or(0);
+                    ses.set("counter", counter + 1).unwrap();
+                    format!("{}", counter + 1)
+                }))
+                .service(web::resource("/times").to(|ses: Session| async move {
+                    let now = OffsetDateTime::now_utc();
+                    let created = ses.created().map(|time| (now - time).whole_minutes());
+                    let accessed =
+                        ses.last_accessed().map(|time| (now - time).whole_minutes());
+                    format!("{:?} {:?}", created, accessed)
+                })),
+        )
+        .await;
+
+        // the timestamps are recorded in new sessions
+        let request = test::TestRequest::get().to_request();
+        let response = app.call(request).await.unwrap();
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        let request = test::TestRequest::with_uri("/times")
+            .cookie(cookie)
+            .to_request();
+        let body = test::read_response(&mut app, request).await;
+        assert_eq!(body, Bytes::from_static(b"Some(0) Some(0)"));
+
+        let cookie = aged_cookie(&[("counter", "5")], Some(3600), Some(600));
+        let request = test::TestRequest::with_uri("/times")
+            .cookie(cookie.clone())
+            .to_request();
+        let response = app.call(request).await.unwrap();
+        // the cookie is issued again with the time of the access
+        let refreshed = response.response().cookies().next().unwrap().into_owned();
+        let body = test::read_body(response).await;
+        assert_eq!(body, Bytes::from_static(b"Some(60) Some(10)"));
+        let request = test::TestRequest::with_uri("/times")
+            .cookie(refreshed)
+            .to_request();
+        let body = test::read_response(&mut app, request).await;
+        assert_eq!(body, Bytes::from_static(b"Some(60) Some(0)"));
+
+        let request = test::TestRequest::get().cookie(cookie).to_request();
+        let body = test::read_response(&mut app, request).await;
+        assert_eq!(body, Bytes::from_static(b"6"));
+
+        // idle for too long
+        let cookie = aged_cookie(&[("counter", "5")], Some(3600), Some(3600));
+        let request = test::TestRequest::get().cookie(cookie).to_request();
+        let body = test::read_response(&mut app, request).await;
+        assert_eq!(body, Bytes::from_static(b"1"));
+
+        // created too long ago
+        let cookie = aged_cookie(&[("counter", "5")], Some(9 * 3600), Some(60));
+        let request = test::TestRequest::get().cookie(cookie).to_request();
+        let body = test::read_response(&mut app, request).await;
+        assert_eq!(body, Bytes::from_static(b"1"));
+
+        // issued before enabling the timeouts
+        let cookie = aged_cookie(&[("counter", "5")], None, None);
+        let request = test::TestRequest::get().cookie(cookie).to_request();
+        let body = test::read_response(&mut app, request).await;
+        assert_eq!(body, Bytes::from_static(b"1"));
+    }
+
+    #[actix_rt::test]
+    async fn clear_keeps_timestamps() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(
+                    CookieSession::signed(&[0; 32])
+                        .secure(false)
+                        .absolute_timeout(Duration::hours(8)),
+                )
+                .service(web::resource("/login").to(|ses: Session| async move {
+                    ses.clear();
+                    ses.set("user", "ferris").unwrap();
+                    let keys: Vec<_> = ses.entries().keys().cloned().collect();
+                    format!("{:?}", keys)
+                }))
+                .service(web::resource("/created").to(|ses: Session| async move {
+                    let now = OffsetDateTime::now_utc();
+                    let created = ses.created().map(|time| (now - time).whole_minutes());
+                    format!("{:?}", created)
+                })),
+        )
+        .await;
+
+        // clearing the session does not extend its absolute timeout
+        let cookie = aged_cookie(&[("counter", "5")], Some(7 * 3600), Some(60));
+        let request = test::TestRequest::with_uri("/login")
+            .cookie(cookie)
+            .to_request();
+        let response = app.call(request).await.unwrap();
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        let body = test::read_body(response).await;
+        assert_eq!(body, Bytes::from_static(b"[\"user\"]"));
+
+        let request = test::TestRequest::with_uri("/created")
+            .cookie(cookie)
+            .to_request();
+        let body = test::read_response(&mut app, request).await;
+        assert_eq!(body, Bytes::from_static(b"Some(420)"));
+    }
+
+    fn big_session_app() -> App<
+        impl actix_service::ServiceFactory<
+            Config = (),
+            Request = ServiceRequest,
+            Response = ServiceResponse,
+            Error = Error,
+            InitError = (),
+        >,
+        actix_web::body::Body,
+    > {
+        App::new()
+            .service(web::resource("/").to(|ses: Session| async move {
+                let data: String = ses.get("data").unwrap().unwrap_or_default();
+                format!("{}", data.len())
+            }))
+            .service(web::resource("/{size}").to(
+                |ses: Session, size: web::Path<usize>| async move {
+                    ses.set("data", "x".repeat(*size))?;
+                    Ok::<_, Error>("set")
+                },
+            ))
+    }
+
+    #[actix_rt::test]
+    async fn chunked_cookie() {
+        let mut app = test::init_service(
+            big_session_app().wrap(CookieSession::signed(&[0; 32]).secure(false)),
+        )
+        .await;
+        let request = test::TestRequest::with_uri("/10000").to_request();
+        let err = app.call(request).await.err().unwrap();
+        assert_eq!(err.as_response_error().status_code(), 500);
+
+        let mut app = test::init_service(
+            big_session_app()
+                .wrap(CookieSession::signed(&[0; 32]).secure(false).chunked(true)),
+        )
+        .await;
+        let request = test::TestRequest::with_uri("/10000").to_request();
+        let response = app.call(request).await.unwrap();
+        let cookies: Vec<_> = response
+            .response()
+            .cookies()
+            .map(|c| c.into_owned())
+            .collect();
+        let mut names: Vec<_> = cookies.iter().map(|c| c.name()).collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            ["actix-session.0", "actix-session.1", "actix-session.2"]
+        );
+
+        let mut request = test::TestRequest::get();
+        for cookie in &cookies {
+            request = request.cookie(cookie.clone());
+        }
+        let body = test::read_response(&mut app, request.to_request()).await;
+        assert_eq!(body, Bytes::from_static(b"10000"));
+
+        // the chunks are only valid together
+        let request = test::TestRequest::get()
+            .cookie(cookies[0].clone())
+            .cookie(cookies[2].clone())
+            .to_request();
+        let body = test::read_response(&mut app, request).await;
+        assert_eq!(body, Bytes::from_static(b"0"));
+
+        // the leftover chunks are removed
+        let mut request = test::TestRequest::with_uri("/10");
+        for cookie in &cookies {
+            request = request.cookie(cookie.clone());
+        }
+        let response = app.call(request.to_request()).await.unwrap();
+        let mut cookies: Vec<_> = response
+            .response()
+            .cookies()
+            .map(|c| (c.name().to_owned(), c.value().is_empty()))
+            .collect();
+        cookies.sort();
+        assert_eq!(
+            cookies,
+            [
+                ("actix-session".to_owned(), false),
+                ("actix-session.0".to_owned(), true),
+                ("actix-session.1".to_owned(), true),
+                ("actix-session.2".to_owned(), true),
+            ]
+        );
+    }
+
+    #[cfg(feature = "cookie-compression")]
+    #[actix_rt::test]
+    async fn compressed_cookie() {
+        let mut app = test::init_service(
+            big_session_app().wrap(
+                CookieSession::private(&[0; 32])
+                    .secure(false)
+                    .compress(true),
+            ),
+        )
+        .await;
+        let request = test::TestRequest::with_uri("/10000").to_request();
+        let response = app.call(request).await.unwrap();
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        assert!(cookie.value().len() < 4000);
+
+        let request = test::TestRequest::get().cookie(cookie).to_request();
+        let body = test::read_response(&mut app, request).await;
+        assert_eq!(body, Bytes::from_static(b"10000"));
+
+        // uncompressed sessions are still accepted
+        let state = CookieSessionStore::default()
+            .serialize(
+                &vec![("data".to_owned(), "\"xx\"".to_owned())]
+                    .into_iter()
+                    .collect(),
+            )
+            .unwrap();
+        assert!(state.starts_with('{'));
+        assert_eq!(
+            CookieSessionStore::deserialize(&state).unwrap()["data"],
+            "\"xx\""
+        );
+    }
+
+    #[actix_rt::test]
+    async fn key_rotation() {
+        for &private in &[false, true] {
+            let session = |key: &[u8]| {
+                if private {
+                    CookieSession::private(key)
+                } else {
+                    CookieSession::signed(key)
+                }
+                .secure(false)
+            };
+            let mut old_app =
+                test::init_service(big_session_app().wrap(session(&[1; 32]))).await;
+            let mut new_app = test::init_service(
+                big_session_app().wrap(session(&[2; 32]).previous_keys(&[&[1; 32]])),
+            )
+            .await;
+            let mut next_app =
+                test::init_service(big_session_app().wrap(session(&[2; 32]))).await;
+
+            let request = test::TestRequest::with_uri("/10").to_request();
+            let response = test::call_service(&mut old_app, request).await;
+            let old_cookie = response.response().cookies().next().unwrap().into_owned();
+
+            // issued again with the current key
+            let request = test::TestRequest::get().cookie(old_cookie).to_request();
+            let response = test::call_service(&mut new_app, request).await;
+            let new_cookie = response.response().cookies().next().unwrap().into_owned();
+            let body = test::read_body(response).await;
+            assert_eq!(body, Bytes::from_static(b"10"));
+
+            let request = test::TestRequest::get()
+                .cookie(new_cookie.clone())
+                .to_request();
+            let response = test::call_service(&mut new_app, request).await;
+            assert_eq!(response.response().cookies().count(), 0);
+
+            let request = test::TestRequest::get().cookie(new_cookie).to_request();
+            let body = test::read_response(&mut next_app, request).await;
+            assert_eq!(body, Bytes::from_static(b"10"));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn named_sessions() {
+        struct Admin;
+
+        let mut app = test::init_service(
+            App::new()
+                .wrap(CookieSession::signed(&[0; 32]).secure(false))
+                .wrap(
+                    CookieSession::private(&[1; 32])
+                        .name("admin-session")
+                        .secure(false)
+                        .namespace::<Admin>(),
+                )
+                .service(web::resource("/login").to(
+                    |user: Session, admin: Session<Admin>| async move {
+                        user.set("id", "user")?;
+                        admin.set("id", "admin")?;
+                        Ok::<_, Error>("logged in")
+                    },
+                ))
+                .service(web::resource("/").to(
+                    |user: Session, admin: Session<Admin>| async move {
+                        let user: Option<String> = user.get("id")?;
+                        let admin: Option<String> = admin.get("id")?;
+                        Ok::<_, Error>(format!("{:?} {:?}", user, admin))
+                    },
+                )),
+        )
+        .await;
+
+        let request = test::TestRequest::with_uri("/login").to_request();
+        let response = test::call_service(&mut app, request).await;
+        let mut cookies: Vec<_> = response
+            .response()
+            .cookies()
+            .map(|c| c.into_owned())
+            .collect();
+        cookies.sort_by(|a, b| a.name().cmp(b.name()));
+        let names: Vec<_> = cookies.iter().map(|c| c.name()).collect();
+        assert_eq!(names, ["actix-session", "admin-session"]);
+
+        let request = test::TestRequest::get()
+            .cookie(cookies[0].clone())
+            .cookie(cookies[1].clone())
+            .to_request();
+        let body = test::read_response(&mut app, request).await;
+        assert_eq!(body, Bytes::from_static(b"Some(\"user\") Some(\"admin\")"));
+
+        // each session only trusts its own cookie
+        let request = test::TestRequest::get()
+            .cookie(cookies[0].clone())
+            .to_request();
+        let body = test::read_response(&mut app, request).await;
+        assert_eq!(body, Bytes::from_static(b"Some(\"user\") None"));
+    }
+
+    #[actix_rt::test]
+    async fn session_lifetime() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(CookieSession::signed(&[0; 32]).secure(false).max_age(3600))
+                .service(web::resource("/").to(|ses: Session| async move {
+                    ses.set("counter", ses.get::<i32>("counter")?.unwrap_or(0) + 1)?;
+                    let days = match ses.lifetime() {
+                        Some(crate::SessionLifetime::Persistent(lifetime)) => {
+                            lifetime.whole_days()
+                        }
+                        _ => 0,
+                    };
+                    Ok::<_, Error>(format!("{}", days))
+                }))
+                .service(web::resource("/login/{remember}").to(
+                    |ses: Session, remember: web::Path<bool>| async move {
+                        ses.set_lifetime(if *remember {
+                            crate::SessionLifetime::Persistent(Duration::days(30))
+                        } else {
+                            crate::SessionLifetime::BrowserSession
+                        })?;
+                        Ok::<_, Error>("logged in")
+                    },
+                )),
+        )
+        .await;
+
+        let request = test::TestRequest::get().to_request();
+        let response = app.call(request).await.unwrap();
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        assert_eq!(cookie.max_age(), Some(Duration::hours(1)));
+
+        let request = test::TestRequest::with_uri("/login/true")
+            .cookie(cookie)
+            .to_request();
+        let response = app.call(request).await.unwrap();
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        assert_eq!(cookie.max_age(), Some(Duration::days(30)));
+        assert!(cookie.expires().is_some());
+
+        // the lifetime is kept by the session
+        let request = test::TestRequest::get().cookie(cookie).to_request();
+        let response = app.call(request).await.unwrap();
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        assert_eq!(cookie.max_age(), Some(Duration::days(30)));
+        let body = test::read_body(response).await;
+        assert_eq!(body, Bytes::from_static(b"30"));
+
+        let request = test::TestRequest::with_uri("/login/false")
+            .cookie(cookie)
+            .to_request();
+        let response = app.call(request).await.unwrap();
+        let cookie = response.response().cookies().next().unwrap().into_owned();
+        assert_eq!(cookie.max_age(), None);
+        assert_eq!(cookie.expires(), None);
+    }
 }
@@ -0,0 +1,584 @@
+//! Protection against cross-site request forgery.
+//!
+//! [**Csrf**](struct.Csrf.html) rejects the requests with unsafe methods which do not
+//! carry the CSRF token of the client, in a header or in a form field. The token is
+//! obtained with the [`CsrfToken`](struct.CsrfToken.html) extractor, to be embedded in
+//! forms or sent by scripts.
+//!
+//! By default, the token is kept in the [`Session`](struct.Session.html) (synchronizer
+//! token pattern). [`Csrf::double_submit`](struct.Csrf.html#method.double_submit)
+//! keeps it in a cookie instead, which the request must repeat (double submit cookie
+//! pattern).
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::iter;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{CONTENT_TYPE, SET_COOKIE};
+use actix_web::http::{HeaderValue, Method, StatusCode};
+use actix_web::{error, Error, FromRequest, HttpMessage, HttpRequest, ResponseError};
+use bytes::BytesMut;
+use derive_more::Display;
+use futures_util::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use futures_util::stream::{self, StreamExt};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+
+use crate::Session;
+
+/// Reserved session key holding the CSRF token.
+const CSRF_KEY: &str = "actix-session.csrf";
+/// Maximum size of a form read for its CSRF token.
+const FORM_LIMIT: usize = 256 * 1024;
+
+/// Errors rejecting a request, rendered as `403 Forbidden`.
+#[derive(Debug, Display, Clone, Copy, PartialEq)]
+pub enum CsrfError {
+    /// The request carries no CSRF token.
+    #[display(fmt = "CSRF token is missing")]
+    MissingToken,
+    /// The CSRF token of the request is not the token of the client.
+    #[display(fmt = "CSRF token is invalid")]
+    InvalidToken,
+}
+
+impl ResponseError for CsrfError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+}
+
+/// The CSRF token of the client.
+///
+/// With the synchronizer token pattern, the token is created and kept in the
+/// session when first extracted; the session middleware must wrap
+/// [`Csrf`](struct.Csrf.html). With the double submit cookie pattern, the token is
+/// provided by the middleware.
+///
+/// ```rust
+/// use actix_session::CsrfToken;
+/// use actix_web::HttpResponse;
+///
+/// async fn form(token: CsrfToken) -> HttpResponse {
+///     HttpResponse::Ok().content_type("text/html").body(format!(
+///         r#"<form method="post"><input type="hidden" name="csrf_token" value="{}"></form>"#,
+///         token.as_str()
+///     ))
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    fn generate() -> Self {
+        let token = iter::repeat(())
+            .map(|()| OsRng.sample(Alphanumeric))
+            .take(32)
+            .collect();
+        CsrfToken(token)
+    }
+
+    /// The token, to embed in forms or to send in the header.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<CsrfToken, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if let Some(token) = req.extensions().get::<CsrfToken>() {
+            return ok(token.clone()).boxed_local();
+        }
+        let session = <Session>::from_request(req, payload);
+        async move {
+            let session = session.await?;
+            let token =
+                session.get_or_insert_with(CSRF_KEY, || CsrfToken::generate().0)?;
+            Ok(CsrfToken(token))
+        }
+        .boxed_local()
+    }
+}
+
+struct CsrfInner {
+    header: String,
+    field: String,
+    exempt: Vec<String>,
+    double_submit: bool,
+    cookie_name: String,
+    cookie_path: String,
+    cookie_domain: Option<String>,
+    cookie_secure: bool,
+    cookie_same_site: SameSite,
+}
+
+impl CsrfInner {
+    /// whether the request must carry the token
+    fn protects(&self, req: &ServiceRequest) -> bool {
+        let safe = matches!(
+            *req.method(),
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+        );
+        !safe && !self.exempt.iter().any(|path| exempts(path, req.path()))
+    }
+
+    /// the token of the cookie sent by the client
+    fn cookie_token(&self, req: &ServiceRequest) -> Option<CsrfToken> {
+        let cookies = req.cookies().ok()?;
+        let cookie = cookies.iter().find(|c| c.name() == self.cookie_name)?;
+        Some(CsrfToken(cookie.value().to_owned()))
+    }
+
+    /// the token submitted with the request, from the header or the form field;
+    /// the form is read and put back
+    async fn submitted(&self, req: &mut ServiceRequest) -> Option<String> {
+        if let Some(token) = req.headers().get(self.header.as_str()) {
+            return token.to_str().ok().map(str::to_owned);
+        }
+
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        if !matches!(content_type, Some(value) if value.starts_with("application/x-www-form-urlencoded"))
+        {
+            return None;
+        }
+
+        let mut payload = req.take_payload();
+        let mut body = BytesMut::new();
+        while let Some(chunk) = payload.next().await {
+            body.extend_from_slice(&chunk.ok()?);
+            if body.len() > FORM_LIMIT {
+                return None;
+            }
+        }
+        let body = body.freeze();
+        let mut fields: HashMap<String, String> =
+            serde_urlencoded::from_bytes(&body).unwrap_or_default();
+        req.set_payload(Payload::Stream(stream::once(ok(body)).boxed_local()));
+        fields.remove(&self.field)
+    }
+
+    /// the token expected from the client, kept in the session; failing to load the
+    /// session fails the request
+    async fn expected(
+        req: ServiceRequest,
+    ) -> Result<(ServiceRequest, Option<String>), Error> {
+        let (req, mut payload) = req.into_parts();
+        let session = <Session>::from_request(&req, &mut payload).await?;
+        let token = session.get::<String>(CSRF_KEY).ok().flatten();
+        match ServiceRequest::from_parts(req, payload) {
+            Ok(req) => Ok((req, token)),
+            Err(_) => Err(error::ErrorInternalServerError("request is shared")),
+        }
+    }
+
+    fn set_cookie<B>(
+        &self,
+        res: &mut ServiceResponse<B>,
+        token: &CsrfToken,
+    ) -> Result<(), Error> {
+        let mut cookie = Cookie::new(self.cookie_name.clone(), token.0.clone());
+        cookie.set_path(self.cookie_path.clone());
+        cookie.set_secure(self.cookie_secure);
+        cookie.set_same_site(self.cookie_same_site);
+        if let Some(ref domain) = self.cookie_domain {
+            cookie.set_domain(domain.clone());
+        }
+
+        let val = HeaderValue::from_str(&cookie.encoded().to_string())?;
+        res.headers_mut().append(SET_COOKIE, val);
+        Ok(())
+    }
+}
+
+/// whether the exempt `prefix` covers `path`, segment-wise
+fn exempts(prefix: &str, path: &str) -> bool {
+    path.starts_with(prefix)
+        && (prefix.ends_with('/')
+            || path.len() == prefix.len()
+            || path[prefix.len()..].starts_with('/'))
+}
+
+/// compares tokens in constant time
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Middleware rejecting cross-site requests.
+///
+/// Requests with an unsafe method, i.e. other than `GET`, `HEAD`, `OPTIONS` and
+/// `TRACE`, must carry the [`CsrfToken`](struct.CsrfToken.html) of the client in the
+/// `x-csrf-token` header, or in the `csrf_token` field of an urlencoded form;
+/// other requests fail with a [`CsrfError`](enum.CsrfError.html).
+///
+/// With the default synchronizer token pattern, the token is kept in the session,
+/// so the session middleware must wrap `Csrf`, i.e. be registered after it.
+///
+/// # Example
+///
+/// ```rust
+/// use actix_session::{CookieSession, Csrf};
+/// use actix_web::{web, App, HttpResponse};
+///
+/// let app = App::new()
+///     .wrap(Csrf::new().exempt("/webhooks"))
+///     .wrap(CookieSession::signed(&[0; 32]))
+///     .service(web::resource("/").to(|| HttpResponse::Ok()));
+/// ```
+pub struct Csrf(Rc<CsrfInner>);
+
+impl Default for Csrf {
+    fn default() -> Self {
+        Csrf::new()
+    }
+}
+
+impl Csrf {
+    /// Construct a `Csrf` middleware keeping the token in the session.
+    pub fn new() -> Self {
+        Csrf(Rc::new(CsrfInner {
+            header: "x-csrf-token".to_owned(),
+            field: "csrf_token".to_owned(),
+            exempt: Vec::new(),
+            double_submit: false,
+            cookie_name: "csrf-token".to_owned(),
+            cookie_path: "/".to_owned(),
+            cookie_domain: None,
+            cookie_secure: true,
+            cookie_same_site: SameSite::Strict,
+        }))
+    }
+
+    /// Construct a stateless `Csrf` middleware, keeping the token in the
+    /// `csrf-token` cookie, which is not http-only so that scripts can read it.
+    ///
+    /// Clients without the cookie get a new token. No session is needed, but the
+    /// token is only as safe as the cookie: a subdomain able to set cookies can
+    /// forge it.
+    pub fn double_submit() -> Self {
+        let mut csrf = Csrf::new();
+        Rc::get_mut(&mut csrf.0).unwrap().double_submit = true;
+        csrf
+    }
+
+    /// Set the header carrying the token. Default is `x-csrf-token`.
+    pub fn header_name<S: Into<String>>(mut self, name: S) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().header = name.into();
+        self
+    }
+
+    /// Set the form field carrying the token. Default is `csrf_token`.
+    pub fn form_field<S: Into<String>>(mut self, name: S) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().field = name.into();
+        self
+    }
+
+    /// Do not check the requests under `path`, e.g. `/webhooks` for `/webhooks`
+    /// and `/webhooks/github`, but not `/webhooks-legacy`.
+    pub fn exempt<S: Into<String>>(mut self, path: S) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().exempt.push(path.into());
+        self
+    }
+
+    /// Set the name of the token cookie of the double submit cookie pattern.
+    /// Default is `csrf-token`.
+    pub fn cookie_name<S: Into<String>>(mut self, name: S) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().cookie_name = name.into();
+        self
+    }
+
+    /// Set the path of the token cookie. Default is `/`.
+    pub fn cookie_path<S: Into<String>>(mut self, path: S) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().cookie_path = path.into();
+        self
+    }
+
+    /// Set the domain of the token cookie.
+    pub fn cookie_domain<S: Into<String>>(mut self, domain: S) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().cookie_domain = Some(domain.into());
+        self
+    }
+
+    /// Set whether the token cookie is only sent over `https`. Default is `true`.
+    pub fn cookie_secure(mut self, secure: bool) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().cookie_secure = secure;
+        self
+    }
+
+    /// Set the SameSite policy of the token cookie. Default is `Strict`.
+    pub fn cookie_same_site(mut self, same_site: SameSite) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().cookie_same_site = same_site;
+        self
+    }
+}
+
+impl<S, B> Transform<S> for Csrf
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CsrfMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CsrfMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            inner: self.0.clone(),
+        })
+    }
+}
+
+/// CSRF middleware
+pub struct CsrfMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    inner: Rc<CsrfInner>,
+}
+
+impl<S, B> Service for CsrfMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let mut srv = self.service.clone();
+        let inner = self.inner.clone();
+
+        async move {
+            let cookie_token = if inner.double_submit {
+                inner.cookie_token(&req)
+            } else {
+                None
+            };
+
+            if inner.protects(&req) {
+                let submitted = match inner.submitted(&mut req).await {
+                    Some(token) => token,
+                    None => return Err(CsrfError::MissingToken.into()),
+                };
+                let expected = if inner.double_submit {
+                    cookie_token.as_ref().map(|token| token.0.clone())
+                } else {
+                    let (request, expected) = CsrfInner::expected(req).await?;
+                    req = request;
+                    expected
+                };
+                match expected {
+                    Some(expected) if tokens_match(&expected, &submitted) => {}
+                    _ => return Err(CsrfError::InvalidToken.into()),
+                }
+            }
+
+            if !inner.double_submit {
+                return srv.call(req).await;
+            }
+
+            let (token, issued) = match cookie_token {
+                Some(token) => (token, false),
+                None => (CsrfToken::generate(), true),
+            };
+            req.extensions_mut().insert(token.clone());
+            let mut res = srv.call(req).await?;
+            if issued {
+                inner.set_cookie(&mut res, &token)?;
+            }
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(all(test, feature = "cookie-session"))]
+mod tests {
+    use actix_web::http::header;
+    use actix_web::{test, web, App};
+
+    use super::*;
+    use crate::CookieSession;
+
+    fn routes(cfg: &mut web::ServiceConfig) {
+        cfg.service(
+            web::resource("/")
+                .route(
+                    web::get()
+                        .to(|token: CsrfToken| async move { token.as_str().to_owned() }),
+                )
+                .route(web::post().to(
+                    |form: web::Form<HashMap<String, String>>| async move {
+                        form["name"].clone()
+                    },
+                )),
+        )
+        .service(web::resource("/webhooks/github").to(|| async { "ok" }));
+    }
+
+    #[actix_rt::test]
+    async fn synchronizer_token() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Csrf::new().exempt("/webhooks"))
+                .wrap(CookieSession::signed(&[0; 32]).secure(false))
+                .configure(routes),
+        )
+        .await;
+
+        let req = test::TestRequest::get().to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+        let token = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+        assert_eq!(token.len(), 32);
+
+        // the token is kept in the session
+        let req = test::TestRequest::get().cookie(cookie.clone()).to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert_eq!(body, token.as_bytes());
+
+        let req = test::TestRequest::post()
+            .cookie(cookie.clone())
+            .set_form(&[("name", "ferris")])
+            .to_request();
+        let err = app.call(req).await.err().unwrap();
+        assert_eq!(err.as_response_error().status_code(), StatusCode::FORBIDDEN);
+
+        let req = test::TestRequest::post()
+            .cookie(cookie.clone())
+            .set_form(&[("name", "ferris"), ("csrf_token", "forged")])
+            .to_request();
+        let err = app.call(req).await.err().unwrap();
+        assert_eq!(err.to_string(), CsrfError::InvalidToken.to_string());
+
+        // the form is still read by the handler
+        let req = test::TestRequest::post()
+            .cookie(cookie.clone())
+            .set_form(&[("name", "ferris"), ("csrf_token", token.as_str())])
+            .to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert_eq!(body, "ferris".as_bytes());
+
+        let req = test::TestRequest::post()
+            .cookie(cookie)
+            .header("x-csrf-token", token.as_str())
+            .set_form(&[("name", "ferris")])
+            .to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert_eq!(body, "ferris".as_bytes());
+
+        // another client does not have the token
+        let req = test::TestRequest::post()
+            .header("x-csrf-token", token.as_str())
+            .set_form(&[("name", "ferris")])
+            .to_request();
+        let err = app.call(req).await.err().unwrap();
+        assert_eq!(err.to_string(), CsrfError::InvalidToken.to_string());
+
+        let req = test::TestRequest::post()
+            .uri("/webhooks/github")
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn session_load_error() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Csrf::new())
+                .wrap_fn(|mut req, srv| {
+                    Session::set_lazy_session(
+                        |_| async { Err(error::ErrorServiceUnavailable("store down")) },
+                        &mut req,
+                    );
+                    srv.call(req)
+                })
+                .configure(routes),
+        )
+        .await;
+
+        // the session error is reported rather than an invalid token
+        let req = test::TestRequest::post()
+            .header("x-csrf-token", "token")
+            .set_form(&[("name", "ferris")])
+            .to_request();
+        let err = app.call(req).await.err().unwrap();
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[actix_rt::test]
+    async fn double_submit_cookie() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Csrf::double_submit().cookie_secure(false))
+                .configure(routes),
+        )
+        .await;
+
+        let req = test::TestRequest::get().to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+        assert_eq!(cookie.name(), "csrf-token");
+        assert_eq!(cookie.http_only(), None);
+        let token = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+        assert_eq!(cookie.value(), token);
+
+        // the cookie is only issued once
+        let req = test::TestRequest::get().cookie(cookie.clone()).to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(res.headers().get(header::SET_COOKIE).is_none());
+
+        let req = test::TestRequest::post()
+            .cookie(cookie.clone())
+            .header("x-csrf-token", token.as_str())
+            .set_form(&[("name", "ferris")])
+            .to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert_eq!(body, "ferris".as_bytes());
+
+        let req = test::TestRequest::post()
+            .header("x-csrf-token", token.as_str())
+            .set_form(&[("name", "ferris")])
+            .to_request();
+        let err = app.call(req).await.err().unwrap();
+        assert_eq!(err.to_string(), CsrfError::InvalidToken.to_string());
+    }
+
+    #[test]
+    fn exempt_paths() {
+        assert!(exempts("/webhooks", "/webhooks"));
+        assert!(exempts("/webhooks", "/webhooks/github"));
+        assert!(!exempts("/webhooks", "/webhooks-legacy"));
+        assert!(exempts("/api/", "/api/users"));
+    }
+}
@@ -0,0 +1,285 @@
+//! One-shot flash messages.
+
+use std::{fmt, slice, vec};
+
+use actix_web::dev::Payload;
+use actix_web::{error, Error, FromRequest, HttpRequest};
+use futures_util::future::{FutureExt, LocalBoxFuture};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::Session;
+
+/// Reserved session key holding the pending flash messages.
+const FLASH_KEY: &str = "actix-session.flash";
+/// Maximum size of the pending flash messages in the serialized session, which keeps
+/// room for the rest of the session in a cookie.
+const FLASH_LIMIT: usize = 1024;
+
+/// Importance of a [`FlashMessage`](struct.FlashMessage.html).
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum FlashLevel {
+    Debug,
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for FlashLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self {
+            FlashLevel::Debug => "debug",
+            FlashLevel::Info => "info",
+            FlashLevel::Success => "success",
+            FlashLevel::Warning => "warning",
+            FlashLevel::Error => "error",
+        };
+        f.write_str(level)
+    }
+}
+
+/// A message shown once, on the next request of the client, e.g. after redirecting
+/// from a form.
+///
+/// Messages are kept in the [`Session`](struct.Session.html) with
+/// [`Session::flash`](struct.Session.html#method.flash), so they work with any
+/// session backend, and read with the
+/// [`FlashMessages`](struct.FlashMessages.html) extractor.
+///
+/// ```rust
+/// use actix_session::{FlashMessage, FlashMessages, Session};
+/// use actix_web::*;
+///
+/// fn save(session: Session) -> Result<HttpResponse> {
+///     session.flash(FlashMessage::success("Saved"))?;
+///     Ok(HttpResponse::SeeOther().header("location", "/").finish())
+/// }
+///
+/// fn index(messages: FlashMessages) -> String {
+///     messages
+///         .iter()
+///         .map(|message| format!("{}: {}\n", message.level(), message.content()))
+///         .collect()
+/// }
+/// # fn main() {}
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FlashMessage(FlashLevel, String);
+
+impl FlashMessage {
+    /// Create a message with the given `level`.
+    pub fn new<S: Into<String>>(level: FlashLevel, content: S) -> Self {
+        FlashMessage(level, content.into())
+    }
+
+    /// Create a message with the `Debug` level.
+    pub fn debug<S: Into<String>>(content: S) -> Self {
+        FlashMessage::new(FlashLevel::Debug, content)
+    }
+
+    /// Create a message with the `Info` level.
+    pub fn info<S: Into<String>>(content: S) -> Self {
+        FlashMessage::new(FlashLevel::Info, content)
+    }
+
+    /// Create a message with the `Success` level.
+    pub fn success<S: Into<String>>(content: S) -> Self {
+        FlashMessage::new(FlashLevel::Success, content)
+    }
+
+    /// Create a message with the `Warning` level.
+    pub fn warning<S: Into<String>>(content: S) -> Self {
+        FlashMessage::new(FlashLevel::Warning, content)
+    }
+
+    /// Create a message with the `Error` level.
+    pub fn error<S: Into<String>>(content: S) -> Self {
+        FlashMessage::new(FlashLevel::Error, content)
+    }
+
+    /// Level of the message.
+    pub fn level(&self) -> FlashLevel {
+        self.0
+    }
+
+    /// Content of the message.
+    pub fn content(&self) -> &str {
+        &self.1
+    }
+}
+
+impl<N> Session<N> {
+    /// Add a flash message, read by the next request extracting
+    /// [`FlashMessages`](struct.FlashMessages.html).
+    ///
+    /// The pending messages take up to 1 KiB of the serialized session, so that
+    /// they fit in a `CookieSession`; the oldest ones are dropped to make room for
+    /// `message`. Fails if `message` alone exceeds the limit.
+    pub fn flash(&self, message: FlashMessage) -> Result<(), Error> {
+        let mut messages = pending(self);
+        messages.push(message);
+
+        loop {
+            if stored_len(&messages)? <= FLASH_LIMIT {
+                self.set(FLASH_KEY, messages)?;
+                return Ok(());
+            }
+            if messages.len() == 1 {
+                return Err(error::ErrorInternalServerError(
+                    "flash message is too large",
+                ));
+            }
+            warn!("dropping flash message, too many are pending");
+            messages.remove(0);
+        }
+    }
+}
+
+/// size of `messages` in the serialized session, where the value of each key is a
+/// JSON string, escaped again
+fn stored_len(messages: &[FlashMessage]) -> Result<usize, Error> {
+    let value = serde_json::to_string(messages)?;
+    Ok(serde_json::to_string(&value)?.len())
+}
+
+/// the pending flash messages, dropped if they cannot be decoded
+fn pending<N>(session: &Session<N>) -> Vec<FlashMessage> {
+    match session.get(FLASH_KEY) {
+        Ok(messages) => messages.unwrap_or_default(),
+        Err(e) => {
+            warn!("dropping invalid flash messages: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Extractor of the flash messages added by the previous requests, which are then
+/// removed from the session.
+///
+/// See [`FlashMessage`](struct.FlashMessage.html).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FlashMessages(Vec<FlashMessage>);
+
+impl FlashMessages {
+    /// Iterate over the messages, oldest first.
+    pub fn iter(&self) -> slice::Iter<'_, FlashMessage> {
+        self.0.iter()
+    }
+
+    /// Number of messages.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if there are no messages.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl IntoIterator for FlashMessages {
+    type Item = FlashMessage;
+    type IntoIter = vec::IntoIter<FlashMessage>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a FlashMessages {
+    type Item = &'a FlashMessage;
+    type IntoIter = slice::Iter<'a, FlashMessage>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl FromRequest for FlashMessages {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<FlashMessages, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let session = <Session>::from_request(req, payload);
+        async move {
+            let session = session.await?;
+            if !session.contains_key(FLASH_KEY) {
+                return Ok(FlashMessages::default());
+            }
+            let messages = pending(&session);
+            session.remove(FLASH_KEY);
+            Ok(FlashMessages(messages))
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(all(test, feature = "cookie-session"))]
+mod tests {
+    use actix_web::{test, web, App};
+
+    use super::*;
+    use crate::CookieSession;
+
+    #[actix_rt::test]
+    async fn flash_messages() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(CookieSession::signed(&[0; 32]).secure(false))
+                .service(web::resource("/save").to(|session: Session| async move {
+                    session.flash(FlashMessage::info("first"))?;
+                    session.flash(FlashMessage::success("saved"))?;
+                    Ok::<_, Error>("saved")
+                }))
+                .service(web::resource("/").to(|messages: FlashMessages| async move {
+                    web::Json(messages.into_iter().collect::<Vec<_>>())
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/save").to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+
+        let req = test::TestRequest::get().cookie(cookie).to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+        let messages: Vec<FlashMessage> = test::read_body_json(res).await;
+        assert_eq!(
+            messages,
+            [FlashMessage::info("first"), FlashMessage::success("saved")]
+        );
+
+        // the messages are only shown once
+        let req = test::TestRequest::get().cookie(cookie).to_request();
+        let messages: Vec<FlashMessage> = test::read_response_json(&mut app, req).await;
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn flash_limit() {
+        let req = test::TestRequest::default().to_srv_request();
+        let session = crate::UserSession::get_session(&req);
+
+        for i in 0..100 {
+            session
+                .flash(FlashMessage::warning(format!("\"message\" {}", i)))
+                .unwrap();
+        }
+        let messages = pending(&session);
+        let value = serde_json::to_string(&messages).unwrap();
+        assert!(serde_json::to_string(&value).unwrap().len() <= FLASH_LIMIT);
+        assert_eq!(messages.last().unwrap().content(), "\"message\" 99");
+        assert!(messages.len() < 100);
+
+        assert!(session
+            .flash(FlashMessage::error("x".repeat(2000)))
+            .is_err());
+        assert_eq!(pending(&session), messages);
+    }
+}
@@ -5,7 +5,9 @@
 //! be accessed via general session api.
 //!
 //! By default, only cookie session backend is implemented. Other
-//! backend implementations can be added.
+//! backend implementations can be added, by implementing
+//! [`SessionStore`](trait.SessionStore.html) and wrapping the application with
+//! [`StoreSession`](struct.StoreSession.html).
 //!
 //! In general, you insert a *session* middleware and initialize it
 //! , such as a `CookieSessionBackend`. To access session data,
@@ -44,22 +46,42 @@
 
 #![deny(rust_2018_idioms)]
 
+use std::any::TypeId;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{hash_map, HashMap, HashSet};
+use std::fmt;
+use std::marker::PhantomData;
 use std::rc::Rc;
 
 use actix_web::dev::{
     Extensions, Payload, RequestHead, ServiceRequest, ServiceResponse,
 };
 use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
-use futures_util::future::{ok, Ready};
+use futures_util::future::{ok, Future, FutureExt, LocalBoxFuture};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use time::{Duration, OffsetDateTime};
 
 #[cfg(feature = "cookie-session")]
 mod cookie;
+#[cfg(feature = "csrf")]
+mod csrf;
+mod flash;
+#[cfg(feature = "session-store")]
+mod memory;
+#[cfg(feature = "session-store")]
+mod store;
 #[cfg(feature = "cookie-session")]
-pub use crate::cookie::CookieSession;
+pub use crate::cookie::{CookieSession, CookieSessionStore};
+#[cfg(feature = "csrf")]
+pub use crate::csrf::{Csrf, CsrfError, CsrfMiddleware, CsrfToken};
+pub use crate::flash::{FlashLevel, FlashMessage, FlashMessages};
+#[cfg(feature = "session-store")]
+pub use crate::memory::MemoryStore;
+#[cfg(feature = "session-store")]
+pub use crate::store::{
+    SessionStore, SessionStoreDegraded, StoreSession, StoreSessionMiddleware,
+};
 
 /// The high-level interface you use to modify session data.
 ///
@@ -84,7 +106,89 @@ pub use crate::cookie::CookieSession;
 /// }
 /// # fn main() {}
 /// ```
-pub struct Session(Rc<RefCell<SessionInner>>);
+///
+/// # Named sessions
+///
+/// Several session middlewares can be used in the same application, e.g. an admin
+/// session and a user session with different cookies and backends, by giving each
+/// one a namespace: a marker type `N`, whose session is extracted as `Session<N>`.
+/// The default namespace is `()`, i.e. `Session` is `Session<()>`.
+///
+/// ```rust
+/// use actix_session::{CookieSession, Session};
+/// use actix_web::{web, App, HttpResponse, Result};
+///
+/// struct Admin;
+///
+/// async fn index(user: Session, admin: Session<Admin>) -> Result<HttpResponse> {
+///     let user_id: Option<String> = user.get("user_id")?;
+///     let admin_id: Option<String> = admin.get("admin_id")?;
+///     Ok(HttpResponse::Ok().finish())
+/// }
+///
+/// let app = App::new()
+///     .wrap(CookieSession::signed(&[0; 32]))
+///     .wrap(
+///         CookieSession::private(&[1; 32])
+///             .name("admin-session")
+///             .namespace::<Admin>(),
+///     )
+///     .service(web::resource("/").to(index));
+/// ```
+pub struct Session<N = ()>(Rc<RefCell<SessionInner>>, PhantomData<fn() -> N>);
+
+/// A session key holding values of type `T`.
+///
+/// Declaring the keys as constants avoids typos in key names, and reading a value
+/// as another type than it was written with.
+///
+/// ```rust
+/// use actix_session::{Session, SessionKey};
+/// use actix_web::*;
+///
+/// const COUNTER: SessionKey<i32> = SessionKey::new("counter");
+///
+/// fn index(session: Session) -> Result<&'static str> {
+///     let count = session.get_typed(&COUNTER)?.unwrap_or(0);
+///     session.insert_typed(&COUNTER, count + 1)?;
+///
+///     Ok("Welcome!")
+/// }
+/// # fn main() {}
+/// ```
+pub struct SessionKey<T> {
+    name: &'static str,
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T> SessionKey<T> {
+    /// Create a key named `name`.
+    pub const fn new(name: &'static str) -> Self {
+        SessionKey {
+            name,
+            value: PhantomData,
+        }
+    }
+
+    /// Name of the key in the session.
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<T> Clone for SessionKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for SessionKey<T> {}
+
+impl<T> fmt::Debug for SessionKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SessionKey").field(&self.name).finish()
+    }
+}
 
 /// Helper trait that allows to get session
 pub trait UserSession {
@@ -109,6 +213,99 @@ impl UserSession for RequestHead {
     }
 }
 
+/// Extension of [`UserSession`](trait.UserSession.html) to get the session of a
+/// namespace, see [named sessions](struct.Session.html#named-sessions).
+pub trait UserSessionExt: UserSession {
+    /// Get the session of namespace `N`.
+    fn get_session_of<N: 'static>(&self) -> Session<N>;
+}
+
+impl UserSessionExt for HttpRequest {
+    fn get_session_of<N: 'static>(&self) -> Session<N> {
+        Session::get_session_of(&mut self.extensions_mut())
+    }
+}
+
+impl UserSessionExt for ServiceRequest {
+    fn get_session_of<N: 'static>(&self) -> Session<N> {
+        Session::get_session_of(&mut self.extensions_mut())
+    }
+}
+
+impl UserSessionExt for RequestHead {
+    fn get_session_of<N: 'static>(&self) -> Session<N> {
+        Session::get_session_of(&mut self.extensions_mut())
+    }
+}
+
+/// The sessions of a request, by namespace.
+#[derive(Default)]
+struct Sessions(HashMap<TypeId, Rc<RefCell<SessionInner>>>);
+
+/// the session of namespace `ns`, created if missing
+fn session_inner(extensions: &mut Extensions, ns: TypeId) -> Rc<RefCell<SessionInner>> {
+    if !extensions.contains::<Sessions>() {
+        extensions.insert(Sessions::default());
+    }
+    let sessions = extensions.get_mut::<Sessions>().unwrap();
+    Rc::clone(sessions.0.entry(ns).or_default())
+}
+
+/// the session of namespace `ns`, if any
+fn find_session_inner(
+    extensions: &Extensions,
+    ns: TypeId,
+) -> Option<Rc<RefCell<SessionInner>>> {
+    extensions.get::<Sessions>()?.0.get(&ns).cloned()
+}
+
+/// Reserved session key holding the creation time of the session.
+pub(crate) const CREATED_KEY: &str = "actix-session.created";
+/// Reserved session key holding the last access time of the session.
+pub(crate) const ACCESSED_KEY: &str = "actix-session.accessed";
+
+/// Reserved session key holding the lifetime set by
+/// [`Session::set_lifetime`](struct.Session.html#method.set_lifetime).
+pub(crate) const LIFETIME_KEY: &str = "actix-session.lifetime";
+
+/// Reserved session keys holding the metadata of the session, which is kept by
+/// [`Session::clear`](struct.Session.html#method.clear).
+const METADATA_KEYS: [&str; 3] = [CREATED_KEY, ACCESSED_KEY, LIFETIME_KEY];
+
+/// Lifetime of a session, overriding the lifetime configured on the session
+/// middleware, see [`Session::set_lifetime`](struct.Session.html#method.set_lifetime).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SessionLifetime {
+    /// The session cookie has no expiration, and is removed when the browser is
+    /// closed.
+    BrowserSession,
+    /// The session cookie and the session stored server side expire after the
+    /// given duration, e.g. when the user asked to be remembered.
+    Persistent(Duration),
+}
+
+impl SessionLifetime {
+    /// The lifetime set in the state of a session, e.g. for a
+    /// [`SessionStore`](trait.SessionStore.html) to honor it.
+    pub fn from_state(state: &HashMap<String, String>) -> Option<SessionLifetime> {
+        let seconds: Option<i64> =
+            serde_json::from_str(state.get(LIFETIME_KEY)?).ok()?;
+        Some(match seconds {
+            Some(seconds) => SessionLifetime::Persistent(Duration::seconds(seconds)),
+            None => SessionLifetime::BrowserSession,
+        })
+    }
+}
+
+/// the time held by `key` in `state`, as a unix timestamp
+pub(crate) fn timestamp(
+    state: &HashMap<String, String>,
+    key: &str,
+) -> Option<OffsetDateTime> {
+    let value = state.get(key)?.parse().ok()?;
+    Some(OffsetDateTime::from_unix_timestamp(value))
+}
+
 #[derive(PartialEq, Clone, Debug)]
 pub enum SessionStatus {
     Changed,
@@ -122,13 +319,54 @@ impl Default for SessionStatus {
     }
 }
 
+/// Keys of the session changed by the request.
+///
+/// Allows backends to write only the modified keys, see
+/// [`Session::get_changed_keys`](struct.Session.html#method.get_changed_keys).
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct SessionChanges {
+    /// Keys set by the request.
+    pub inserted: HashSet<String>,
+    /// Keys removed by the request.
+    pub removed: HashSet<String>,
+    /// Whether the session was cleared, i.e. every key not in `inserted` was removed.
+    pub cleared: bool,
+}
+
+type SessionLoader = Box<
+    dyn FnOnce(
+        HttpRequest,
+    ) -> LocalBoxFuture<'static, Result<HashMap<String, String>, Error>>,
+>;
+
 #[derive(Default)]
 struct SessionInner {
     state: HashMap<String, String>,
     pub status: SessionStatus,
+    changes: SessionChanges,
+    loader: Option<SessionLoader>,
 }
 
-impl Session {
+impl SessionInner {
+    /// adds the keys loaded from the store, unless set or removed by the request
+    fn merge_loaded(&mut self, state: HashMap<String, String>) {
+        if self.status == SessionStatus::Purged {
+            return;
+        }
+        for (key, value) in state {
+            let kept = if self.changes.cleared {
+                Session::is_metadata_key(&key)
+            } else {
+                !self.changes.removed.contains(&key)
+            };
+            if kept {
+                self.state.entry(key).or_insert(value);
+            }
+        }
+    }
+}
+
+impl<N> Session<N> {
     /// Get a `value` from the session.
     pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, Error> {
         if let Some(s) = self.0.borrow().state.get(key) {
@@ -146,25 +384,167 @@ impl Session {
             inner
                 .state
                 .insert(key.to_owned(), serde_json::to_string(&value)?);
+            inner.changes.removed.remove(key);
+            inner.changes.inserted.insert(key.to_owned());
         }
         Ok(())
     }
 
+    /// Get the value of a typed `key` from the session.
+    pub fn get_typed<T: DeserializeOwned>(
+        &self,
+        key: &SessionKey<T>,
+    ) -> Result<Option<T>, Error> {
+        self.get(key.name())
+    }
+
+    /// Set the value of a typed `key` in the session.
+    pub fn insert_typed<T: Serialize>(
+        &self,
+        key: &SessionKey<T>,
+        value: T,
+    ) -> Result<(), Error> {
+        self.set(key.name(), value)
+    }
+
+    /// Returns `true` if the session has a value for `key`.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.0.borrow().state.contains_key(key)
+    }
+
+    /// All the keys of the session, with their JSON serialized values, except for
+    /// the metadata of the session such as its timestamps and lifetime.
+    pub fn entries(&self) -> HashMap<String, String> {
+        self.0
+            .borrow()
+            .state
+            .iter()
+            .filter(|(key, _)| !Session::is_metadata_key(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Get the value of `key`, setting it to the value returned by `f` first if the
+    /// session has no value for `key`.
+    pub fn get_or_insert_with<T, F>(&self, key: &str, f: F) -> Result<T, Error>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> T,
+    {
+        match self.get(key)? {
+            Some(value) => Ok(value),
+            None => {
+                let value = f();
+                self.set(key, &value)?;
+                Ok(value)
+            }
+        }
+    }
+
+    /// Set the value of `key` to the value returned by `f` from its current value,
+    /// and return the new value.
+    ///
+    /// ```rust
+    /// # use actix_session::Session;
+    /// # use actix_web::*;
+    /// fn index(session: Session) -> Result<String> {
+    ///     let count = session.update("counter", |count: Option<i32>| {
+    ///         count.unwrap_or(0) + 1
+    ///     })?;
+    ///
+    ///     Ok(format!("Visit #{}", count))
+    /// }
+    /// # fn main() {}
+    /// ```
+    pub fn update<T, F>(&self, key: &str, f: F) -> Result<T, Error>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce(Option<T>) -> T,
+    {
+        let value = f(self.get(key)?);
+        self.set(key, &value)?;
+        Ok(value)
+    }
+
+    /// Time the session was created.
+    ///
+    /// Only recorded when the session middleware enforces timeouts, see
+    /// [`StoreSession::idle_timeout`](struct.StoreSession.html#method.idle_timeout);
+    /// `None` until a new session is first stored.
+    pub fn created(&self) -> Option<OffsetDateTime> {
+        timestamp(&self.0.borrow().state, CREATED_KEY)
+    }
+
+    /// Time the session was last accessed, before the current request.
+    ///
+    /// Only recorded when the session middleware enforces timeouts, see
+    /// [`StoreSession::idle_timeout`](struct.StoreSession.html#method.idle_timeout);
+    /// `None` until a new session is first stored.
+    pub fn last_accessed(&self) -> Option<OffsetDateTime> {
+        timestamp(&self.0.borrow().state, ACCESSED_KEY)
+    }
+
+    /// Set the lifetime of this session, e.g. to remember the user for 30 days
+    /// after logging in, instead of the lifetime configured on the middleware.
+    ///
+    /// The lifetime is kept in the session, and sets the expiration of the session
+    /// cookie whenever it is issued, as well as the time to live of sessions stored
+    /// server side which honor it, such as `RedisSession`.
+    ///
+    /// ```rust
+    /// use actix_session::{Session, SessionLifetime};
+    /// use actix_web::*;
+    /// use time::Duration;
+    ///
+    /// fn login(session: Session, remember: bool) -> Result<&'static str> {
+    ///     session.set_lifetime(if remember {
+    ///         SessionLifetime::Persistent(Duration::days(30))
+    ///     } else {
+    ///         SessionLifetime::BrowserSession
+    ///     })?;
+    ///     session.renew();
+    ///
+    ///     Ok("Welcome!")
+    /// }
+    /// # fn main() {}
+    /// ```
+    pub fn set_lifetime(&self, lifetime: SessionLifetime) -> Result<(), Error> {
+        let seconds = match lifetime {
+            SessionLifetime::BrowserSession => None,
+            SessionLifetime::Persistent(duration) => Some(duration.whole_seconds()),
+        };
+        self.set(LIFETIME_KEY, seconds)
+    }
+
+    /// The lifetime set with [`set_lifetime`](#method.set_lifetime), if any.
+    pub fn lifetime(&self) -> Option<SessionLifetime> {
+        SessionLifetime::from_state(&self.0.borrow().state)
+    }
+
     /// Remove value from the session.
     pub fn remove(&self, key: &str) {
         let mut inner = self.0.borrow_mut();
         if inner.status != SessionStatus::Purged {
             inner.status = SessionStatus::Changed;
             inner.state.remove(key);
+            inner.changes.inserted.remove(key);
+            inner.changes.removed.insert(key.to_owned());
         }
     }
 
     /// Clear the session.
+    ///
+    /// The metadata of the session, i.e. its timestamps and lifetime, is kept, so
+    /// that clearing the session does not extend its absolute timeout.
     pub fn clear(&self) {
         let mut inner = self.0.borrow_mut();
         if inner.status != SessionStatus::Purged {
             inner.status = SessionStatus::Changed;
-            inner.state.clear()
+            inner.state.retain(|key, _| Session::is_metadata_key(key));
+            inner.changes = SessionChanges {
+                cleared: true,
+                ..SessionChanges::default()
+            };
         }
     }
 
@@ -182,6 +562,15 @@ impl Session {
             inner.status = SessionStatus::Renewed;
         }
     }
+}
+
+impl Session {
+    /// Whether `key` is reserved for the metadata of the session, i.e. its
+    /// timestamps and lifetime, which is not returned by
+    /// [`entries`](#method.entries).
+    pub fn is_metadata_key(key: &str) -> bool {
+        METADATA_KEYS.contains(&key)
+    }
 
     /// Adds the given key-value pairs to the session on the request.
     ///
@@ -205,9 +594,105 @@ impl Session {
         data: impl IntoIterator<Item = (String, String)>,
         req: &mut ServiceRequest,
     ) {
-        let session = Session::get_session(&mut *req.extensions_mut());
-        let mut inner = session.0.borrow_mut();
-        inner.state.extend(data);
+        Session::set_session_in(TypeId::of::<()>(), data, req)
+    }
+
+    /// Adds the given key-value pairs to the session of namespace `N`, like
+    /// [`set_session`](#method.set_session).
+    pub fn set_session_of<N: 'static>(
+        data: impl IntoIterator<Item = (String, String)>,
+        req: &mut ServiceRequest,
+    ) {
+        Session::set_session_in(TypeId::of::<N>(), data, req)
+    }
+
+    pub(crate) fn set_session_in(
+        ns: TypeId,
+        data: impl IntoIterator<Item = (String, String)>,
+        req: &mut ServiceRequest,
+    ) {
+        let inner = session_inner(&mut req.extensions_mut(), ns);
+        inner.borrow_mut().state.extend(data);
+    }
+
+    /// Defers loading the session state on the request until the session is first
+    /// extracted with the [`Session`](struct.Session.html) extractor.
+    ///
+    /// `loader` is called with the request at most once. The keys it returns are
+    /// added to the session, unless they were already set or removed. Sessions
+    /// obtained with
+    /// [`UserSession::get_session`](trait.UserSession.html#tymethod.get_session) are
+    /// not loaded, see [`load_changed`](#method.load_changed).
+    pub fn set_lazy_session<F, Fut>(loader: F, req: &mut ServiceRequest)
+    where
+        F: FnOnce(HttpRequest) -> Fut + 'static,
+        Fut: Future<Output = Result<HashMap<String, String>, Error>> + 'static,
+    {
+        Session::set_lazy_session_in(TypeId::of::<()>(), loader, req)
+    }
+
+    /// Defers loading the session of namespace `N`, like
+    /// [`set_lazy_session`](#method.set_lazy_session).
+    pub fn set_lazy_session_of<N, F, Fut>(loader: F, req: &mut ServiceRequest)
+    where
+        N: 'static,
+        F: FnOnce(HttpRequest) -> Fut + 'static,
+        Fut: Future<Output = Result<HashMap<String, String>, Error>> + 'static,
+    {
+        Session::set_lazy_session_in(TypeId::of::<N>(), loader, req)
+    }
+
+    pub(crate) fn set_lazy_session_in<F, Fut>(
+        ns: TypeId,
+        loader: F,
+        req: &mut ServiceRequest,
+    ) where
+        F: FnOnce(HttpRequest) -> Fut + 'static,
+        Fut: Future<Output = Result<HashMap<String, String>, Error>> + 'static,
+    {
+        let inner = session_inner(&mut req.extensions_mut(), ns);
+        inner.borrow_mut().loader = Some(Box::new(move |req| loader(req).boxed_local()));
+    }
+
+    /// Calls the loader of a session which was changed but never extracted, e.g.
+    /// obtained with
+    /// [`UserSession::get_session`](trait.UserSession.html#tymethod.get_session),
+    /// so that its changes can be written back. Returns whether it was loaded.
+    pub async fn load_changed<B>(res: &ServiceResponse<B>) -> Result<bool, Error> {
+        Session::load_changed_in(TypeId::of::<()>(), res).await
+    }
+
+    /// Calls the loader of the session of namespace `N`, like
+    /// [`load_changed`](#method.load_changed).
+    pub async fn load_changed_of<N: 'static, B>(
+        res: &ServiceResponse<B>,
+    ) -> Result<bool, Error> {
+        Session::load_changed_in(TypeId::of::<N>(), res).await
+    }
+
+    pub(crate) async fn load_changed_in<B>(
+        ns: TypeId,
+        res: &ServiceResponse<B>,
+    ) -> Result<bool, Error> {
+        let inner = match find_session_inner(&res.request().extensions(), ns) {
+            Some(inner) => inner,
+            None => return Ok(false),
+        };
+        let loader = {
+            let mut inner = inner.borrow_mut();
+            if inner.status == SessionStatus::Unchanged {
+                return Ok(false);
+            }
+            inner.loader.take()
+        };
+        match loader {
+            Some(loader) => {
+                let state = loader(res.request().clone()).await?;
+                inner.borrow_mut().merge_loaded(state);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
     }
 
     pub fn get_changes<B>(
@@ -216,11 +701,25 @@ impl Session {
         SessionStatus,
         Option<impl Iterator<Item = (String, String)>>,
     ) {
-        if let Some(s_impl) = res
-            .request()
-            .extensions()
-            .get::<Rc<RefCell<SessionInner>>>()
-        {
+        Session::get_changes_in(TypeId::of::<()>(), res)
+    }
+
+    /// Returns the status and state of the session of namespace `N`, like
+    /// [`get_changes`](#method.get_changes).
+    pub fn get_changes_of<N: 'static, B>(
+        res: &mut ServiceResponse<B>,
+    ) -> (
+        SessionStatus,
+        Option<impl Iterator<Item = (String, String)>>,
+    ) {
+        Session::get_changes_in(TypeId::of::<N>(), res)
+    }
+
+    pub(crate) fn get_changes_in<B>(
+        ns: TypeId,
+        res: &mut ServiceResponse<B>,
+    ) -> (SessionStatus, Option<hash_map::IntoIter<String, String>>) {
+        if let Some(s_impl) = find_session_inner(&res.request().extensions(), ns) {
             let state =
                 std::mem::replace(&mut s_impl.borrow_mut().state, HashMap::new());
             (s_impl.borrow().status.clone(), Some(state.into_iter()))
@@ -229,13 +728,39 @@ impl Session {
         }
     }
 
-    fn get_session(extensions: &mut Extensions) -> Session {
-        if let Some(s_impl) = extensions.get::<Rc<RefCell<SessionInner>>>() {
-            return Session(Rc::clone(&s_impl));
+    /// Returns the keys changed by the request, leaving no changes behind.
+    ///
+    /// Only meaningful when the status returned by
+    /// [`get_changes`](#method.get_changes) is `SessionStatus::Changed`.
+    pub fn get_changed_keys<B>(res: &mut ServiceResponse<B>) -> SessionChanges {
+        Session::get_changed_keys_in(TypeId::of::<()>(), res)
+    }
+
+    /// Returns the keys of the session of namespace `N` changed by the request,
+    /// like [`get_changed_keys`](#method.get_changed_keys).
+    pub fn get_changed_keys_of<N: 'static, B>(
+        res: &mut ServiceResponse<B>,
+    ) -> SessionChanges {
+        Session::get_changed_keys_in(TypeId::of::<N>(), res)
+    }
+
+    pub(crate) fn get_changed_keys_in<B>(
+        ns: TypeId,
+        res: &mut ServiceResponse<B>,
+    ) -> SessionChanges {
+        if let Some(s_impl) = find_session_inner(&res.request().extensions(), ns) {
+            std::mem::take(&mut s_impl.borrow_mut().changes)
+        } else {
+            SessionChanges::default()
         }
-        let inner = Rc::new(RefCell::new(SessionInner::default()));
-        extensions.insert(inner.clone());
-        Session(inner)
+    }
+
+    fn get_session(extensions: &mut Extensions) -> Session {
+        Session::get_session_of(extensions)
+    }
+
+    fn get_session_of<N: 'static>(extensions: &mut Extensions) -> Session<N> {
+        Session(session_inner(extensions, TypeId::of::<N>()), PhantomData)
     }
 }
 
@@ -257,14 +782,29 @@ impl Session {
 /// }
 /// # fn main() {}
 /// ```
-impl FromRequest for Session {
+impl<N: 'static> FromRequest for Session<N> {
     type Error = Error;
-    type Future = Ready<Result<Session, Error>>;
+    type Future = LocalBoxFuture<'static, Result<Session<N>, Error>>;
     type Config = ();
 
     #[inline]
     fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
-        ok(Session::get_session(&mut *req.extensions_mut()))
+        let inner = session_inner(&mut req.extensions_mut(), TypeId::of::<N>());
+        let session = Session(inner, PhantomData);
+        let loader = session.0.borrow_mut().loader.take();
+
+        match loader {
+            Some(loader) => {
+                let fut = loader(req.clone());
+                async move {
+                    let state = fut.await?;
+                    session.0.borrow_mut().merge_loaded(state);
+                    Ok(session)
+                }
+                .boxed_local()
+            }
+            None => ok(session).boxed_local(),
+        }
     }
 }
 
@@ -323,6 +863,196 @@ mod tests {
         assert_eq!(res, Some(10));
     }
 
+    #[test]
+    fn get_session_dyn() {
+        let mut req = test::TestRequest::default().to_srv_request();
+
+        Session::set_session(
+            vec![("key".to_string(), serde_json::to_string(&10).unwrap())],
+            &mut req,
+        );
+
+        let req: &dyn UserSession = &req;
+        let res = req.get_session().get::<u32>("key").unwrap();
+        assert_eq!(res, Some(10));
+    }
+
+    #[test]
+    fn get_named_session() {
+        struct Admin;
+
+        let mut req = test::TestRequest::default().to_srv_request();
+
+        Session::set_session_of::<Admin>(
+            vec![("key".to_string(), serde_json::to_string(&true).unwrap())],
+            &mut req,
+        );
+        assert_eq!(req.get_session().get::<bool>("key").unwrap(), None);
+
+        let session = req.get_session_of::<Admin>();
+        assert_eq!(session.get("key").unwrap(), Some(true));
+        session.set("key2", 1).unwrap();
+
+        let mut res = req.into_response(HttpResponse::Ok().finish());
+        let changes = Session::get_changed_keys_of::<Admin, _>(&mut res);
+        assert_eq!(
+            changes.inserted,
+            ["key2".to_string()].iter().cloned().collect()
+        );
+        let (status, state) = Session::get_changes_of::<Admin, _>(&mut res);
+        assert_eq!(status, SessionStatus::Changed);
+        assert_eq!(state.unwrap().count(), 2);
+        let (status, state) = Session::get_changes(&mut res);
+        assert_eq!(status, SessionStatus::Unchanged);
+        assert_eq!(state.unwrap().count(), 0);
+    }
+
+    #[test]
+    fn changed_keys() {
+        let mut req = test::TestRequest::default().to_srv_request();
+
+        Session::set_session(
+            vec![
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "2".to_string()),
+            ],
+            &mut req,
+        );
+        let session = Session::get_session(&mut req.extensions_mut());
+        session.set("c", 3).unwrap();
+        session.set("a", 4).unwrap();
+        session.remove("b");
+        session.set("d", 5).unwrap();
+        session.remove("d");
+
+        let mut res = req.into_response(HttpResponse::Ok().finish());
+        let changes = Session::get_changed_keys(&mut res);
+        let keys = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect();
+        assert_eq!(changes.inserted, keys(&["a", "c"]));
+        assert_eq!(changes.removed, keys(&["b", "d"]));
+        assert!(!changes.cleared);
+        assert_eq!(
+            Session::get_changed_keys(&mut res),
+            SessionChanges::default()
+        );
+    }
+
+    #[test]
+    fn changed_keys_cleared() {
+        let mut req = test::TestRequest::default().to_srv_request();
+
+        Session::set_session(vec![("a".to_string(), "1".to_string())], &mut req);
+        let session = Session::get_session(&mut req.extensions_mut());
+        session.remove("a");
+        session.clear();
+        session.set("b", 2).unwrap();
+
+        let mut res = req.into_response(HttpResponse::Ok().finish());
+        let changes = Session::get_changed_keys(&mut res);
+        assert!(changes.cleared);
+        assert_eq!(
+            changes.inserted,
+            vec!["b".to_string()].into_iter().collect()
+        );
+        assert!(changes.removed.is_empty());
+    }
+
+    #[test]
+    fn typed_keys() {
+        const COUNTER: SessionKey<u32> = SessionKey::new("counter");
+        const COUNTER_STR: SessionKey<String> = SessionKey::new("counter");
+
+        let req = test::TestRequest::default().to_srv_request();
+        let session = req.get_session();
+        assert_eq!(session.get_typed(&COUNTER).unwrap(), None);
+
+        session.insert_typed(&COUNTER, 10).unwrap();
+        assert_eq!(session.get_typed(&COUNTER).unwrap(), Some(10));
+        assert!(session.get_typed(&COUNTER_STR).is_err());
+        assert_eq!(COUNTER.name(), "counter");
+        assert_eq!(format!("{:?}", COUNTER), "SessionKey(\"counter\")");
+    }
+
+    #[test]
+    fn entries() {
+        let mut req = test::TestRequest::default().to_srv_request();
+
+        Session::set_session(vec![("a".to_string(), "1".to_string())], &mut req);
+        let session = req.get_session();
+        session.set("b", "two").unwrap();
+        assert!(session.contains_key("a"));
+        assert!(!session.contains_key("c"));
+
+        // metadata is not part of the entries
+        session
+            .set_lifetime(SessionLifetime::BrowserSession)
+            .unwrap();
+        let mut entries: Vec<_> = session.entries().into_iter().collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "\"two\"".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn get_or_insert_with_and_update() {
+        let req = test::TestRequest::default().to_srv_request();
+        let session = req.get_session();
+
+        assert_eq!(session.get_or_insert_with("a", || 1).unwrap(), 1);
+        assert_eq!(session.get_or_insert_with("a", || 2).unwrap(), 1);
+
+        let a = session
+            .update("a", |a: Option<i32>| a.unwrap() + 1)
+            .unwrap();
+        assert_eq!(a, 2);
+        let b = session
+            .update("b", |b: Option<i32>| b.unwrap_or(10))
+            .unwrap();
+        assert_eq!(b, 10);
+        assert_eq!(session.get::<i32>("a").unwrap(), Some(2));
+        assert_eq!(session.get::<i32>("b").unwrap(), Some(10));
+
+        // the value is neither overwritten nor updated as another type
+        assert!(session
+            .update("a", |_: Option<String>| String::new())
+            .is_err());
+        assert!(session.get_or_insert_with("a", String::new).is_err());
+        assert_eq!(session.get::<i32>("a").unwrap(), Some(2));
+    }
+
+    #[actix_rt::test]
+    async fn lazy_session() {
+        let mut req = test::TestRequest::default().to_srv_request();
+
+        Session::set_lazy_session(
+            |_| async {
+                let mut state = HashMap::new();
+                state.insert("a".to_string(), "1".to_string());
+                state.insert("b".to_string(), "2".to_string());
+                Ok(state)
+            },
+            &mut req,
+        );
+        let session = req.get_session();
+        session.set("b", 3).unwrap();
+        assert_eq!(session.get::<i32>("a").unwrap(), None);
+
+        let (req, mut payload) = req.into_parts();
+        let session = <Session>::from_request(&req, &mut payload).await.unwrap();
+        assert_eq!(session.get::<i32>("a").unwrap(), Some(1));
+        assert_eq!(session.get::<i32>("b").unwrap(), Some(3));
+
+        // loaded once
+        session.remove("a");
+        let session = <Session>::from_request(&req, &mut payload).await.unwrap();
+        assert_eq!(session.get::<i32>("a").unwrap(), None);
+    }
+
     #[test]
     fn purge_session() {
         let req = test::TestRequest::default().to_srv_request();
@@ -0,0 +1,137 @@
+//! In-memory session store.
+
+use std::collections::HashMap;
+use std::iter;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::Error;
+use futures_util::future::{ok, FutureExt, LocalBoxFuture};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+
+use crate::{SessionChanges, SessionStore};
+
+struct Entry {
+    state: HashMap<String, String>,
+    expires: Instant,
+}
+
+struct MemoryStoreInner {
+    ttl: Duration,
+    sessions: Mutex<HashMap<String, Entry>>,
+}
+
+/// Keep sessions in memory.
+///
+/// Clones share the same sessions, so that a store created before starting the
+/// server can be used by all the workers. Sessions are lost when the process exits,
+/// which makes this store suitable for tests and single-node applications.
+///
+/// Expired sessions are removed whenever a new session is saved.
+#[derive(Clone)]
+pub struct MemoryStore(Arc<MemoryStoreInner>);
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+impl MemoryStore {
+    /// Create new empty store, with a time to live of 2 hours.
+    pub fn new() -> Self {
+        MemoryStore(Arc::new(MemoryStoreInner {
+            ttl: Duration::from_secs(7200),
+            sessions: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Set time to live in seconds for sessions.
+    ///
+    /// Panics if the store was cloned.
+    pub fn ttl(mut self, ttl: u64) -> Self {
+        Arc::get_mut(&mut self.0).unwrap().ttl = Duration::from_secs(ttl);
+        self
+    }
+
+    /// Number of sessions in the store, including expired sessions not removed yet.
+    pub fn len(&self) -> usize {
+        self.0.sessions.lock().unwrap().len()
+    }
+
+    /// Returns `true` if the store contains no sessions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn write(&self, key: String, state: HashMap<String, String>) {
+        let expires = Instant::now() + self.0.ttl;
+        let mut sessions = self.0.sessions.lock().unwrap();
+        sessions.insert(key, Entry { state, expires });
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Option<HashMap<String, String>>, Error>> {
+        let sessions = self.0.sessions.lock().unwrap();
+        let state = sessions
+            .get(key)
+            .filter(|entry| entry.expires > Instant::now())
+            .map(|entry| entry.state.clone());
+        ok(state).boxed_local()
+    }
+
+    fn save(
+        &self,
+        state: HashMap<String, String>,
+    ) -> LocalBoxFuture<'_, Result<String, Error>> {
+        let key: String = iter::repeat(())
+            .map(|()| OsRng.sample(Alphanumeric))
+            .take(32)
+            .collect();
+
+        let now = Instant::now();
+        self.0
+            .sessions
+            .lock()
+            .unwrap()
+            .retain(|_, entry| entry.expires > now);
+        self.write(key.clone(), state);
+        ok(key).boxed_local()
+    }
+
+    fn update(
+        &self,
+        key: String,
+        state: HashMap<String, String>,
+        _: SessionChanges,
+    ) -> LocalBoxFuture<'_, Result<String, Error>> {
+        self.write(key.clone(), state);
+        ok(key).boxed_local()
+    }
+
+    fn delete(&self, key: String) -> LocalBoxFuture<'_, Result<(), Error>> {
+        self.0.sessions.lock().unwrap().remove(&key);
+        ok(()).boxed_local()
+    }
+
+    fn ttl<'a>(
+        &'a self,
+        key: &'a str,
+        _: &'a HashMap<String, String>,
+    ) -> LocalBoxFuture<'a, Result<bool, Error>> {
+        let now = Instant::now();
+        let mut sessions = self.0.sessions.lock().unwrap();
+        let extended = match sessions.get_mut(key) {
+            Some(entry) if entry.expires > now => {
+                entry.expires = now + self.0.ttl;
+                true
+            }
+            _ => false,
+        };
+        ok(extended).boxed_local()
+    }
+}
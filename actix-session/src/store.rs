@@ -0,0 +1,1249 @@
+//! Session storage.
+//!
+//! [**StoreSession**](struct.StoreSession.html) keeps the session state in a
+//! [`SessionStore`](trait.SessionStore.html), and only sends the key of the session to
+//! the client, in a signed or private cookie.
+//!
+//! The middleware drives the store: it loads the session of the request, then saves,
+//! updates or deletes it according to the changes made by the request, issuing,
+//! renewing and removing the session cookie accordingly.
+
+use std::any::TypeId;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::iter;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header::SET_COOKIE, HeaderValue};
+use actix_web::{error, Error, HttpMessage};
+use futures_util::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use log::warn;
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    timestamp, Session, SessionChanges, SessionLifetime, SessionStatus, ACCESSED_KEY,
+    CREATED_KEY, LIFETIME_KEY,
+};
+
+/// Storage of the session state, driven by [`StoreSession`](struct.StoreSession.html).
+///
+/// Sessions are identified by a key, which is sent to the client in the session
+/// cookie.
+pub trait SessionStore {
+    /// Load the state of a session, or `None` if there is no such session.
+    fn load<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Option<HashMap<String, String>>, Error>>;
+
+    /// Save the state of a new session, returning its key.
+    fn save(
+        &self,
+        state: HashMap<String, String>,
+    ) -> LocalBoxFuture<'_, Result<String, Error>>;
+
+    /// Update the state of an existing session, returning its key, which may differ
+    /// from the previous one.
+    ///
+    /// `changes` are the keys changed by the request, allowing to write only those.
+    fn update(
+        &self,
+        key: String,
+        state: HashMap<String, String>,
+        changes: SessionChanges,
+    ) -> LocalBoxFuture<'_, Result<String, Error>>;
+
+    /// Load the state of a session along with its version, for stores detecting
+    /// concurrent changes to a session.
+    ///
+    /// The version is kept out of the session state, and passed back to
+    /// [`update_versioned`](#method.update_versioned) when the session is written.
+    /// Defaults to [`load`](#tymethod.load), without a version.
+    fn load_versioned<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Option<(HashMap<String, String>, Option<u64>)>, Error>>
+    {
+        self.load(key)
+            .map(|res| res.map(|state| state.map(|state| (state, None))))
+            .boxed_local()
+    }
+
+    /// Update the state of an existing session loaded at `version`, see
+    /// [`load_versioned`](#method.load_versioned).
+    ///
+    /// Defaults to [`update`](#tymethod.update), ignoring the version.
+    fn update_versioned(
+        &self,
+        key: String,
+        state: HashMap<String, String>,
+        changes: SessionChanges,
+        _version: Option<u64>,
+    ) -> LocalBoxFuture<'_, Result<String, Error>> {
+        self.update(key, state, changes)
+    }
+
+    /// Delete a session.
+    fn delete(&self, key: String) -> LocalBoxFuture<'_, Result<(), Error>>;
+
+    /// Extend the time to live of an unchanged session, without writing its state.
+    ///
+    /// Called on every access when
+    /// [`StoreSession::sliding_expiration`](struct.StoreSession.html#method.sliding_expiration)
+    /// is enabled. Returns whether the session was extended, in which case the session
+    /// cookie is issued again to match.
+    fn ttl<'a>(
+        &'a self,
+        key: &'a str,
+        state: &'a HashMap<String, String>,
+    ) -> LocalBoxFuture<'a, Result<bool, Error>>;
+}
+
+/// Request extension set when the session could not be loaded and
+/// [`StoreSession::fail_open`](struct.StoreSession.html#method.fail_open) is enabled.
+///
+/// The session of such a request is empty, and its changes are discarded.
+///
+/// ```rust
+/// use actix_session::SessionStoreDegraded;
+/// use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+///
+/// async fn index(req: HttpRequest) -> HttpResponse {
+///     if req.extensions().contains::<SessionStoreDegraded>() {
+///         return HttpResponse::ServiceUnavailable().finish();
+///     }
+///     HttpResponse::Ok().finish()
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct SessionStoreDegraded;
+
+/// Outcome of loading the session of a request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Load {
+    Pending,
+    /// found at the given version, if the store keeps versions
+    Found(Option<u64>),
+    Missing,
+    Failed,
+}
+
+struct StoreSessionInner<T> {
+    store: T,
+    key: Key,
+    previous_keys: Vec<Key>,
+    private: bool,
+    name: String,
+    path: String,
+    domain: Option<String>,
+    secure: bool,
+    http_only: bool,
+    max_age: Option<Duration>,
+    expires_in: Option<Duration>,
+    same_site: Option<SameSite>,
+    lazy: bool,
+    lazy_load: bool,
+    sliding_expiration: bool,
+    fail_open: bool,
+    idle_timeout: Option<Duration>,
+    absolute_timeout: Option<Duration>,
+    chunked: bool,
+    namespace: TypeId,
+}
+
+impl<T> StoreSessionInner<T> {
+    /// verifies the session cookie with the current and previous keys, returning the
+    /// session key and whether the cookie must be issued again
+    fn verify(&self, req: &ServiceRequest) -> Option<(String, bool)> {
+        let cookies = req.cookies().ok()?;
+        let cookie = match cookies.iter().find(|c| c.name() == self.name) {
+            Some(cookie) => cookie.clone(),
+            None if self.chunked => self.reassemble(&cookies)?,
+            None => return None,
+        };
+
+        let mut jar = CookieJar::new();
+        jar.add_original(cookie);
+
+        let keys = iter::once(&self.key).chain(self.previous_keys.iter());
+        for (i, key) in keys.enumerate() {
+            // cookies issued before switching between signed and private are accepted
+            let (cookie, private) = match jar.private(key).get(&self.name) {
+                Some(cookie) => (cookie, true),
+                None => match jar.signed(key).get(&self.name) {
+                    Some(cookie) => (cookie, false),
+                    None => continue,
+                },
+            };
+            return Some((cookie.value().to_owned(), i > 0 || private != self.private));
+        }
+        None
+    }
+
+    /// whether the session cookie expires, and must follow the session lifetime
+    fn expires(&self, lifetime: Option<SessionLifetime>) -> bool {
+        match lifetime {
+            Some(SessionLifetime::Persistent(_)) => true,
+            Some(SessionLifetime::BrowserSession) => false,
+            None => self.max_age.is_some() || self.expires_in.is_some(),
+        }
+    }
+
+    /// whether the creation and last access times are recorded in the session
+    fn timestamps(&self) -> bool {
+        self.idle_timeout.is_some() || self.absolute_timeout.is_some()
+    }
+
+    /// whether the session outlived its idle or absolute timeout; sessions without
+    /// the timestamps of an enforced timeout are expired
+    fn expired(&self, state: &HashMap<String, String>) -> bool {
+        let now = OffsetDateTime::now_utc();
+        let outlived = |key, timeout: Option<Duration>| match timeout {
+            Some(timeout) => match timestamp(state, key) {
+                Some(time) => time + timeout <= now,
+                None => true,
+            },
+            None => false,
+        };
+        outlived(CREATED_KEY, self.absolute_timeout)
+            || outlived(ACCESSED_KEY, self.idle_timeout)
+    }
+
+    /// records the creation and last access times in `state`, returning whether
+    /// they changed
+    fn stamp(
+        &self,
+        state: &mut HashMap<String, String>,
+        changes: &mut SessionChanges,
+    ) -> bool {
+        let now = OffsetDateTime::now_utc().unix_timestamp().to_string();
+        let mut changed = false;
+        if !state.contains_key(CREATED_KEY) {
+            state.insert(CREATED_KEY.to_owned(), now.clone());
+            changes.inserted.insert(CREATED_KEY.to_owned());
+            changed = true;
+        }
+        // accesses within the same second are not written
+        if state.get(ACCESSED_KEY) != Some(&now) {
+            state.insert(ACCESSED_KEY.to_owned(), now);
+            changes.inserted.insert(ACCESSED_KEY.to_owned());
+            changed = true;
+        }
+        changed
+    }
+
+    fn set_cookie<B>(
+        &self,
+        res: &mut ServiceResponse<B>,
+        value: String,
+        lifetime: Option<SessionLifetime>,
+    ) -> Result<(), Error> {
+        let mut cookie = Cookie::new(self.name.clone(), value);
+        cookie.set_path(self.path.clone());
+        cookie.set_secure(self.secure);
+        cookie.set_http_only(self.http_only);
+
+        if let Some(ref domain) = self.domain {
+            cookie.set_domain(domain.clone());
+        }
+
+        match lifetime {
+            Some(SessionLifetime::Persistent(lifetime)) => {
+                cookie.set_expires(OffsetDateTime::now_utc() + lifetime);
+                cookie.set_max_age(lifetime);
+            }
+            Some(SessionLifetime::BrowserSession) => {}
+            None => {
+                if let Some(expires_in) = self.expires_in {
+                    cookie.set_expires(OffsetDateTime::now_utc() + expires_in);
+                }
+
+                if let Some(max_age) = self.max_age {
+                    cookie.set_max_age(max_age);
+                }
+            }
+        }
+
+        if let Some(same_site) = self.same_site {
+            cookie.set_same_site(same_site);
+        }
+
+        let mut jar = CookieJar::new();
+        if self.private {
+            jar.private(&self.key).add(cookie);
+        } else {
+            jar.signed(&self.key).add(cookie);
+        }
+        let cookie = jar.delta().next().unwrap().clone();
+
+        if !self.chunked {
+            let val = HeaderValue::from_str(&cookie.encoded().to_string())?;
+            res.headers_mut().append(SET_COOKIE, val);
+            return Ok(());
+        }
+
+        let chunks = split_chunks(cookie.value());
+        if chunks.len() > MAX_CHUNKS {
+            return Err(error::ErrorInternalServerError(
+                "session cookie is too large",
+            ));
+        }
+        let mut names = Vec::with_capacity(chunks.len());
+        if chunks.len() == 1 {
+            names.push(self.name.clone());
+        } else {
+            names.extend((0..chunks.len()).map(|i| format!("{}.{}", self.name, i)));
+        }
+        for (name, chunk) in names.iter().zip(chunks) {
+            let mut cookie = cookie.clone();
+            cookie.set_name(name.clone());
+            cookie.set_value(chunk.to_owned());
+            let val = HeaderValue::from_str(&cookie.encoded().to_string())?;
+            res.headers_mut().append(SET_COOKIE, val);
+        }
+
+        // the chunks left over by a larger session
+        let stale: Vec<_> = match res.request().cookies() {
+            Ok(cookies) => cookies
+                .iter()
+                .map(|c| c.name().to_owned())
+                .filter(|name| self.is_session_cookie(name) && !names.contains(name))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        for name in stale {
+            self.remove_named_cookie(res, name)?;
+        }
+
+        Ok(())
+    }
+
+    /// invalidates session cookie, and its chunks
+    fn remove_cookie<B>(&self, res: &mut ServiceResponse<B>) -> Result<(), Error> {
+        let mut names = vec![self.name.clone()];
+        if self.chunked {
+            if let Ok(cookies) = res.request().cookies() {
+                names.extend(
+                    cookies.iter().map(|c| c.name().to_owned()).filter(|name| {
+                        *name != self.name && self.is_session_cookie(name)
+                    }),
+                );
+            }
+        }
+        for name in names {
+            self.remove_named_cookie(res, name)?;
+        }
+        Ok(())
+    }
+
+    fn remove_named_cookie<B>(
+        &self,
+        res: &mut ServiceResponse<B>,
+        name: String,
+    ) -> Result<(), Error> {
+        let mut cookie = Cookie::named(name);
+        cookie.set_value("");
+        cookie.set_path(self.path.clone());
+        cookie.set_max_age(Duration::zero());
+        cookie.set_expires(OffsetDateTime::now_utc() - Duration::days(365));
+
+        // the browser only removes the cookie with the same path and domain
+        if let Some(ref domain) = self.domain {
+            cookie.set_domain(domain.clone());
+        }
+
+        let val = HeaderValue::from_str(&cookie.to_string())?;
+        res.headers_mut().append(SET_COOKIE, val);
+
+        Ok(())
+    }
+
+    /// whether `name` is the name of the session cookie, or of one of its chunks
+    fn is_session_cookie(&self, name: &str) -> bool {
+        if name == self.name {
+            return true;
+        }
+        let len = self.name.len();
+        self.chunked
+            && name.starts_with(&self.name)
+            && name[len..].starts_with('.')
+            && name[len + 1..].parse::<usize>().is_ok()
+    }
+
+    /// joins the chunks of the session cookie, which are protected as a whole
+    fn reassemble(&self, cookies: &[Cookie<'static>]) -> Option<Cookie<'static>> {
+        let mut value = String::new();
+        for i in 0..MAX_CHUNKS {
+            let name = format!("{}.{}", self.name, i);
+            match cookies.iter().find(|c| c.name() == name) {
+                Some(cookie) => value.push_str(cookie.value()),
+                None if i == 0 => return None,
+                None => break,
+            }
+        }
+        Some(Cookie::new(self.name.clone(), value))
+    }
+}
+
+/// Maximum size of the percent-encoded value of a chunk of the session cookie.
+const CHUNK_SIZE: usize = 3584;
+/// Maximum number of chunks of the session cookie.
+const MAX_CHUNKS: usize = 8;
+
+/// splits `value` so that each chunk fits in a cookie once percent-encoded
+fn split_chunks(value: &str) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let (mut start, mut size) = (0, 0);
+    for (i, c) in value.char_indices() {
+        // an upper bound of the length of the percent-encoded character
+        let len = if c.is_ascii_alphanumeric() || "-._~!$&'()*+,".contains(c) {
+            1
+        } else {
+            3 * c.len_utf8()
+        };
+        if size + len > CHUNK_SIZE {
+            chunks.push(&value[start..i]);
+            start = i;
+            size = 0;
+        }
+        size += len;
+    }
+    chunks.push(&value[start..]);
+    chunks
+}
+
+impl<T: SessionStore> StoreSessionInner<T> {
+    /// loads the state of the session, recording the outcome in `load`
+    async fn load(
+        &self,
+        key: Option<&str>,
+        req: &impl HttpMessage,
+        load: &Cell<Load>,
+    ) -> Result<HashMap<String, String>, Error> {
+        let key = match key {
+            Some(key) => key,
+            // new clients have no session to load
+            None => {
+                load.set(Load::Missing);
+                return Ok(HashMap::new());
+            }
+        };
+        match self.store.load_versioned(key).await {
+            Ok(Some((state, _))) if self.timestamps() && self.expired(&state) => {
+                // the client gets a new session
+                if let Err(err) = self.store.delete(key.to_owned()).await {
+                    warn!("failed to delete expired session: {}", err);
+                }
+                load.set(Load::Missing);
+                Ok(HashMap::new())
+            }
+            Ok(Some((state, version))) => {
+                load.set(Load::Found(version));
+                Ok(state)
+            }
+            Ok(None) => {
+                load.set(Load::Missing);
+                Ok(HashMap::new())
+            }
+            Err(err) if self.fail_open => {
+                warn!("session store is unavailable: {}", err);
+                req.extensions_mut().insert(SessionStoreDegraded);
+                // the session is empty and is not written back
+                load.set(Load::Failed);
+                Ok(HashMap::new())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// writes the changes of the session loaded at `version` back to the store; the
+    /// session cookie is issued again if `resign`, unless it is removed
+    async fn write<B>(
+        &self,
+        res: &mut ServiceResponse<B>,
+        key: Option<String>,
+        version: Option<u64>,
+        resign: bool,
+    ) -> Result<(), Error> {
+        let (mut status, state) = Session::get_changes_in(self.namespace, res);
+        let mut state: HashMap<_, _> = match state {
+            Some(state) => state.collect(),
+            None => return Ok(()),
+        };
+        let mut changes = Session::get_changed_keys_in(self.namespace, res);
+
+        let stamped = self.timestamps()
+            && status != SessionStatus::Purged
+            && !(self.lazy && key.is_none() && state.is_empty())
+            && self.stamp(&mut state, &mut changes);
+        if stamped && status == SessionStatus::Unchanged {
+            status = SessionStatus::Changed;
+        }
+
+        let lifetime = SessionLifetime::from_state(&state);
+        // the cookie follows the lifetime set by the request
+        let resign = resign
+            || changes.cleared
+            || changes.inserted.contains(LIFETIME_KEY)
+            || changes.removed.contains(LIFETIME_KEY);
+
+        match (status, key) {
+            (SessionStatus::Purged, key) => {
+                self.remove_cookie(res)?;
+                if let Some(key) = key {
+                    self.store.delete(key).await?;
+                }
+                Ok(())
+            }
+            (SessionStatus::Unchanged, Some(key)) => {
+                let extended = self.sliding_expiration
+                    && self.store.ttl(&key, &state).await?
+                    && self.expires(lifetime);
+                if extended || resign {
+                    self.set_cookie(res, key, lifetime)?;
+                }
+                Ok(())
+            }
+            (SessionStatus::Changed, Some(key)) => {
+                let new_key = self
+                    .store
+                    .update_versioned(key.clone(), state, changes, version)
+                    .await?;
+                if new_key != key
+                    || (self.sliding_expiration && self.expires(lifetime))
+                    || resign
+                {
+                    self.set_cookie(res, new_key, lifetime)?;
+                }
+                Ok(())
+            }
+            (SessionStatus::Renewed, Some(key)) => {
+                self.store.delete(key).await?;
+                let key = self.store.save(state).await?;
+                self.set_cookie(res, key, lifetime)
+            }
+            // a new session is issued upon first request (new client)
+            (_, None) => {
+                if self.lazy && state.is_empty() {
+                    return Ok(());
+                }
+                let key = self.store.save(state).await?;
+                self.set_cookie(res, key, lifetime)
+            }
+        }
+    }
+}
+
+/// Keep the session state in a [`SessionStore`](trait.SessionStore.html).
+///
+/// Only the key of the session is sent to the client, in a signed or private cookie,
+/// see [`CookieSession`](struct.CookieSession.html) for the cookie security policies.
+/// The constructor panics if the key is less than 32 bytes long.
+///
+/// On first request, a new session cookie is returned in response, regardless of
+/// whether any session state is set, unless [`lazy`](#method.lazy) is enabled. With
+/// subsequent requests, the session is written back to the store only if it changed.
+/// As a user logs out, call `session.purge()` to delete the session from the store
+/// and remove the session cookie.
+///
+/// # Example
+///
+/// ```rust
+/// use actix_session::{MemoryStore, StoreSession};
+/// use actix_web::{web, App, HttpResponse, HttpServer};
+///
+/// // the store is shared by the workers
+/// let store = MemoryStore::new();
+///
+/// HttpServer::new(move || {
+///     App::new()
+///         .wrap(StoreSession::new(store.clone(), &[0; 32]).cookie_secure(false))
+///         .service(web::resource("/").to(|| HttpResponse::Ok()))
+/// });
+/// ```
+pub struct StoreSession<T>(Rc<StoreSessionInner<T>>);
+
+impl<T> Clone for StoreSession<T> {
+    fn clone(&self) -> Self {
+        StoreSession(self.0.clone())
+    }
+}
+
+impl<T: SessionStore> StoreSession<T> {
+    /// Construct new `StoreSession` instance.
+    ///
+    /// Panics if key length is less than 32 bytes.
+    pub fn new(store: T, key: &[u8]) -> Self {
+        StoreSession(Rc::new(StoreSessionInner {
+            store,
+            key: Key::derive_from(key),
+            previous_keys: Vec::new(),
+            private: false,
+            name: "actix-session".to_owned(),
+            path: "/".to_owned(),
+            domain: None,
+            secure: true,
+            http_only: true,
+            max_age: None,
+            expires_in: None,
+            same_site: None,
+            lazy: false,
+            lazy_load: false,
+            sliding_expiration: false,
+            fail_open: false,
+            idle_timeout: None,
+            absolute_timeout: None,
+            chunked: false,
+            namespace: TypeId::of::<()>(),
+        }))
+    }
+}
+
+impl<T> StoreSession<T> {
+    /// The session store.
+    pub fn store(&self) -> &T {
+        &self.0.store
+    }
+
+    /// The session store, e.g. to configure it.
+    ///
+    /// Panics if the middleware was cloned.
+    pub fn store_mut(&mut self) -> &mut T {
+        &mut Rc::get_mut(&mut self.0).unwrap().store
+    }
+
+    /// Set the keys used before the current one, e.g. when rotating keys.
+    ///
+    /// Cookies signed with a previous key are still accepted, and issued again with
+    /// the current key. Panics if a key is less than 32 bytes long.
+    pub fn previous_keys(mut self, keys: &[&[u8]]) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().previous_keys =
+            keys.iter().map(|key| Key::derive_from(key)).collect();
+        self
+    }
+
+    /// Encrypt the session cookie instead of only signing it. Default is `false`.
+    ///
+    /// Cookies issued before changing this option are still accepted, and issued
+    /// again.
+    pub fn cookie_private(mut self, private: bool) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().private = private;
+        self
+    }
+
+    /// Set custom cookie name for the session key. Default is `actix-session`.
+    pub fn cookie_name<S: Into<String>>(mut self, name: S) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().name = name.into();
+        self
+    }
+
+    /// Set custom cookie path. Default is `/`.
+    pub fn cookie_path<S: Into<String>>(mut self, path: S) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().path = path.into();
+        self
+    }
+
+    /// Set custom cookie domain
+    pub fn cookie_domain<S: Into<String>>(mut self, domain: S) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().domain = Some(domain.into());
+        self
+    }
+
+    /// Set custom cookie secure. Default is `true`.
+    ///
+    /// If the `secure` field is set, a cookie will only be transmitted when the
+    /// connection is secure - i.e. `https`
+    pub fn cookie_secure(mut self, secure: bool) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().secure = secure;
+        self
+    }
+
+    /// Set custom cookie HttpOnly policy. Default is `true`.
+    pub fn cookie_http_only(mut self, http_only: bool) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().http_only = http_only;
+        self
+    }
+
+    /// Set custom cookie SameSite
+    pub fn cookie_same_site(mut self, same_site: SameSite) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().same_site = Some(same_site);
+        self
+    }
+
+    /// Spread session cookies too large for a single cookie over several cookies,
+    /// named after the session cookie: `actix-session.0`, `actix-session.1` and so
+    /// on. Default is `false`.
+    ///
+    /// The signed or encrypted value of the cookie is split, so that the chunks are
+    /// only valid together; session cookies are limited to 8 chunks of about 3.5
+    /// KiB. The chunks left over as a session shrinks are removed.
+    pub fn cookie_chunked(mut self, chunked: bool) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().chunked = chunked;
+        self
+    }
+
+    /// Set custom cookie max-age
+    pub fn cookie_max_age(mut self, max_age: Duration) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().max_age = Some(max_age);
+        self
+    }
+
+    /// Set custom cookie expires, relative to the time the cookie is issued
+    pub fn cookie_expires_in(mut self, expires_in: Duration) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().expires_in = Some(expires_in);
+        self
+    }
+
+    /// When true, prevents adding session cookies to responses until
+    /// the session contains data. Default is `false`.
+    ///
+    /// Useful when trying to comply with laws that require consent for setting cookies.
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().lazy = lazy;
+        self
+    }
+
+    /// Load the session only when it is first extracted with the
+    /// [`Session`](struct.Session.html) extractor, instead of before calling the
+    /// service. Default is `false`.
+    ///
+    /// Requests which never extract the session, e.g. for static assets, then do
+    /// not reach the store at all, and no session is issued to new clients; the
+    /// session is not written back either. Sessions obtained with
+    /// [`UserSession::get_session`](trait.UserSession.html#tymethod.get_session)
+    /// are empty, and are loaded after the service only when changed, so that
+    /// their changes are written back.
+    pub fn lazy_load(mut self, lazy_load: bool) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().lazy_load = lazy_load;
+        self
+    }
+
+    /// Extend the lifetime of the session whenever it is accessed, not only when it
+    /// changes, see [`SessionStore::ttl`](trait.SessionStore.html#tymethod.ttl).
+    /// Default is `false`.
+    pub fn sliding_expiration(mut self, sliding: bool) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().sliding_expiration = sliding;
+        self
+    }
+
+    /// Expire sessions which are not accessed for `timeout`. Default is no idle
+    /// timeout.
+    ///
+    /// Unlike the expiration of the cookie, which is up to the client, the timeout
+    /// is enforced by the middleware: the time of the last access is recorded in
+    /// the session, which is then written back on every access, and expired
+    /// sessions are deleted and replaced by a new session. Sessions stored before
+    /// enabling the timeout have no such record, and are expired too.
+    ///
+    /// See [`Session::last_accessed`](struct.Session.html#method.last_accessed).
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Expire sessions `timeout` after their creation, however often they are
+    /// accessed. Default is no absolute timeout.
+    ///
+    /// The timeout is enforced by the middleware like
+    /// [`idle_timeout`](#method.idle_timeout); renewing the session keeps its
+    /// creation time.
+    ///
+    /// See [`Session::created`](struct.Session.html#method.created).
+    pub fn absolute_timeout(mut self, timeout: Duration) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().absolute_timeout = Some(timeout);
+        self
+    }
+
+    /// Set whether requests are served when the store fails to load their session.
+    ///
+    /// When `true`, such requests get an empty session which is not written back,
+    /// and carry the [`SessionStoreDegraded`](struct.SessionStoreDegraded.html)
+    /// extension; failures to write sessions back are logged. When `false`, such
+    /// requests fail. Default is `false`.
+    pub fn fail_open(mut self, fail_open: bool) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().fail_open = fail_open;
+        self
+    }
+
+    /// Serve the session as `Session<N>` rather than `Session`, so that it does not
+    /// collide with the sessions of other middlewares.
+    ///
+    /// Each namespace should use its own cookie name.
+    pub fn namespace<N: 'static>(mut self) -> Self {
+        Rc::get_mut(&mut self.0).unwrap().namespace = TypeId::of::<N>();
+        self
+    }
+}
+
+impl<S, B, T> Transform<S> for StoreSession<T>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+    T: SessionStore + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = StoreSessionMiddleware<S, T>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(StoreSessionMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            inner: self.0.clone(),
+        })
+    }
+}
+
+/// Session store middleware
+pub struct StoreSessionMiddleware<S, T> {
+    service: Rc<RefCell<S>>,
+    inner: Rc<StoreSessionInner<T>>,
+}
+
+impl<S, B, T> Service for StoreSessionMiddleware<S, T>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+    B: 'static,
+    T: SessionStore + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let mut srv = self.service.clone();
+        let inner = self.inner.clone();
+
+        async move {
+            let (key, resign) = match inner.verify(&req) {
+                Some((key, resign)) => (Some(key), resign),
+                None => (None, false),
+            };
+
+            let load = Rc::new(Cell::new(Load::Pending));
+            if inner.lazy_load {
+                let (inner, key, load) = (inner.clone(), key.clone(), load.clone());
+                Session::set_lazy_session_in(
+                    inner.namespace,
+                    move |req| async move {
+                        inner.load(key.as_deref(), &req, &load).await
+                    },
+                    &mut req,
+                );
+            } else {
+                let state = inner.load(key.as_deref(), &req, &load).await?;
+                Session::set_session_in(inner.namespace, state, &mut req);
+            }
+
+            let mut res = srv.call(req).await?;
+
+            if load.get() == Load::Pending {
+                Session::load_changed_in(inner.namespace, &res).await?;
+            }
+
+            let (key, version) = match load.get() {
+                Load::Found(version) => (key, version),
+                Load::Missing => (None, None),
+                // the session was never touched, or is not written back
+                Load::Pending | Load::Failed => return Ok(res),
+            };
+
+            // a cookie verified with a previous key is issued again
+            if let Err(err) = inner.write(&mut res, key, version, resign).await {
+                if !inner.fail_open {
+                    return Err(err);
+                }
+                warn!("failed to write session: {}", err);
+            }
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStore;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+    use futures_util::future::err;
+
+    fn routes(cfg: &mut web::ServiceConfig) {
+        cfg.service(web::resource("/").to(|ses: Session| async move {
+            let counter: i32 = ses.get("counter")?.unwrap_or(0);
+            Ok::<_, Error>(HttpResponse::Ok().json(counter))
+        }))
+        .service(web::resource("/count").to(|ses: Session| async move {
+            let counter: i32 = ses.get("counter")?.unwrap_or(0);
+            ses.set("counter", counter + 1)?;
+            Ok::<_, Error>(HttpResponse::Ok().json(counter + 1))
+        }))
+        .service(web::resource("/renew").to(|ses: Session| async move {
+            ses.renew();
+            "renewed"
+        }))
+        .service(web::resource("/purge").to(|ses: Session| async move {
+            ses.purge();
+            "purged"
+        }))
+        .service(web::resource("/static").to(|| async { "static" }));
+    }
+
+    fn session_cookie<B>(res: &ServiceResponse<B>) -> Option<Cookie<'static>> {
+        res.response()
+            .cookies()
+            .find(|c| c.name() == "actix-session")
+            .map(|c| c.into_owned())
+    }
+
+    #[actix_rt::test]
+    async fn store_session() {
+        let store = MemoryStore::new();
+        let mut app = test::init_service(
+            App::new()
+                .wrap(StoreSession::new(store.clone(), &[0; 32]).cookie_secure(false))
+                .configure(routes),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/count").to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = session_cookie(&res).unwrap();
+        assert_eq!(store.len(), 1);
+
+        // unchanged sessions are not written back
+        let req = test::TestRequest::get().cookie(cookie.clone()).to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(session_cookie(&res).is_none());
+        let counter: i32 = test::read_body_json(res).await;
+        assert_eq!(counter, 1);
+
+        let req = test::TestRequest::with_uri("/count")
+            .cookie(cookie.clone())
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(session_cookie(&res).is_none());
+        let counter: i32 = test::read_body_json(res).await;
+        assert_eq!(counter, 2);
+
+        // the state is moved to a new key
+        let req = test::TestRequest::with_uri("/renew")
+            .cookie(cookie.clone())
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        let renewed = session_cookie(&res).unwrap();
+        assert_ne!(renewed.value(), cookie.value());
+        assert_eq!(store.len(), 1);
+
+        // the previous key is unknown, a new session is issued
+        let req = test::TestRequest::get().cookie(cookie.clone()).to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_ne!(session_cookie(&res).unwrap().value(), cookie.value());
+        let counter: i32 = test::read_body_json(res).await;
+        assert_eq!(counter, 0);
+        assert_eq!(store.len(), 2);
+
+        let req = test::TestRequest::get()
+            .cookie(renewed.clone())
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        let counter: i32 = test::read_body_json(res).await;
+        assert_eq!(counter, 2);
+
+        // the session is deleted, and its cookie removed
+        let req = test::TestRequest::with_uri("/purge")
+            .cookie(renewed)
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(session_cookie(&res).unwrap().value(), "");
+        assert_eq!(store.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn purge_scoped_cookie() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(
+                    StoreSession::new(MemoryStore::new(), &[0; 32])
+                        .cookie_secure(false)
+                        .cookie_path("/app")
+                        .cookie_domain("example.com"),
+                )
+                .configure(routes),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/count").to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = session_cookie(&res).unwrap();
+
+        // the removal cookie has the scope of the session cookie
+        let req = test::TestRequest::with_uri("/purge")
+            .cookie(cookie)
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        let removal = session_cookie(&res).unwrap();
+        assert_eq!(removal.value(), "");
+        assert_eq!(removal.path(), Some("/app"));
+        assert_eq!(removal.domain(), Some("example.com"));
+    }
+
+    #[actix_rt::test]
+    async fn lazy_store_session() {
+        let store = MemoryStore::new();
+        let mut app = test::init_service(
+            App::new()
+                .wrap(StoreSession::new(store.clone(), &[0; 32]).lazy(true))
+                .configure(routes),
+        )
+        .await;
+
+        let req = test::TestRequest::get().to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(session_cookie(&res).is_none());
+        assert_eq!(store.len(), 0);
+
+        let req = test::TestRequest::with_uri("/count").to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(session_cookie(&res).is_some());
+        assert_eq!(store.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn lazy_load_store_session() {
+        let store = MemoryStore::new();
+        let mut app = test::init_service(
+            App::new()
+                .wrap(
+                    StoreSession::new(store.clone(), &[0; 32])
+                        .lazy_load(true)
+                        .sliding_expiration(true)
+                        .cookie_max_age(Duration::hours(1)),
+                )
+                .configure(routes),
+        )
+        .await;
+
+        // no session is issued unless it is used
+        let req = test::TestRequest::with_uri("/static").to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(session_cookie(&res).is_none());
+        assert_eq!(store.len(), 0);
+
+        let req = test::TestRequest::with_uri("/count").to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = session_cookie(&res).unwrap();
+        assert_eq!(store.len(), 1);
+
+        // the session is neither loaded nor written back unless it is used
+        let req = test::TestRequest::with_uri("/static")
+            .cookie(cookie.clone())
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(session_cookie(&res).is_none());
+
+        let req = test::TestRequest::with_uri("/count")
+            .cookie(cookie)
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(session_cookie(&res).is_some());
+        let counter: i32 = test::read_body_json(res).await;
+        assert_eq!(counter, 2);
+    }
+
+    #[actix_rt::test]
+    async fn lazy_load_user_session() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(StoreSession::new(MemoryStore::new(), &[0; 32]).lazy_load(true))
+                .configure(routes)
+                .service(web::resource("/mark").to(|req: HttpRequest| {
+                    crate::UserSession::get_session(&req)
+                        .set("marked", true)
+                        .unwrap();
+                    HttpResponse::Ok()
+                }))
+                .service(web::resource("/marked").to(|ses: Session| async move {
+                    let marked: bool = ses.get("marked")?.unwrap_or(false);
+                    Ok::<_, Error>(HttpResponse::Ok().json(marked))
+                })),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/count").to_request();
+        let res = test::call_service(&mut app, req).await;
+        let cookie = session_cookie(&res).unwrap();
+
+        // the changes of a session which was not extracted are written back, on
+        // top of its stored state
+        let req = test::TestRequest::with_uri("/mark")
+            .cookie(cookie.clone())
+            .to_request();
+        test::call_service(&mut app, req).await;
+
+        let req = test::TestRequest::with_uri("/marked")
+            .cookie(cookie.clone())
+            .to_request();
+        let marked: bool = test::read_response_json(&mut app, req).await;
+        assert!(marked);
+        let req = test::TestRequest::get().cookie(cookie).to_request();
+        let counter: i32 = test::read_response_json(&mut app, req).await;
+        assert_eq!(counter, 1);
+    }
+
+    #[actix_rt::test]
+    async fn lazy_load_unavailable() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(StoreSession::new(Unavailable, &[0; 32]).lazy_load(true))
+                .configure(routes),
+        )
+        .await;
+
+        let mut jar = CookieJar::new();
+        jar.signed(&Key::derive_from(&[0; 32]))
+            .add(Cookie::new("actix-session", "key"));
+        let cookie = jar.get("actix-session").unwrap().clone();
+
+        // the store is only reached by requests using the session
+        let req = test::TestRequest::with_uri("/static")
+            .cookie(cookie.clone())
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get().cookie(cookie).to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[actix_rt::test]
+    async fn previous_keys() {
+        let store = MemoryStore::new();
+        let mut old_app = test::init_service(
+            App::new()
+                .wrap(StoreSession::new(store.clone(), &[1; 32]))
+                .configure(routes),
+        )
+        .await;
+        let mut new_app = test::init_service(
+            App::new()
+                .wrap(StoreSession::new(store, &[2; 32]).previous_keys(&[&[1; 32]]))
+                .configure(routes),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/count").to_request();
+        let res = test::call_service(&mut old_app, req).await;
+        let old_cookie = session_cookie(&res).unwrap();
+
+        let req = test::TestRequest::get()
+            .cookie(old_cookie.clone())
+            .to_request();
+        let res = test::call_service(&mut new_app, req).await;
+        let new_cookie = session_cookie(&res).unwrap();
+        assert_ne!(new_cookie.value(), old_cookie.value());
+        let counter: i32 = test::read_body_json(res).await;
+        assert_eq!(counter, 1);
+
+        let req = test::TestRequest::get().cookie(new_cookie).to_request();
+        let res = test::call_service(&mut new_app, req).await;
+        assert!(session_cookie(&res).is_none());
+        let counter: i32 = test::read_body_json(res).await;
+        assert_eq!(counter, 1);
+    }
+
+    /// A store which cannot be reached.
+    struct Unavailable;
+
+    impl SessionStore for Unavailable {
+        fn load<'a>(
+            &'a self,
+            _: &'a str,
+        ) -> LocalBoxFuture<'a, Result<Option<HashMap<String, String>>, Error>> {
+            err(actix_web::error::ErrorInternalServerError("unavailable")).boxed_local()
+        }
+
+        fn save(
+            &self,
+            _: HashMap<String, String>,
+        ) -> LocalBoxFuture<'_, Result<String, Error>> {
+            err(actix_web::error::ErrorInternalServerError("unavailable")).boxed_local()
+        }
+
+        fn update(
+            &self,
+            _: String,
+            _: HashMap<String, String>,
+            _: SessionChanges,
+        ) -> LocalBoxFuture<'_, Result<String, Error>> {
+            err(actix_web::error::ErrorInternalServerError("unavailable")).boxed_local()
+        }
+
+        fn delete(&self, _: String) -> LocalBoxFuture<'_, Result<(), Error>> {
+            err(actix_web::error::ErrorInternalServerError("unavailable")).boxed_local()
+        }
+
+        fn ttl<'a>(
+            &'a self,
+            _: &'a str,
+            _: &'a HashMap<String, String>,
+        ) -> LocalBoxFuture<'a, Result<bool, Error>> {
+            err(actix_web::error::ErrorInternalServerError("unavailable")).boxed_local()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn fail_open() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(StoreSession::new(Unavailable, &[0; 32]).fail_open(true))
+                .service(web::resource("/").to(
+                    |req: HttpRequest, ses: Session| async move {
+                        ses.set("counter", 1)?;
+                        let degraded =
+                            req.extensions().contains::<SessionStoreDegraded>();
+                        Ok::<_, Error>(HttpResponse::Ok().json(degraded))
+                    },
+                )),
+        )
+        .await;
+
+        let mut jar = CookieJar::new();
+        jar.signed(&Key::derive_from(&[0; 32]))
+            .add(Cookie::new("actix-session", "key"));
+        let cookie = jar.get("actix-session").unwrap().clone();
+
+        // the session cannot be loaded
+        let req = test::TestRequest::get().cookie(cookie).to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(session_cookie(&res).is_none());
+        let degraded: bool = test::read_body_json(res).await;
+        assert!(degraded);
+
+        // the session cannot be written
+        let req = test::TestRequest::get().to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(session_cookie(&res).is_none());
+        let degraded: bool = test::read_body_json(res).await;
+        assert!(!degraded);
+    }
+}